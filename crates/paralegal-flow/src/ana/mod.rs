@@ -121,14 +121,54 @@ impl<'tcx> SPDGGenerator<'tcx> {
             .map(|id| (*id, def_info_for_item(*id, tcx)))
             .collect();
 
-        let type_info = self.collect_type_info();
-        type_info_sanity_check(&controllers, &type_info);
-        ProgramDescription {
-            type_info,
+        let mut desc = ProgramDescription {
+            type_info: self.collect_type_info(),
             instruction_info: self.collect_instruction_info(&controllers),
             controllers,
             def_info,
+            sources: Default::default(),
+        };
+        if self.report_invariant_violations(&desc) {
+            desc.canonicalize();
+        } else {
+            tcx.sess
+                .warn("The graph is not canonicalized, because it mentions nonexistent nodes");
+        }
+        if let Some(mode) = self.opts.modelctrl().embed_sources() {
+            for file in desc.embed_sources(mode) {
+                tcx.sess.warn(format!(
+                    "Could not read {} to embed it, diagnostics for it will have no source code",
+                    file.abs_file_path.display()
+                ));
+            }
+        }
+        desc
+    }
+
+    /// Run [`ProgramDescription::validate`] and report the broken invariants.
+    ///
+    /// Types without type info are an error, or a warning if `--relaxed` was
+    /// passed. The other invariants are only reported, as warnings, with
+    /// `--validate`.
+    ///
+    /// Returns whether every node mentioned by a controller exists, which
+    /// [`ProgramDescription::canonicalize`] relies on.
+    fn report_invariant_violations(&self, desc: &ProgramDescription) -> bool {
+        let Err(errors) = desc.validate() else {
+            return true;
+        };
+        let sess = self.tcx.sess;
+        let mut nodes_exist = true;
+        for error in errors {
+            nodes_exist &= !error.is_missing_node();
+            let msg = format!("Invariant broken: {error}");
+            if error.is_missing_type_info() && !self.opts.relaxed() {
+                sess.err(msg);
+            } else if error.is_missing_type_info() || self.opts.modelctrl().validate() {
+                sess.warn(msg);
+            }
         }
+        nodes_exist
     }
 
     /// Create an [`InstructionInfo`] record for each [`GlobalLocation`]
@@ -228,6 +268,17 @@ impl<'tcx> SPDGGenerator<'tcx> {
     }
 }
 
+/// Render a location through def paths, so that it does not depend on the
+/// compilation session. Used to derive [`StableNodeId`]s.
+fn stable_location_string(loc: GlobalLocation, tcx: TyCtxt) -> String {
+    let function = tcx.def_path_str(loc.function.to_def_id());
+    match loc.location {
+        RichLocation::Location(location) => format!("{function}@{location:?}"),
+        RichLocation::Start => format!("{function}@start"),
+        RichLocation::End => format!("{function}@end"),
+    }
+}
+
 fn src_loc_for_span(span: RustSpan, tcx: TyCtxt) -> Span {
     let (source_file, start_line, start_col, end_line, end_col) =
         tcx.sess.source_map().span_to_location_info(span);
@@ -588,14 +639,16 @@ impl<'a, 'tcx, C: Extend<DefId>> GraphConverter<'tcx, 'a, C> {
         let markers = self.make_spdg_impl();
         let arguments = self.determine_arguments();
         let return_ = self.determine_return();
-        SPDG {
+        let mut spdg = SPDG {
             graph: self.spdg,
             name: Identifier::new(self.target.name()),
             arguments,
             markers,
             return_,
             type_assigns: self.types,
-        }
+        };
+        spdg.disambiguate_stable_ids();
+        spdg
     }
 
     /// This initializes the fields `spdg` and `index_map` and should be called first
@@ -605,6 +658,7 @@ impl<'a, 'tcx, C: Extend<DefId>> GraphConverter<'tcx, 'a, C> {
         let input = &g_ref.graph;
         let tcx = self.tcx();
         let mut markers: HashMap<NodeIndex, Vec<Identifier>> = HashMap::new();
+        let controller_path = tcx.def_path_str(self.local_def_id.to_def_id());
 
         for (i, weight) in input.node_references() {
             let (kind, is_external_call_source, node_markers) = self.determine_node_kind(weight);
@@ -612,13 +666,27 @@ impl<'a, 'tcx, C: Extend<DefId>> GraphConverter<'tcx, 'a, C> {
             let body = &tcx.body_for_def_id(at.function).unwrap().body;
 
             let node_span = body.local_decls[weight.place.local].source_info.span;
+            let description = format!("{:?}", weight.place);
+            // Made unique once the graph is complete, see
+            // `SPDG::disambiguate_stable_ids`
+            let stable_id = StableNodeId::from_parts(
+                std::iter::once(controller_path.clone())
+                    .chain(
+                        weight
+                            .at
+                            .iter_from_root()
+                            .map(|loc| stable_location_string(loc, tcx)),
+                    )
+                    .chain([description.clone()]),
+            );
             let new_idx = self.register_node(
                 i,
                 NodeInfo {
                     at: weight.at,
-                    description: format!("{:?}", weight.place),
+                    description,
                     kind,
                     span: src_loc_for_span(node_span, tcx),
+                    stable_id,
                 },
             );
 
@@ -729,25 +797,6 @@ impl<'a, 'tcx, C: Extend<DefId>> GraphConverter<'tcx, 'a, C> {
     }
 }
 
-/// Checks the invariant that [`SPDGGenerator::collect_type_info`] should
-/// produce a map that is a superset of the types found in all the `types` maps
-/// on [`SPDG`].
-///
-/// Additionally this also inserts missing types into the map *only* for
-/// generators created by async functions.
-fn type_info_sanity_check(controllers: &ControllerMap, types: &TypeInfoMap) {
-    controllers
-        .values()
-        .flat_map(|spdg| spdg.type_assigns.values())
-        .flat_map(|types| &types.0)
-        .for_each(|t| {
-            assert!(
-                types.contains_key(t),
-                "Invariant broken: Type {t:?} is not present in type map"
-            );
-        })
-}
-
 /// If `did` is a method of an `impl` of a trait, then return the `DefId` that
 /// refers to the method on the trait definition.
 fn get_parent(tcx: TyCtxt, did: DefId) -> Option<DefId> {
@@ -11,6 +11,7 @@
 
 use anyhow::Error;
 use clap::ValueEnum;
+use paralegal_spdg::{artifact::ArtifactFormat, embed::EmbedMode};
 use std::ffi::{OsStr, OsString};
 
 use crate::utils::TinyBitSet;
@@ -44,6 +45,8 @@ impl TryFrom<ClapArgs> for Args {
             debug,
             debug_target,
             result_path,
+            binary_output,
+            sharded_output,
             relaxed,
             target,
             abort_after_analysis,
@@ -84,6 +87,8 @@ impl TryFrom<ClapArgs> for Args {
             verbose,
             log_level_config,
             result_path,
+            binary_output,
+            sharded_output,
             relaxed,
             target,
             abort_after_analysis,
@@ -104,6 +109,10 @@ pub struct Args {
     log_level_config: LogLevelConfig,
     /// Where to write the resulting forge code to (defaults to `analysis_result.frg`)
     result_path: std::path::PathBuf,
+    /// Write the result in the compact binary format instead of JSON
+    binary_output: bool,
+    /// Write the result as a directory with one file per controller
+    sharded_output: bool,
     /// Emit warnings instead of aborting the analysis on sanity checks
     relaxed: bool,
 
@@ -146,6 +155,15 @@ pub struct ClapArgs {
     /// Where to write the resulting GraphLocation (defaults to `flow-graph.json`)
     #[clap(long, default_value = "flow-graph.json")]
     result_path: std::path::PathBuf,
+    /// Write the resulting graph in a compact binary encoding instead of JSON.
+    /// Consumers detect the encoding automatically.
+    #[clap(long, env = "PARALEGAL_BINARY_OUTPUT")]
+    binary_output: bool,
+    /// Write the resulting graph as a directory at `--result-path`, with an
+    /// index and one file per controller, so that consumers can load
+    /// controllers individually.
+    #[clap(long, env = "PARALEGAL_SHARDED_OUTPUT")]
+    sharded_output: bool,
     /// Emit warnings instead of aborting the analysis on sanity checks
     #[clap(long, env = "PARALEGAL_RELAXED")]
     relaxed: bool,
@@ -316,6 +334,18 @@ impl Args {
     pub fn result_path(&self) -> &std::path::Path {
         self.result_path.as_path()
     }
+    /// The encoding to use when writing the result file
+    pub fn artifact_format(&self) -> ArtifactFormat {
+        if self.binary_output {
+            ArtifactFormat::Binary
+        } else {
+            ArtifactFormat::Json
+        }
+    }
+    /// Write the result file as a sharded directory
+    pub fn sharded_output(&self) -> bool {
+        self.sharded_output
+    }
     /// Should we output additional log messages (level `info`)
     pub fn verbose(&self) -> bool {
         self.verbose
@@ -353,12 +383,45 @@ pub struct ModelCtrl {
     /// `dump_serialized_flow_graph`.
     #[clap(long, env)]
     external_annotations: Option<std::path::PathBuf>,
+    /// Embed the source code that spans point into in the emitted graph, so
+    /// that policies can render diagnostics without access to the source
+    /// tree. `lines` stores only the lines covered by spans, `files` each
+    /// referenced file in its entirety.
+    #[clap(long, env = "PARALEGAL_EMBED_SOURCES", value_enum)]
+    embed_sources: Option<EmbedSources>,
+    /// Check all invariants of the emitted graph and warn about the broken
+    /// ones. Without this flag only missing type information is checked.
+    #[clap(long, env = "PARALEGAL_VALIDATE")]
+    validate: bool,
+}
+
+/// How much source code to embed into the graph, see
+/// [`ModelCtrl::embed_sources`].
+#[derive(Clone, Copy, serde::Serialize, serde::Deserialize, clap::ValueEnum)]
+enum EmbedSources {
+    /// Only the lines covered by spans
+    Lines,
+    /// Every referenced file in its entirety
+    Files,
 }
 
 impl ModelCtrl {
     pub fn external_annotations(&self) -> Option<&std::path::Path> {
         self.external_annotations.as_deref()
     }
+
+    /// Whether and how much source code to embed into the graph
+    pub fn embed_sources(&self) -> Option<EmbedMode> {
+        self.embed_sources.map(|e| match e {
+            EmbedSources::Lines => EmbedMode::Lines,
+            EmbedSources::Files => EmbedMode::Files,
+        })
+    }
+
+    /// Whether to warn about all broken invariants of the emitted graph
+    pub fn validate(&self) -> bool {
+        self.validate
+    }
 }
 
 /// Arguments which control marker assignment and discovery
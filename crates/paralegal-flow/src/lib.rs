@@ -157,16 +157,35 @@ impl rustc_driver::Callbacks for Callbacks {
                     paralegal_spdg::dot::dump(&desc, out).unwrap();
                 }
 
-                serde_json::to_writer(
-                    &mut std::fs::OpenOptions::new()
-                        .truncate(true)
-                        .create(true)
-                        .write(true)
-                        .open(self.opts.result_path())
-                        .unwrap(),
-                    &desc,
-                )
-                .unwrap();
+                let header = paralegal_spdg::artifact::ArtifactHeader::new(extractor_commit());
+                let result_path = self.opts.result_path();
+                let written = if self.opts.sharded_output() {
+                    paralegal_spdg::artifact::sharded::write_sharded(
+                        &header,
+                        &desc,
+                        self.opts.artifact_format(),
+                        result_path,
+                    )
+                } else {
+                    paralegal_spdg::artifact::write_to_file(
+                        &header,
+                        &desc,
+                        self.opts.artifact_format(),
+                        result_path,
+                    )
+                };
+                if let Err(e) = written {
+                    let hint = if self.opts.sharded_output() && result_path.is_file() {
+                        ", remove it or pass a different --result-path for sharded output"
+                    } else {
+                        ""
+                    };
+                    tcx.sess.err(format!(
+                        "Could not write the graph to {}: {e}{hint}",
+                        result_path.display()
+                    ));
+                    tcx.sess.abort_if_errors();
+                }
 
                 anyhow::Ok(if self.opts.abort_after_analysis() {
                     rustc_driver::Compilation::Stop
@@ -178,6 +197,13 @@ impl rustc_driver::Callbacks for Callbacks {
     }
 }
 
+/// The commit this extractor was built from, as recorded in the artifact
+/// header.
+fn extractor_commit() -> &'static str {
+    // The build script wraps the hash in quotes
+    env!("COMMIT_HASH").trim_matches('"')
+}
+
 pub const CARGO_ENCODED_RUSTFLAGS: &str = "CARGO_ENCODED_RUSTFLAGS";
 
 fn add_to_rustflags(new: impl IntoIterator<Item = String>) -> Result<(), std::env::VarError> {
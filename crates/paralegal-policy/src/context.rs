@@ -1,25 +1,32 @@
-use std::{collections::HashSet, io::Write, process::exit, sync::Arc};
+use std::{
+    collections::HashSet,
+    io::Write,
+    process::exit,
+    sync::{Arc, OnceLock},
+};
 
+use paralegal_spdg::artifact::sharded::ShardReader;
 pub use paralegal_spdg::rustc_portable::{DefId, LocalDefId};
-use paralegal_spdg::traverse::{generic_flows_to, EdgeSelection};
+use paralegal_spdg::traverse::{
+    generic_flows_to_avoiding, k_shortest_paths, reachable_from_avoiding, EdgeSelection,
+};
 use paralegal_spdg::{
-    CallString, DisplayNode, Endpoint, GlobalNode, HashMap, Identifier, InstructionInfo,
-    IntoIterGlobalNodes, Node as SPDGNode, NodeCluster, NodeInfo, ProgramDescription, SPDGImpl,
-    Span, TypeId, SPDG,
+    CallString, ControllerMap, DisplayNode, EdgeInfo, Endpoint, GlobalNode, HashMap, Identifier,
+    InstructionInfo, IntoIterGlobalNodes, Node as SPDGNode, NodeCluster, NodeInfo,
+    ProgramDescription, SPDGImpl, Span, StableNodeId, TypeId, TypeInfoMap, SPDG,
 };
 
 use anyhow::{anyhow, bail, ensure, Result};
 use itertools::{Either, Itertools};
-use petgraph::prelude::Bfs;
-use petgraph::visit::{Control, DfsEvent, EdgeFiltered, EdgeRef, GraphBase, NodeIndexable, Walker};
+use petgraph::visit::{Control, DfsEvent, EdgeRef, GraphBase, NodeIndexable};
 use petgraph::Incoming;
 
-use super::flows_to::CtrlFlowsTo;
+use super::flows_to::{CtrlFlowsTo, ReachabilityIndex};
 
 use crate::Diagnostics;
 use crate::{
     assert_warning,
-    diagnostics::{CombinatorContext, DiagnosticsRecorder, HasDiagnosticsBase},
+    diagnostics::{CombinatorContext, DiagnosticsFormat, DiagnosticsRecorder, HasDiagnosticsBase},
 };
 
 /// User-defined PDG markers.
@@ -36,6 +43,42 @@ pub type MarkableId = GlobalNode;
 type MarkerIndex = HashMap<Marker, MarkerTargets>;
 type FlowsTo = HashMap<ControllerId, CtrlFlowsTo>;
 
+/// Nodes a flow must not pass through, see [`Context::flows_to_avoiding`].
+///
+/// A [`Marker`] converts into this with `.into()`.
+pub enum Avoid<'a> {
+    /// Nodes that carry this marker, directly or via their type
+    Marker(Marker),
+    /// Nodes for which this function returns `true`
+    Predicate(Box<dyn Fn(GlobalNode) -> bool + 'a>),
+}
+
+impl<'a> Avoid<'a> {
+    /// Avoid the nodes for which `pred` holds
+    pub fn predicate(pred: impl Fn(GlobalNode) -> bool + 'a) -> Self {
+        Avoid::Predicate(Box::new(pred))
+    }
+
+    fn applies(&self, ctx: &Context, node: GlobalNode) -> bool {
+        match self {
+            Avoid::Marker(marker) => ctx.marker_to_ids.get(marker).map_or(false, |marked| {
+                marked.nodes.contains(&node)
+                    || ctx
+                        .get_node_types(node)
+                        .iter()
+                        .any(|t| marked.types.contains(t))
+            }),
+            Avoid::Predicate(pred) => pred(node),
+        }
+    }
+}
+
+impl From<Marker> for Avoid<'_> {
+    fn from(marker: Marker) -> Self {
+        Avoid::Marker(marker)
+    }
+}
+
 /// Collection of entities a particular marker has been applied to
 #[derive(Clone, Debug, Default)]
 pub struct MarkerTargets {
@@ -55,24 +98,40 @@ impl MarkerTargets {
     }
 }
 
-use petgraph::visit::{GraphRef, IntoNeighbors, Visitable};
-
-fn bfs_iter<
-    G: IntoNeighbors + GraphRef + Visitable<NodeId = SPDGNode, Map = <SPDGImpl as Visitable>::Map>,
->(
-    g: G,
-    controller_id: LocalDefId,
+/// The nodes in `start`, followed by the nodes `step` yields for any of them,
+/// without duplicates.
+fn with_start_nodes<I: Iterator<Item = SPDGNode>>(
+    controller_id: ControllerId,
     start: impl IntoIterator<Item = SPDGNode>,
-) -> impl Iterator<Item = GlobalNode> {
-    let mut discovered = g.visit_map();
-    let stack: std::collections::VecDeque<petgraph::prelude::NodeIndex> =
-        start.into_iter().collect();
-    for n in stack.iter() {
-        petgraph::visit::VisitMap::visit(&mut discovered, *n);
-    }
-    let bfs = Bfs { stack, discovered };
-    let walker_iter = Walker::iter(bfs, g);
-    walker_iter.map(move |inner| GlobalNode::from_local_node(controller_id, inner))
+    step: impl Fn(SPDGNode) -> I,
+) -> std::vec::IntoIter<GlobalNode> {
+    let start = start.into_iter().collect::<Vec<_>>();
+    let stepped = start.iter().flat_map(|n| step(*n));
+    start
+        .iter()
+        .copied()
+        .chain(stepped)
+        .unique()
+        .map(|n| GlobalNode::from_local_node(controller_id, n))
+        .collect::<Vec<_>>()
+        .into_iter()
+}
+
+/// Where the controller graphs of a [`Context`] come from.
+#[derive(Debug)]
+enum Graphs {
+    /// All graphs are in [`Context::desc`]
+    Loaded,
+    /// The graphs are read from a sharded artifact on first access, see
+    /// [`Context::new_lazy`].
+    Lazy {
+        reader: ShardReader,
+        /// The controllers from the index, with empty graphs
+        stubs: ControllerMap,
+        graphs: HashMap<ControllerId, OnceLock<SPDG>>,
+        /// Check each graph against the index when it is loaded
+        validate: bool,
+    },
 }
 
 /// Interface for defining policies.
@@ -95,13 +154,30 @@ fn bfs_iter<
 /// [`Self::emit_diagnostics`]. If you used
 /// [`super::GraphLocation::with_context`] this will be done automatically for
 /// you.
+///
+/// The flow indices of a controller are computed when it is first queried. A
+/// context created with [`Self::new_lazy`] also defers reading the graphs
+/// themselves, which makes checking a few controllers of a large application
+/// much cheaper.
 #[derive(Debug)]
 pub struct Context {
     marker_to_ids: MarkerIndex,
     desc: ProgramDescription,
+    graphs: Graphs,
     flows_to: FlowsTo,
     pub(crate) diagnostics: DiagnosticsRecorder,
     name_map: HashMap<Identifier, Vec<DefId>>,
+    stable_ids: OnceLock<HashMap<StableNodeId, GlobalNode>>,
+}
+
+/// Options for constructing a [`Context`] with [`Context::new_with_config`].
+#[derive(Clone, Debug, Default)]
+pub struct ContextConfig {
+    /// Check the description with [`ProgramDescription::validate`] and record
+    /// every broken invariant as an error. Off by default, because artifacts
+    /// produced by the extractor are already validated. In a context created
+    /// with [`Context::new_lazy`] each graph is checked when it is loaded.
+    pub validate: bool,
 }
 
 impl Context {
@@ -109,24 +185,159 @@ impl Context {
     ///
     /// This also precomputes some data structures like an index over markers.
     pub fn new(desc: ProgramDescription) -> Self {
+        Self::new_with_config(desc, ContextConfig::default())
+    }
+
+    /// Construct a [`Context`] from a [`ProgramDescription`] with non-default
+    /// options.
+    pub fn new_with_config(desc: ProgramDescription, config: ContextConfig) -> Self {
+        let errors = if config.validate {
+            desc.validate().err().unwrap_or_default()
+        } else {
+            vec![]
+        };
+        let ctx = Self::build(desc, Graphs::Loaded);
+        for error in errors {
+            ctx.error(format!("Invalid program description: {error}"));
+        }
+        ctx
+    }
+
+    /// Construct a [`Context`] that reads controller graphs from a sharded
+    /// artifact when they are first accessed (see
+    /// [`paralegal_spdg::artifact::sharded`]). `index` and `reader` are the
+    /// result of [`ShardReader::open`].
+    ///
+    /// [`ShardReader::open`] checks that every shard exists. Queries that
+    /// need a graph still panic if its shard cannot be read, use
+    /// [`Self::try_controller`] to handle that case. In this mode
+    /// [`ProgramDescription::controllers`] of [`Self::desc`] is empty. With
+    /// [`ContextConfig::validate`] each graph is checked with
+    /// [`ProgramDescription::validate_controller`] when it is loaded.
+    pub fn new_lazy(
+        mut index: ProgramDescription,
+        reader: ShardReader,
+        config: ContextConfig,
+    ) -> Self {
+        let stubs = std::mem::take(&mut index.controllers);
+        let graphs = Graphs::Lazy {
+            graphs: stubs.keys().map(|id| (*id, OnceLock::new())).collect(),
+            stubs,
+            reader,
+            validate: config.validate,
+        };
+        Self::build(index, graphs)
+    }
+
+    fn build(desc: ProgramDescription, graphs: Graphs) -> Self {
         let name_map = desc
             .def_info
             .iter()
             .map(|(k, v)| (v.name, *k))
             .into_group_map();
+        let summaries = match &graphs {
+            Graphs::Loaded => &desc.controllers,
+            Graphs::Lazy { stubs, .. } => stubs,
+        };
+        let marker_to_ids = Self::build_index_on_markers(summaries, &desc.type_info);
+        let flows_to = summaries
+            .keys()
+            .map(|id| (*id, CtrlFlowsTo::default()))
+            .collect();
         Context {
-            marker_to_ids: Self::build_index_on_markers(&desc),
-            flows_to: Self::build_flows_to(&desc),
+            marker_to_ids,
             desc,
+            graphs,
+            flows_to,
             diagnostics: Default::default(),
             name_map,
+            stable_ids: OnceLock::new(),
+        }
+    }
+
+    /// The graph of controller `ctrl_id`. In a context created with
+    /// [`Self::new_lazy`] it is read from disk on the first call.
+    ///
+    /// Panics if there is no such controller or its shard cannot be read.
+    pub fn controller(&self, ctrl_id: ControllerId) -> &SPDG {
+        self.try_controller(ctrl_id)
+            .unwrap_or_else(|e| panic!("{e:?}"))
+    }
+
+    /// Like [`Self::controller`], but returns an error instead of panicking.
+    pub fn try_controller(&self, ctrl_id: ControllerId) -> Result<&SPDG> {
+        match &self.graphs {
+            Graphs::Loaded => self
+                .desc
+                .controllers
+                .get(&ctrl_id)
+                .ok_or_else(|| anyhow!("No controller {ctrl_id:?}")),
+            Graphs::Lazy {
+                reader,
+                graphs,
+                validate,
+                ..
+            } => {
+                let graph = graphs
+                    .get(&ctrl_id)
+                    .ok_or_else(|| anyhow!("No controller {ctrl_id:?}"))?;
+                if let Some(loaded) = graph.get() {
+                    return Ok(loaded);
+                }
+                let loaded = anyhow::Context::with_context(reader.load(ctrl_id), || {
+                    format!("Could not load controller {ctrl_id:?}")
+                })?;
+                let mut first_load = false;
+                let loaded = graph.get_or_init(|| {
+                    first_load = true;
+                    loaded
+                });
+                // Only once, even if several threads loaded the graph
+                if *validate && first_load {
+                    let errors = self.desc.validate_controller(ctrl_id, loaded).err();
+                    for error in errors.unwrap_or_default() {
+                        self.error(format!("Invalid program description: {error}"));
+                    }
+                }
+                Ok(loaded)
+            }
         }
     }
 
+    /// All controllers with their names, markers, arguments and types. The
+    /// graphs are empty in a context created with [`Self::new_lazy`].
+    pub(crate) fn summaries(&self) -> &ControllerMap {
+        match &self.graphs {
+            Graphs::Loaded => &self.desc.controllers,
+            Graphs::Lazy { stubs, .. } => stubs,
+        }
+    }
+
+    /// The reachability index over `edges` in controller `ctrl_id`, built on
+    /// first use.
+    fn reachability(&self, ctrl_id: ControllerId, edges: EdgeSelection) -> &ReachabilityIndex {
+        self.flows_to[&ctrl_id].reachability(self.controller(ctrl_id), edges)
+    }
+
+    fn stable_ids(&self) -> &HashMap<StableNodeId, GlobalNode> {
+        self.stable_ids.get_or_init(|| {
+            self.all_controllers()
+                .flat_map(|(ctrl_id, spdg)| {
+                    spdg.graph.node_indices().map(move |n| {
+                        (
+                            spdg.node_info(n).stable_id,
+                            GlobalNode::from_local_node(ctrl_id, n),
+                        )
+                    })
+                })
+                .collect()
+        })
+    }
+
     /// Find the call string that identifies the call site or statement at which
     /// this node is captured.
     pub fn associated_call_site(&self, node: GlobalNode) -> CallString {
-        self.desc.controllers[&node.controller_id()]
+        self.controller(node.controller_id())
             .node_info(node.local_node())
             .at
     }
@@ -138,8 +349,7 @@ impl Context {
     /// printing them in error messages or for debugging. Policies contingent on
     /// controller names are likely unsound.
     pub fn controllers_by_name(&self, name: Identifier) -> impl Iterator<Item = Endpoint> + '_ {
-        self.desc
-            .controllers
+        self.summaries()
             .iter()
             .filter(move |(_n, g)| g.name == name)
             .map(|t| *t.0)
@@ -212,8 +422,10 @@ impl Context {
 
     /// Dispatch and drain all queued diagnostics, aborts the program if any of
     /// them demand failure.
+    ///
+    /// See [`Self::report`] to handle failing policies without exiting.
     pub fn emit_diagnostics_may_exit(&self, w: impl Write) -> Result<()> {
-        if !self.diagnostics.emit(w)? {
+        if !self.emit_diagnostics(w)? {
             exit(1)
         }
         Ok(())
@@ -221,7 +433,17 @@ impl Context {
 
     /// Dispatch and drain all queued diagnostics without aborting the program.
     pub fn emit_diagnostics(&self, w: impl Write) -> std::io::Result<bool> {
-        self.diagnostics.emit(w)
+        self.emit_diagnostics_as(w, DiagnosticsFormat::Text)
+    }
+
+    /// Like [`Self::emit_diagnostics`] but renders the diagnostics in
+    /// `format`.
+    pub fn emit_diagnostics_as(
+        &self,
+        w: impl Write,
+        format: DiagnosticsFormat,
+    ) -> std::io::Result<bool> {
+        self.diagnostics.emit(w, &self.desc, format)
     }
 
     /// Emit a warning if this marker was not found in the source code.
@@ -233,8 +455,8 @@ impl Context {
         )
     }
 
-    fn build_index_on_markers(desc: &ProgramDescription) -> MarkerIndex {
-        desc.controllers
+    fn build_index_on_markers(controllers: &ControllerMap, type_info: &TypeInfoMap) -> MarkerIndex {
+        controllers
             .iter()
             .flat_map(|(&ctrl_id, spdg)| {
                 spdg.markers.iter().flat_map(move |(&inner, anns)| {
@@ -246,7 +468,7 @@ impl Context {
                     })
                 })
             })
-            .chain(desc.type_info.iter().flat_map(|(k, v)| {
+            .chain(type_info.iter().flat_map(|(k, v)| {
                 v.markers
                     .iter()
                     .copied()
@@ -259,13 +481,6 @@ impl Context {
             })
     }
 
-    fn build_flows_to(desc: &ProgramDescription) -> FlowsTo {
-        desc.controllers
-            .iter()
-            .map(|(id, c)| (*id, CtrlFlowsTo::build(c)))
-            .collect()
-    }
-
     /// Returns whether a node flows to a node through the configured edge type.
     ///
     /// Nodes do not flow to themselves. CallArgument nodes do flow to their respective CallSites.
@@ -282,20 +497,141 @@ impl Context {
             return false;
         }
 
-        if edge_type.is_data() {
-            let flows_to = &self.flows_to[&cf_id];
-            src.iter_nodes().any(|src| {
-                sink.iter_nodes()
-                    .any(|sink| flows_to.data_flows_to[src.index()][sink.index()])
-            })
-        } else {
-            generic_flows_to(
-                src.iter_nodes(),
-                edge_type,
-                &self.desc.controllers[&cf_id],
-                sink.iter_nodes(),
-            )
+        let index = self.reachability(cf_id, edge_type);
+        // Only data flow is irreflexive
+        let reflexive = !edge_type.is_data();
+        src.iter_nodes().any(|src| {
+            sink.iter_nodes()
+                .any(|sink| (reflexive && src == sink) || index.reaches(src, sink))
+        })
+    }
+
+    /// Returns whether `src` flows to `sink` along a path on which no node
+    /// other than the endpoints is matched by `avoid`, e.g. without passing
+    /// through a sanitizer.
+    ///
+    /// Reflexive like [`Context::flows_to`]: with [`EdgeSelection::Control`]
+    /// and [`EdgeSelection::Both`] every node flows to itself, with
+    /// [`EdgeSelection::Data`] only if it lies on a cycle. Avoiding nothing
+    /// therefore agrees with `flows_to`.
+    ///
+    /// ```no_run
+    /// # use paralegal_policy::{Context, EdgeSelection, GlobalNode, paralegal_spdg::Identifier};
+    /// # fn check(ctx: &Context, src: GlobalNode, sink: GlobalNode) -> bool {
+    /// let sanitizer = Identifier::new_intern("sanitizes");
+    /// ctx.flows_to_avoiding(src, sink, EdgeSelection::Data, sanitizer)
+    /// # }
+    /// ```
+    pub fn flows_to_avoiding<'a>(
+        &self,
+        src: impl IntoIterGlobalNodes,
+        sink: impl IntoIterGlobalNodes,
+        edge_type: EdgeSelection,
+        avoid: impl Into<Avoid<'a>>,
+    ) -> bool {
+        let cf_id = src.controller_id();
+        if sink.controller_id() != cf_id {
+            return false;
+        }
+        let avoid = avoid.into();
+        if let Avoid::Marker(marker) = avoid {
+            self.report_marker_if_absent(marker);
+        }
+        generic_flows_to_avoiding(
+            src.iter_nodes(),
+            edge_type,
+            self.controller(cf_id),
+            sink.iter_nodes(),
+            |n| avoid.applies(self, GlobalNode::from_local_node(cf_id, n)),
+        )
+    }
+
+    /// All nodes influenced by `src` along paths on which no node (other than
+    /// `src` and the influenced node) is matched by `avoid`. See
+    /// [`Context::flows_to_avoiding`].
+    ///
+    /// Like [`Context::influencees`] the input nodes are only returned for
+    /// [`EdgeSelection::Control`] and [`EdgeSelection::Both`] or if they lie
+    /// on a cycle.
+    pub fn influencees_avoiding<'a>(
+        &self,
+        src: impl IntoIterGlobalNodes,
+        edge_type: EdgeSelection,
+        avoid: impl Into<Avoid<'a>>,
+    ) -> impl Iterator<Item = GlobalNode> + '_ {
+        let cf_id = src.controller_id();
+        let avoid = avoid.into();
+        if let Avoid::Marker(marker) = avoid {
+            self.report_marker_if_absent(marker);
         }
+        reachable_from_avoiding(src.iter_nodes(), edge_type, self.controller(cf_id), |n| {
+            avoid.applies(self, GlobalNode::from_local_node(cf_id, n))
+        })
+        .into_iter()
+        .map(move |n| GlobalNode::from_local_node(cf_id, n))
+    }
+
+    /// A shortest path witnessing [`Context::flows_to`], e.g. to show the user
+    /// how a forbidden flow arises with [`DiagnosticBuilder::with_flow_path`].
+    ///
+    /// Returns `None` if there is no flow. Like `flows_to`, control and
+    /// combined flows are reflexive: with [`EdgeSelection::Control`] or
+    /// [`EdgeSelection::Both`] a node that is in both `src` and `sink` is
+    /// witnessed by a path without edges. With [`EdgeSelection::Data`] it only
+    /// yields a path if it lies on a cycle.
+    ///
+    /// [`DiagnosticBuilder::with_flow_path`]: crate::diagnostics::DiagnosticBuilder::with_flow_path
+    pub fn flow_path(
+        &self,
+        src: impl IntoIterGlobalNodes,
+        sink: impl IntoIterGlobalNodes,
+        edge_type: EdgeSelection,
+    ) -> Option<FlowPath> {
+        self.flow_paths(src, sink, edge_type, 1).pop()
+    }
+
+    /// Up to `k` distinct paths from `src` to `sink`, shortest first. See
+    /// [`Context::flow_path`].
+    pub fn flow_paths(
+        &self,
+        src: impl IntoIterGlobalNodes,
+        sink: impl IntoIterGlobalNodes,
+        edge_type: EdgeSelection,
+        k: usize,
+    ) -> Vec<FlowPath> {
+        let cf_id = src.controller_id();
+        if sink.controller_id() != cf_id {
+            return vec![];
+        }
+        let spdg = self.controller(cf_id);
+        let sinks = sink.iter_nodes().collect::<HashSet<_>>();
+        let mut paths = if edge_type.is_data() {
+            vec![]
+        } else {
+            src.iter_nodes()
+                .filter(|n| sinks.contains(n))
+                .unique()
+                .take(k)
+                .map(|n| FlowPath {
+                    nodes: vec![GlobalNode::from_local_node(cf_id, n)],
+                    edges: vec![],
+                })
+                .collect::<Vec<_>>()
+        };
+        let remaining = k - paths.len();
+        paths.extend(
+            k_shortest_paths(src.iter_nodes(), sinks, edge_type, spdg, remaining)
+                .into_iter()
+                .map(|path| FlowPath {
+                    nodes: path
+                        .nodes
+                        .iter()
+                        .map(|n| GlobalNode::from_local_node(cf_id, *n))
+                        .collect(),
+                    edges: path.edges.iter().map(|e| spdg.graph[*e].clone()).collect(),
+                }),
+        );
+        paths
     }
 
     /// Find the node that represents the `index`th argument of the controller
@@ -306,7 +642,7 @@ impl Context {
     /// If the controller with this id does not exist *or* the controller has
     /// fewer than `index` arguments.
     pub fn controller_argument(&self, ctrl_id: ControllerId, index: u32) -> Option<GlobalNode> {
-        let ctrl = self.desc.controllers.get(&ctrl_id)?;
+        let ctrl = self.summaries().get(&ctrl_id)?;
         let inner = *ctrl.arguments.get(index as usize)?;
 
         Some(GlobalNode::from_local_node(ctrl_id, inner))
@@ -320,10 +656,16 @@ impl Context {
         influencer: impl IntoIterGlobalNodes + Sized + Copy,
         target: impl IntoIterGlobalNodes + Sized + Copy,
     ) -> bool {
-        self.flows_to(influencer, target, EdgeSelection::Control)
-            || self
-                .influencees(influencer, EdgeSelection::Data)
-                .any(|inf| self.flows_to(inf, target, EdgeSelection::Control))
+        let cf_id = influencer.controller_id();
+        if target.controller_id() != cf_id {
+            return false;
+        }
+        let data = self.reachability(cf_id, EdgeSelection::Data);
+        let control = self.reachability(cf_id, EdgeSelection::Control);
+        let influences = |n| target.iter_nodes().any(|t| n == t || control.reaches(n, t));
+        influencer
+            .iter_nodes()
+            .any(|n| influences(n) || data.reachable_from(n).any(influences))
     }
 
     /// Returns iterator over all Nodes that influence the given sink Node.
@@ -334,26 +676,9 @@ impl Context {
         sink: impl IntoIterGlobalNodes + Sized,
         edge_type: EdgeSelection,
     ) -> impl Iterator<Item = GlobalNode> + '_ {
-        use petgraph::visit::*;
         let cf_id = sink.controller_id();
-        let nodes = sink.iter_nodes();
-
-        let reversed_graph = Reversed(&self.desc.controllers[&cf_id].graph);
-
-        match edge_type {
-            EdgeSelection::Data => {
-                let edges_filtered =
-                    EdgeFiltered::from_fn(reversed_graph, |e| e.weight().is_data());
-                bfs_iter(&edges_filtered, cf_id, nodes).collect::<Vec<_>>()
-            }
-            EdgeSelection::Control => {
-                let edges_filtered =
-                    EdgeFiltered::from_fn(reversed_graph, |e| e.weight().is_control());
-                bfs_iter(&edges_filtered, cf_id, nodes).collect::<Vec<_>>()
-            }
-            EdgeSelection::Both => bfs_iter(reversed_graph, cf_id, nodes).collect::<Vec<_>>(),
-        }
-        .into_iter()
+        let index = self.reachability(cf_id, edge_type);
+        with_start_nodes(cf_id, sink.iter_nodes(), |n| index.reaching(n))
     }
 
     /// Returns iterator over all Nodes that are influenced by the given src Node.
@@ -365,25 +690,21 @@ impl Context {
         edge_type: EdgeSelection,
     ) -> impl Iterator<Item = GlobalNode> + '_ {
         let cf_id = src.controller_id();
-
-        let graph = &self.desc.controllers[&cf_id].graph;
-
+        let index = self.reachability(cf_id, edge_type);
         match edge_type {
             EdgeSelection::Data => src
                 .iter_nodes()
                 .flat_map(|src| {
-                    self.flows_to[&cf_id].data_flows_to[src.index()]
-                        .iter_ones()
-                        .map(move |i| GlobalNode::unsafe_new(cf_id, i))
+                    index
+                        .reachable_from(src)
+                        .map(move |n| GlobalNode::from_local_node(cf_id, n))
                 })
-                .collect::<Vec<_>>(),
-            EdgeSelection::Both => bfs_iter(graph, cf_id, src.iter_nodes()).collect::<Vec<_>>(),
-            EdgeSelection::Control => {
-                let edges_filtered = EdgeFiltered::from_fn(graph, |e| e.weight().is_control());
-                bfs_iter(&edges_filtered, cf_id, src.iter_nodes()).collect::<Vec<_>>()
+                .collect::<Vec<_>>()
+                .into_iter(),
+            EdgeSelection::Both | EdgeSelection::Control => {
+                with_start_nodes(cf_id, src.iter_nodes(), |n| index.reachable_from(n))
             }
         }
-        .into_iter()
     }
 
     /// Returns an iterator over all objects marked with `marker`.
@@ -398,7 +719,7 @@ impl Context {
 
     /// Get the type(s) of a Node.
     pub fn get_node_types(&self, node: GlobalNode) -> &[DefId] {
-        self.desc.controllers[&node.controller_id()]
+        self.summaries()[&node.controller_id()]
             .type_assigns
             .get(&node.local_node())
             .map_or(&[], |v| v.0.as_slice())
@@ -422,7 +743,7 @@ impl Context {
         &self,
         ctrl_id: ControllerId,
     ) -> impl Iterator<Item = GlobalNode> + '_ {
-        let ctrl = &self.desc.controllers[&ctrl_id];
+        let ctrl = self.controller(ctrl_id);
         ctrl.graph
             .node_indices()
             .map(move |inner| GlobalNode::from_local_node(ctrl_id, inner))
@@ -434,7 +755,7 @@ impl Context {
         ctrl_id: ControllerId,
         t: DefId,
     ) -> impl Iterator<Item = GlobalNode> + '_ {
-        self.desc.controllers[&ctrl_id]
+        self.summaries()[&ctrl_id]
             .type_assigns
             .iter()
             .filter_map(move |(src, ids)| {
@@ -450,12 +771,17 @@ impl Context {
         ctrl_id: ControllerId,
         _edge_type: EdgeSelection,
     ) -> impl Iterator<Item = GlobalNode> + '_ {
-        let g = &self.desc.controllers[&ctrl_id].graph;
+        let g = &self.controller(ctrl_id).graph;
         g.externals(Incoming)
             .map(move |inner| GlobalNode::from_local_node(ctrl_id, inner))
     }
 
     /// Returns the input [`ProgramDescription`].
+    ///
+    /// In a context created with [`Self::new_lazy`] the
+    /// [`controllers`](ProgramDescription::controllers) of the description
+    /// are empty, use [`Self::controller`] and [`Self::all_controllers`]
+    /// instead.
     pub fn desc(&self) -> &ProgramDescription {
         &self.desc
     }
@@ -494,7 +820,7 @@ impl Context {
             .into_group_map();
 
         for (ctrl_id, starts) in &start_map {
-            let spdg = &self.desc.controllers[&ctrl_id];
+            let spdg = self.controller(*ctrl_id);
             let g = &spdg.graph;
             let mut origin_map = vec![<SPDGImpl as GraphBase>::NodeId::end(); g.node_bound()];
             for s in starts {
@@ -555,9 +881,10 @@ impl Context {
         })
     }
 
-    /// Iterate over all defined controllers
+    /// Iterate over all defined controllers. In a context created with
+    /// [`Self::new_lazy`] this loads every graph.
     pub fn all_controllers(&self) -> impl Iterator<Item = (ControllerId, &SPDG)> {
-        self.desc().controllers.iter().map(|(k, v)| (*k, v))
+        self.summaries().keys().map(|k| (*k, self.controller(*k)))
     }
 
     /// Returns a DisplayDef for the given def_id
@@ -567,10 +894,7 @@ impl Context {
 
     /// Returns a DisplayNode for the given Node
     pub fn describe_node(&self, node: GlobalNode) -> DisplayNode {
-        DisplayNode::pretty(
-            node.local_node(),
-            &self.desc.controllers[&node.controller_id()],
-        )
+        DisplayNode::pretty(node.local_node(), self.controller(node.controller_id()))
     }
 
     /// Return which data is being read from for the modification performed at
@@ -579,7 +903,7 @@ impl Context {
         let ctrl_id = call_string.root().function;
         NodeCluster::new(
             ctrl_id,
-            self.desc.controllers[&ctrl_id]
+            self.controller(ctrl_id)
                 .graph
                 .edge_references()
                 .filter(|e| e.weight().at == call_string)
@@ -592,7 +916,7 @@ impl Context {
         let ctrl_id = call_string.root().function;
         NodeCluster::new(
             ctrl_id,
-            self.desc.controllers[&ctrl_id]
+            self.controller(ctrl_id)
                 .graph
                 .edge_references()
                 .filter(|e| e.weight().at == call_string)
@@ -602,7 +926,20 @@ impl Context {
 
     /// Retrieve metadata about a node.
     pub fn node_info(&self, node: GlobalNode) -> &NodeInfo {
-        self.desc.controllers[&node.controller_id()].node_info(node.local_node())
+        self.controller(node.controller_id())
+            .node_info(node.local_node())
+    }
+
+    /// The identifier of this node that is stable across rebuilds, see
+    /// [`StableNodeId`].
+    pub fn stable_id(&self, node: GlobalNode) -> StableNodeId {
+        self.node_info(node).stable_id
+    }
+
+    /// Find the node with this stable identifier, e.g. one that was persisted
+    /// from a previous run. Returns `None` if the node no longer exists.
+    pub fn node_by_stable_id(&self, id: StableNodeId) -> Option<GlobalNode> {
+        self.stable_ids().get(&id).copied()
     }
 
     /// Retrieve metadata about the instruction executed by a specific node.
@@ -613,7 +950,7 @@ impl Context {
 
     /// Return the immediate successors of this node
     pub fn successors(&self, node: GlobalNode) -> impl Iterator<Item = GlobalNode> + '_ {
-        self.desc.controllers[&node.controller_id()]
+        self.controller(node.controller_id())
             .graph
             .neighbors(node.local_node())
             .map(move |n| GlobalNode::from_local_node(node.controller_id(), n))
@@ -621,7 +958,7 @@ impl Context {
 
     /// Return the immediate predecessors of this node
     pub fn predecessors(&self, node: GlobalNode) -> impl Iterator<Item = GlobalNode> + '_ {
-        self.desc.controllers[&node.controller_id()]
+        self.controller(node.controller_id())
             .graph
             .neighbors_directed(node.local_node(), petgraph::Direction::Incoming)
             .map(move |n| GlobalNode::from_local_node(node.controller_id(), n))
@@ -634,7 +971,7 @@ impl Context {
         src: impl IntoIterGlobalNodes + Sized,
     ) -> paralegal_spdg::NodeCluster {
         let mut start: Vec<_> = src.iter_nodes().collect();
-        let ctrl = &self.desc.controllers[&src.controller_id()].graph;
+        let ctrl = &self.controller(src.controller_id()).graph;
 
         for _ in 0..n {
             start = start.into_iter().flat_map(|n| ctrl.neighbors(n)).collect();
@@ -648,6 +985,42 @@ impl Context {
     }
 }
 
+/// A sequence of edges from a source to a sink, as returned by
+/// [`Context::flow_path`] and [`Context::flow_paths`].
+#[derive(Clone, Debug)]
+pub struct FlowPath {
+    nodes: Vec<GlobalNode>,
+    edges: Vec<EdgeInfo>,
+}
+
+impl FlowPath {
+    /// The nodes on the path, starting at the source and ending at the sink.
+    pub fn nodes(&self) -> &[GlobalNode] {
+        &self.nodes
+    }
+
+    /// The edges on the path. `edges()[i]` leads from `nodes()[i]` to
+    /// `nodes()[i + 1]`.
+    pub fn edges(&self) -> &[EdgeInfo] {
+        &self.edges
+    }
+
+    /// The node the path starts at
+    pub fn source(&self) -> GlobalNode {
+        self.nodes[0]
+    }
+
+    /// The node the path ends at
+    pub fn sink(&self) -> GlobalNode {
+        *self.nodes.last().unwrap()
+    }
+
+    /// Pairs of each edge with the node it leads to
+    pub fn hops(&self) -> impl Iterator<Item = (&EdgeInfo, GlobalNode)> + '_ {
+        self.edges.iter().zip(self.nodes[1..].iter().copied())
+    }
+}
+
 /// Provide display trait for DefId in a Context.
 pub struct DisplayDef<'a> {
     /// DefId to display.
@@ -813,6 +1186,286 @@ fn test_context() {
     );
 }
 
+#[test]
+fn test_stable_ids() {
+    let ctx = crate::test_utils::test_ctx();
+    let nodes = ctx.desc().all_nodes();
+    // Stable ids are unique
+    assert_eq!(ctx.stable_ids().len(), nodes.len());
+    for node in nodes {
+        assert_eq!(ctx.node_by_stable_id(ctx.stable_id(node)), Some(node));
+    }
+}
+
+#[test]
+fn test_slices() {
+    use paralegal_spdg::traverse::{backward_slice, forward_slice};
+    let ctx = crate::test_utils::test_ctx();
+    let ctrl = ctx
+        .controller_by_name(Identifier::new_intern("controller"))
+        .unwrap();
+    let spdg = &ctx.desc().controllers[&ctrl];
+    let src = ctx.controller_argument(ctrl, 0).unwrap();
+
+    let slice = forward_slice([src.local_node()], EdgeSelection::Data, spdg);
+    assert!(slice.node_for_original(src.local_node()).is_some());
+    for (new, &old) in slice.original.iter().enumerate() {
+        let old_node = GlobalNode::from_local_node(ctrl, old);
+        assert!(old_node == src || ctx.flows_to(src, old_node, EdgeSelection::Data));
+        assert_eq!(
+            slice.spdg.markers.get(&SPDGNode::new(new)),
+            spdg.markers.get(&old)
+        );
+    }
+
+    let sink = *slice.original.last().unwrap();
+    let back = backward_slice([sink], EdgeSelection::Data, spdg);
+    assert!(back.node_for_original(src.local_node()).is_some());
+}
+
+#[test]
+fn test_flow_paths() {
+    let ctx = crate::test_utils::test_ctx();
+    let ctrl = ctx
+        .controller_by_name(Identifier::new_intern("controller"))
+        .unwrap();
+    let src = ctx.controller_argument(ctrl, 0).unwrap();
+    let sink1 = crate::test_utils::get_sink_node(&ctx, ctrl, "sink1");
+    let sink2 = crate::test_utils::get_sink_node(&ctx, ctrl, "sink2");
+
+    let path = ctx.flow_path(src, &sink1, EdgeSelection::Data).unwrap();
+    assert_eq!(path.source(), src);
+    assert!(sink1.iter_nodes().any(|n| n == path.sink().local_node()));
+    assert_eq!(path.nodes().len(), path.edges().len() + 1);
+    assert!(path.edges().iter().all(EdgeInfo::is_data));
+    for (from, to) in path.nodes().iter().tuple_windows() {
+        assert!(ctx.successors(*from).any(|n| n == *to));
+    }
+    assert!(ctx.flow_path(src, &sink2, EdgeSelection::Data).is_none());
+
+    let paths = ctx.flow_paths(src, &sink1, EdgeSelection::Both, 3);
+    assert!(!paths.is_empty());
+    assert!(paths
+        .iter()
+        .tuple_windows()
+        .all(|(a, b)| a.edges().len() <= b.edges().len()));
+
+    // Every positive `flows_to` has a witness, also the reflexive ones
+    for selection in [
+        EdgeSelection::Data,
+        EdgeSelection::Control,
+        EdgeSelection::Both,
+    ] {
+        let path = ctx.flow_path(src, src, selection);
+        assert_eq!(path.is_some(), ctx.flows_to(src, src, selection));
+        if !selection.is_data() {
+            let path = path.unwrap();
+            assert_eq!(path.nodes(), [src]);
+            assert!(path.edges().is_empty());
+        }
+    }
+}
+
+#[test]
+fn test_flows_to_avoiding() {
+    let ctx = crate::test_utils::test_ctx();
+    let ctrl = ctx
+        .controller_by_name(Identifier::new_intern("controller"))
+        .unwrap();
+    let src = ctx.controller_argument(ctrl, 0).unwrap();
+    let sink1 = crate::test_utils::get_sink_node(&ctx, ctrl, "sink1");
+
+    let nothing = || Avoid::predicate(|_| false);
+    assert!(ctx.flows_to_avoiding(src, &sink1, EdgeSelection::Data, nothing()));
+    assert_eq!(
+        ctx.influencees_avoiding(src, EdgeSelection::Data, nothing())
+            .collect::<HashSet<_>>(),
+        ctx.influencees(src, EdgeSelection::Data)
+            .collect::<HashSet<_>>()
+    );
+
+    for selection in [
+        EdgeSelection::Data,
+        EdgeSelection::Control,
+        EdgeSelection::Both,
+    ] {
+        for sink in [src, sink1.iter_global_nodes().next().unwrap()] {
+            assert_eq!(
+                ctx.flows_to_avoiding(src, sink, selection, nothing()),
+                ctx.flows_to(src, sink, selection),
+                "{selection:?}"
+            );
+        }
+        assert_eq!(
+            ctx.influencees_avoiding(src, selection, nothing())
+                .collect::<HashSet<_>>(),
+            ctx.influencees(src, selection).collect::<HashSet<_>>(),
+            "{selection:?}"
+        );
+    }
+
+    // Avoiding everything leaves only direct edges
+    let direct = ctx
+        .successors(src)
+        .any(|n| sink1.iter_nodes().any(|s| s == n.local_node()));
+    assert_eq!(
+        ctx.flows_to_avoiding(src, &sink1, EdgeSelection::Both, Avoid::predicate(|_| true)),
+        direct
+    );
+}
+
+#[test]
+fn test_extracted_graph_is_valid() {
+    let ctx = crate::test_utils::test_ctx();
+    // The extractor only records def info for the items it visits, not for
+    // every called function
+    let errors = ctx.desc().validate().err().unwrap_or_default();
+    assert_eq!(
+        errors
+            .iter()
+            .filter(|e| !e.is_missing_def_info())
+            .collect::<Vec<_>>(),
+        Vec::<&paralegal_spdg::validate::ValidationError>::new()
+    );
+}
+
+#[test]
+fn test_lazy_context() {
+    use paralegal_spdg::artifact::{sharded, ArtifactFormat, ArtifactHeader};
+    let eager = crate::test_utils::test_ctx();
+    let dir = std::env::temp_dir().join(format!("paralegal-lazy-{}", std::process::id()));
+    let header = ArtifactHeader::new("test");
+    sharded::write_sharded(&header, eager.desc(), ArtifactFormat::Json, &dir).unwrap();
+    let lazy = crate::GraphLocation::custom(dir.clone())
+        .lazy()
+        .build_context()
+        .unwrap();
+    assert!(lazy.desc().controllers.is_empty());
+
+    let name = Identifier::new_intern("controller");
+    let ctrl = lazy.controller_by_name(name).unwrap();
+    assert_eq!(ctrl, eager.controller_by_name(name).unwrap());
+    let sink = Marker::new_intern("sink");
+    assert_eq!(
+        lazy.marked_nodes(sink).collect::<HashSet<_>>(),
+        eager.marked_nodes(sink).collect::<HashSet<_>>()
+    );
+    let src = lazy.controller_argument(ctrl, 0).unwrap();
+    for node in eager.all_nodes_for_ctrl(ctrl) {
+        assert_eq!(
+            lazy.flows_to(src, node, EdgeSelection::Data),
+            eager.flows_to(src, node, EdgeSelection::Data)
+        );
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_parallel_diagnostics_are_ordered() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Context>();
+    assert_send_sync::<crate::PolicyContext>();
+    assert_send_sync::<crate::diagnostics::ControllerContext>();
+
+    let ctx = Arc::new(crate::test_utils::test_ctx());
+    let run = || {
+        let names = ctx.par_controller_contexts(|ctx| {
+            let name = ctx.current().name;
+            ctx.warning(format!("checked {name}"));
+            name
+        });
+        let mut out = vec![];
+        assert!(ctx.emit_diagnostics(&mut out).unwrap());
+        (names, String::from_utf8(out).unwrap())
+    };
+    let (names, first) = run();
+    assert_eq!(names.len(), ctx.all_controllers().count());
+    assert!(names.windows(2).all(|w| w[0] <= w[1]));
+    let positions = names
+        .iter()
+        .map(|name| first.find(&format!("checked {name}")).unwrap())
+        .collect::<Vec<_>>();
+    assert!(positions.windows(2).all(|w| w[0] <= w[1]));
+    assert_eq!(run().1, first);
+}
+
+#[test]
+fn test_json_diagnostics() {
+    let ctx = crate::test_utils::test_ctx();
+    let ctrl = ctx
+        .controller_by_name(Identifier::new_intern("controller"))
+        .unwrap();
+    let src = ctx.controller_argument(ctrl, 0).unwrap();
+    let mut err = ctx.struct_node_error(src, "flows somewhere");
+    err.with_code("T001");
+    err.emit();
+    let mut out = vec![];
+    assert!(!ctx
+        .emit_diagnostics_as(&mut out, DiagnosticsFormat::JsonLines)
+        .unwrap());
+    let lines = String::from_utf8(out).unwrap();
+    let record: serde_json::Value = serde_json::from_str(lines.trim()).unwrap();
+    assert_eq!(record["code"], "T001");
+    assert_eq!(record["node"]["controller"], "controller");
+    assert_eq!(
+        record["node"]["stable_id"],
+        serde_json::to_value(ctx.stable_id(src)).unwrap()
+    );
+}
+
+#[test]
+fn test_baseline() {
+    let ctx = crate::test_utils::test_ctx();
+    let ctrl = ctx
+        .controller_by_name(Identifier::new_intern("controller"))
+        .unwrap();
+    let src = ctx.controller_argument(ctrl, 0).unwrap();
+    let record = |code: &str| {
+        ctx.clone()
+            .named_policy(Identifier::new_intern("baselined"), |ctx| {
+                let mut err = ctx.struct_node_error(src, "known violation");
+                err.with_code(code);
+                err.emit();
+            })
+    };
+    record("B001");
+    record("B002");
+    let baseline = ctx.baseline();
+    assert_eq!(baseline.entries().len(), 2);
+    assert!(baseline.entries()[0]
+        .fingerprint
+        .ends_with(&ctx.stable_id(src).to_string()));
+    assert!(!ctx.emit_diagnostics(std::io::sink()).unwrap());
+
+    record("B001");
+    let outcome = ctx.apply_baseline(&baseline);
+    assert_eq!(outcome.suppressed, 1);
+    assert_eq!(outcome.stale.len(), 1);
+    assert_eq!(outcome.stale[0].code.as_deref(), Some("B002"));
+    // Only the warning about the stale entry remains
+    assert!(ctx.emit_diagnostics(std::io::sink()).unwrap());
+}
+
+#[test]
+fn test_report() {
+    let ctx = crate::test_utils::test_ctx();
+    ctx.clone()
+        .named_policy(Identifier::new_intern("quiet"), |_| ());
+    ctx.clone()
+        .named_policy(Identifier::new_intern("failing"), |ctx| {
+            ctx.error("violated")
+        });
+    let report = ctx.report();
+    assert!(!report.passed());
+    let verdicts = report.verdicts();
+    assert!(verdicts[&Identifier::new_intern("quiet")]);
+    assert!(!verdicts[&Identifier::new_intern("failing")]);
+    assert_eq!(report.diagnostics().len(), 1);
+    assert_eq!(report.diagnostics()[0].message(), "violated");
+    // The diagnostics were drained
+    assert!(ctx.report().passed());
+}
+
 #[test]
 #[ignore = "Something is weird with the PDG construction here.
     See https://github.com/willcrichton/flowistry/issues/95"]
@@ -1005,3 +1658,91 @@ fn test_has_ctrl_influence() -> Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn test_datalog_rules_match_context() {
+    use paralegal_spdg::export::datalog::{Facts, RULES};
+    // The rules of `paralegal.dl` that are evaluated below
+    for rule in [
+        "flows_to(a, b) :- data_edge(a, b).",
+        "flows_to(a, c) :- flows_to(a, b), data_edge(b, c).",
+        "ctrl_flows_to(a, a) :- node(a, _, _).",
+        "ctrl_flows_to(a, c) :- ctrl_flows_to(a, b), ctrl_edge(b, c).",
+        "any_flows_to(a, a) :- node(a, _, _).",
+        "any_flows_to(a, c) :- any_flows_to(a, b), edge(b, c).",
+        "has_ctrl_influence(a, b) :- ctrl_flows_to(a, b).",
+        "has_ctrl_influence(a, b) :- flows_to(a, n), ctrl_flows_to(n, b).",
+    ] {
+        assert!(RULES.contains(rule), "{rule} is not in the rules");
+    }
+
+    type Relation = HashSet<(String, String)>;
+    /// Least fixed point of `rel(a, c) :- rel(a, b), step(b, c)` from `base`
+    fn closure(base: Relation, step: &[(String, String)]) -> Relation {
+        let mut rel = base;
+        loop {
+            let new = rel
+                .iter()
+                .flat_map(|(a, b)| {
+                    step.iter()
+                        .filter(move |(from, _)| from == b)
+                        .map(move |(_, c)| (a.clone(), c.clone()))
+                })
+                .filter(|pair| !rel.contains(pair))
+                .collect::<Vec<_>>();
+            if new.is_empty() {
+                return rel;
+            }
+            rel.extend(new);
+        }
+    }
+
+    let ctx = crate::test_utils::test_ctx();
+    let facts = Facts::new(ctx.desc());
+    let reflexive = facts
+        .node
+        .iter()
+        .map(|(n, _, _)| (n.clone(), n.clone()))
+        .collect::<Relation>();
+    let edges = [facts.data_edge.clone(), facts.ctrl_edge.clone()].concat();
+    let flows_to = closure(facts.data_edge.iter().cloned().collect(), &facts.data_edge);
+    let ctrl_flows_to = closure(reflexive.clone(), &facts.ctrl_edge);
+    let any_flows_to = closure(reflexive, &edges);
+    let mut has_ctrl_influence = ctrl_flows_to.clone();
+    for (a, n) in &flows_to {
+        has_ctrl_influence.extend(
+            ctrl_flows_to
+                .iter()
+                .filter(|(from, _)| from == n)
+                .map(|(_, b)| (a.clone(), b.clone())),
+        );
+    }
+
+    for (id, spdg) in ctx.all_controllers() {
+        let nodes = spdg
+            .all_sources()
+            .map(|n| GlobalNode::from_local_node(id, n))
+            .collect::<Vec<_>>();
+        for &a in &nodes {
+            for &b in &nodes {
+                let pair = (ctx.stable_id(a).to_string(), ctx.stable_id(b).to_string());
+                for (selection, derived) in [
+                    (EdgeSelection::Data, &flows_to),
+                    (EdgeSelection::Control, &ctrl_flows_to),
+                    (EdgeSelection::Both, &any_flows_to),
+                ] {
+                    assert_eq!(
+                        ctx.flows_to(a, b, selection),
+                        derived.contains(&pair),
+                        "{selection:?} flow from {a:?} to {b:?}"
+                    );
+                }
+                assert_eq!(
+                    ctx.has_ctrl_influence(a, b),
+                    has_ctrl_influence.contains(&pair),
+                    "control influence of {a:?} on {b:?}"
+                );
+            }
+        }
+    }
+}
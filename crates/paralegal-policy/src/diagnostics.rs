@@ -75,16 +75,63 @@
 //! Note that some methods, like [`Context::always_happens_before`] add a named
 //! combinator context by themselves when you use their
 //! [`report`][crate::AlwaysHappensBefore::report] functions.
-
-#![allow(clippy::arc_with_non_send_sync)]
+//!
+//! ## Parallel Evaluation
+//!
+//! All contexts are [`Send`] and [`Sync`], so diagnostics may be recorded
+//! from any thread. [`Context::par_controller_contexts`] checks a policy for
+//! every controller on multiple threads. The diagnostics of each controller
+//! are held back until all of them are done and then recorded in the order
+//! of the controller names, so the output does not depend on scheduling.
+//!
+//! ## Machine Readable Output
+//!
+//! Besides the default text, diagnostics can be emitted as SARIF or as JSON
+//! lines, see [`DiagnosticsFormat`] and [`Context::emit_diagnostics_as`]. A
+//! JSON line has these fields:
+//!
+//! - `severity`, `message` and `code` (see [`DiagnosticBuilder::with_code`],
+//!   `null` if none was set)
+//! - `context`: the policies, controllers and combinators the diagnostic was
+//!   recorded in, outermost first, as `{"kind": ..., "name": ...}`
+//! - `span`: the file, `start` and `end` (each with `line` and `col`) and the
+//!   `highlight`ed portion, or `null`
+//! - `node`: the graph node the message is about with its `controller`, the
+//!   `stable_id` (see [`Context::stable_id`]), its `index` in the graph and
+//!   `description`, or `null`
+//! - `label`: the label of the span (see
+//!   [`DiagnosticBuilder::with_primary_label`]) or `null` and `labels`: the
+//!   secondary spans, each with `span` and `label` (see
+//!   [`DiagnosticBuilder::with_span_label`])
+//! - `children`: the attached notes, help and warnings, each with `severity`,
+//!   `message`, `span`, `node`, `label` and `labels`
+//!
+//! ## Known Violations
+//!
+//! When a policy is introduced on an existing code base, the violations that
+//! are already accepted can be recorded in a [`baseline::Baseline`]. Only
+//! violations that are not in the baseline fail later runs, see the
+//! [`baseline`] module.
 
 use colored::*;
-use std::rc::Rc;
+use std::collections::BTreeSet;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
 use std::{io::Write, sync::Arc};
 
-use paralegal_spdg::{GlobalNode, Identifier, Span, SpanCoord, SPDG};
+use paralegal_spdg::{
+    embed::SourceReader, GlobalNode, Identifier, ProgramDescription, Span, SpanCoord, StableNodeId,
+    SPDG,
+};
+
+use crate::{Context, ControllerId, FlowPath};
+
+pub mod baseline;
+mod json;
+mod report;
+mod sarif;
 
-use crate::{Context, ControllerId};
+pub use report::PolicyReport;
 
 /// Check the condition and emit a [`Diagnostics::error`] if it fails.
 #[macro_export]
@@ -127,7 +174,7 @@ macro_rules! assert_warning {
 }
 
 /// Severity of a recorded diagnostic message
-#[derive(Debug, Clone, Copy, strum::AsRefStr)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum::AsRefStr)]
 #[strum(serialize_all = "snake_case")]
 pub enum Severity {
     /// This indicates that the policy failed.
@@ -155,8 +202,44 @@ impl Severity {
     }
 }
 
-/// Context provided to [`HasDiagnosticsBase::record`].
-type DiagnosticContextStack = Vec<String>;
+/// One level of the context a diagnostic was recorded in.
+#[derive(Debug, Clone, Copy)]
+enum ContextFrame {
+    Policy(Identifier),
+    Controller(Identifier),
+    Combinator(Identifier),
+}
+
+impl ContextFrame {
+    fn kind(self) -> &'static str {
+        match self {
+            ContextFrame::Policy(_) => "policy",
+            ContextFrame::Controller(_) => "controller",
+            ContextFrame::Combinator(_) => "combinator",
+        }
+    }
+
+    fn name(self) -> Identifier {
+        match self {
+            ContextFrame::Policy(name)
+            | ContextFrame::Controller(name)
+            | ContextFrame::Combinator(name) => name,
+        }
+    }
+}
+
+impl std::fmt::Display for ContextFrame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ContextFrame::Policy(name) => write!(f, "[policy: {name}]"),
+            ContextFrame::Controller(name) => write!(f, "[controller: {name}]"),
+            ContextFrame::Combinator(name) => write!(f, "{name}"),
+        }
+    }
+}
+
+/// Context provided to [`HasDiagnosticsBase::record`], innermost first.
+type DiagnosticContextStack = Vec<ContextFrame>;
 
 /// Representation of a diagnostic message. You should not interact with this
 /// type directly but use the methods on [`Diagnostics`] or
@@ -164,18 +247,51 @@ type DiagnosticContextStack = Vec<String>;
 #[derive(Debug)]
 pub struct Diagnostic {
     context: DiagnosticContextStack,
+    /// Assigned by the policy author with [`DiagnosticBuilder::with_code`]
+    code: Option<String>,
     main: DiagnosticPart,
     children: Vec<DiagnosticPart>,
 }
 
 impl Diagnostic {
-    fn write(&self, w: &mut impl std::fmt::Write) -> std::fmt::Result {
+    /// The main message
+    pub fn message(&self) -> &str {
+        &self.main.message
+    }
+
+    /// The severity of the main message
+    pub fn severity(&self) -> Severity {
+        self.main.severity
+    }
+
+    /// The code assigned with [`DiagnosticBuilder::with_code`]
+    pub fn code(&self) -> Option<&str> {
+        self.code.as_deref()
+    }
+
+    /// The outermost policy this diagnostic was recorded in
+    pub fn policy(&self) -> Option<Identifier> {
+        self.context.iter().rev().find_map(|frame| match frame {
+            ContextFrame::Policy(name) => Some(*name),
+            _ => None,
+        })
+    }
+
+    /// The outermost controller this diagnostic was recorded in
+    pub fn controller(&self) -> Option<Identifier> {
+        self.context.iter().rev().find_map(|frame| match frame {
+            ContextFrame::Controller(name) => Some(*name),
+            _ => None,
+        })
+    }
+
+    fn write(&self, w: &mut impl std::fmt::Write, sources: &SourceReader) -> std::fmt::Result {
         for ctx in self.context.iter().rev() {
             write!(w, "{ctx} ")?;
         }
-        self.main.write(w)?;
+        self.main.write(w, sources, self.code.as_deref())?;
         for c in &self.children {
-            c.write(w)?;
+            c.write(w, sources, None)?;
         }
         Ok(())
     }
@@ -186,6 +302,41 @@ struct DiagnosticPart {
     message: String,
     severity: Severity,
     span: Option<HighlightedSpan>,
+    /// The graph node this part was created for, if any
+    node: Option<InvolvedNode>,
+    /// Shown next to the underline of [`Self::span`]
+    primary_label: Option<String>,
+    /// Secondary spans shown in the same code frames as [`Self::span`]
+    labels: Vec<SpanLabel>,
+}
+
+/// A secondary span of a [`DiagnosticPart`], underlined with `-` and
+/// annotated with a label.
+#[derive(Clone, Debug)]
+struct SpanLabel {
+    span: HighlightedSpan,
+    label: String,
+}
+
+/// A graph node mentioned in a diagnostic, with the information needed to
+/// identify it after the [`Context`] is gone.
+#[derive(Clone, Debug)]
+struct InvolvedNode {
+    node: GlobalNode,
+    stable_id: StableNodeId,
+    controller: Identifier,
+    description: String,
+}
+
+impl InvolvedNode {
+    fn new(ctx: &Context, node: GlobalNode) -> Self {
+        InvolvedNode {
+            node,
+            stable_id: ctx.stable_id(node),
+            controller: ctx.summaries()[&node.controller_id()].name,
+            description: ctx.node_info(node).description.clone(),
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -224,52 +375,178 @@ impl From<Span> for HighlightedSpan {
 }
 
 impl DiagnosticPart {
-    fn write(&self, s: &mut impl std::fmt::Write) -> std::fmt::Result {
+    fn new(message: String, severity: Severity, span: Option<HighlightedSpan>) -> Self {
+        DiagnosticPart {
+            message,
+            severity,
+            span,
+            node: None,
+            primary_label: None,
+            labels: vec![],
+        }
+    }
+
+    /// Source code is taken from `sources`, which are embedded in the
+    /// description or read from disk. If neither is available only the location is printed.
+    ///
+    /// The primary span and the labeled spans are rendered together, with one
+    /// code frame per file, see [`write_code_frames`].
+    fn write(
+        &self,
+        s: &mut impl std::fmt::Write,
+        sources: &SourceReader,
+        code: Option<&str>,
+    ) -> std::fmt::Result {
         let severity = self.severity;
         let coloring = severity.color();
 
-        use std::io::BufRead;
-
-        writeln!(s, "{}: {}", severity.as_ref().color(coloring), self.message)?;
-        if let Some(src_loc) = &self.span {
-            let start_line = src_loc.span.start.line as usize;
-            let start_col = src_loc.span.start.col as usize;
-            let end_line = src_loc.span.end.line as usize;
-            let end_col = src_loc.span.end.col as usize;
-            let (hl_start_line, hl_start_col, hl_end_line, hl_end_col) =
-                if let Some(hl) = &src_loc.highlight {
-                    (
-                        hl.start.line as usize,
-                        hl.start.col as usize,
-                        hl.end.line as usize,
-                        hl.end.col as usize,
-                    )
-                } else {
-                    (start_line, start_col, end_line, end_col)
-                };
-            let max_line_len =
-                std::cmp::max(start_line.to_string().len(), end_line.to_string().len());
-            let tab: String = " ".repeat(max_line_len);
-            writeln!(
-                s,
-                "{tab}{} {}:{}:{}",
-                "-->".blue(),
-                src_loc.span.source_file.file_path,
-                start_line,
-                start_col,
-            )?;
-            writeln!(s, "{tab} {}", "|".blue())?;
-            let lines = std::io::BufReader::new(
-                std::fs::File::open(&src_loc.span.source_file.abs_file_path).unwrap(),
-            )
-            .lines()
-            .skip(start_line - 1)
-            .take(end_line - start_line + 1)
-            .enumerate();
-            for (i, line) in lines {
-                let line_content: String = line.unwrap();
-                let line_num = start_line + i;
+        let label = match code {
+            Some(code) => format!("{}[{code}]", severity.as_ref()),
+            None => severity.as_ref().to_owned(),
+        };
+        writeln!(s, "{}: {}", label.color(coloring), self.message)?;
+        let annotations = self
+            .span
+            .iter()
+            .map(|span| Annotation {
+                span,
+                label: self.primary_label.as_deref(),
+                primary: true,
+            })
+            .chain(self.labels.iter().map(|l| Annotation {
+                span: &l.span,
+                label: Some(&l.label),
+                primary: false,
+            }))
+            .collect::<Vec<_>>();
+        write_code_frames(s, sources, &annotations, coloring)
+    }
+}
 
+/// A span that is underlined in a code frame, with `^` if it is the primary
+/// span of a part and with `-` otherwise.
+struct Annotation<'a> {
+    span: &'a HighlightedSpan,
+    label: Option<&'a str>,
+    primary: bool,
+}
+
+impl Annotation<'_> {
+    /// Start and end of the underlined portion
+    fn highlight(&self) -> (SpanCoord, SpanCoord) {
+        self.span
+            .highlight
+            .as_ref()
+            .map_or((self.span.span.start, self.span.span.end), |hl| {
+                (hl.start, hl.end)
+            })
+    }
+
+    /// The display columns to underline in `line`, which is line `line_num`
+    /// of the file. `None` if this annotation does not cover that line.
+    fn columns(&self, line_num: usize, line: &str) -> Option<(usize, usize)> {
+        let (start, end) = self.highlight();
+        if line_num < start.line as usize || line_num > end.line as usize {
+            return None;
+        }
+        let to = if line_num == end.line as usize {
+            prefix_width(line, end.col)
+        } else {
+            line_length_while(line, |_| true)
+        };
+        let from = if line_num == start.line as usize {
+            prefix_width(line, start.col)
+        } else {
+            line_length_while(line, char::is_whitespace)
+        };
+        Some((from, to.max(from)))
+    }
+
+    fn color(&self, primary: Color) -> Color {
+        if self.primary {
+            primary
+        } else {
+            Color::Blue
+        }
+    }
+}
+
+/// Display width of `line` before column `col` (1-based)
+fn prefix_width(line: &str, col: u32) -> usize {
+    let len = (col as usize).saturating_sub(1);
+    line_length_while(line.get(..len).unwrap_or(line), |_| true)
+}
+
+/// Write a code frame for each file mentioned in `annotations`, in the order
+/// in which the files first occur, similar to rustc.
+///
+/// All annotations in a file share one frame. Lines between annotations are
+/// elided with `...` if more than one line apart. Each label is shown under
+/// the last underlined line of its annotation. A label is placed right of the
+/// underline if nothing else is underlined after it, otherwise it is connected
+/// to the start of its underline with `|` on the lines below.
+fn write_code_frames(
+    s: &mut impl std::fmt::Write,
+    sources: &SourceReader,
+    annotations: &[Annotation],
+    coloring: Color,
+) -> std::fmt::Result {
+    let max_line_len = annotations
+        .iter()
+        .map(|a| a.span.span.end.line.to_string().len())
+        .max()
+        .unwrap_or(0);
+    let tab: String = " ".repeat(max_line_len);
+    let mut files = vec![];
+    for a in annotations {
+        if !files.contains(&a.span.span.source_file) {
+            files.push(a.span.span.source_file);
+        }
+    }
+    for (i, file) in files.into_iter().enumerate() {
+        let in_file = annotations
+            .iter()
+            .filter(|a| a.span.span.source_file == file)
+            .collect::<Vec<_>>();
+        let location = in_file[0].span.span.start;
+        let arrow = if i == 0 { "-->" } else { ":::" };
+        writeln!(
+            s,
+            "{tab}{} {}:{}:{}",
+            arrow.blue(),
+            file.file_path,
+            location.line,
+            location.col,
+        )?;
+        let mut ranges = in_file
+            .iter()
+            .map(|a| (a.span.span.start.line, a.span.span.end.line))
+            .collect::<Vec<_>>();
+        ranges.sort();
+        let mut merged: Vec<(u32, u32)> = vec![];
+        for (start, end) in ranges {
+            match merged.last_mut() {
+                // Also merge if only one line is in between, eliding it
+                // would not save any space
+                Some(last) if start <= last.1 + 2 => last.1 = last.1.max(end),
+                _ => merged.push((start, end)),
+            }
+        }
+        let Some(chunks) = merged
+            .iter()
+            .map(|(start, end)| sources.lines(file, *start..=*end))
+            .collect::<Option<Vec<_>>>()
+        else {
+            writeln!(s, "{tab} {} (source not available)", "=".blue())?;
+            continue;
+        };
+        writeln!(s, "{tab} {}", "|".blue())?;
+        for (n, ((start, _), lines)) in merged.iter().zip(chunks).enumerate() {
+            if n > 0 {
+                writeln!(s, "{}", "...".blue())?;
+            }
+            for (k, line_content) in lines.into_iter().enumerate() {
+                let line_num = *start as usize + k;
                 writeln!(
                     s,
                     "{:<max_line_len$} {} {}",
@@ -277,31 +554,107 @@ impl DiagnosticPart {
                     "|".blue(),
                     line_content.replace('\t', &" ".repeat(TAB_SIZE))
                 )?;
-                if line_num >= hl_start_line && line_num <= hl_end_line {
-                    let end: usize = if line_num == hl_end_line {
-                        line_length_while(&line_content[0..hl_end_col - 1], |_| true)
-                    } else {
-                        line_length_while(&line_content, |_| true)
-                    };
-                    let start: usize = if line_num == hl_start_line {
-                        line_length_while(&line_content[0..hl_start_col - 1], |_| true)
-                    } else {
-                        line_length_while(&line_content, char::is_whitespace)
-                    };
-                    writeln!(
-                        s,
-                        "{tab} {} {}{}",
-                        "|".blue(),
-                        " ".repeat(start),
-                        "^".repeat(end - start).color(coloring)
-                    )?;
-                }
+                write_underlines(s, &tab, line_num, &line_content, &in_file, coloring)?;
             }
-            writeln!(s, "{tab} {}", "|".blue())?;
         }
+        writeln!(s, "{tab} {}", "|".blue())?;
+    }
+    Ok(())
+}
 
-        Ok(())
+/// Append `text` to `out` at display column `at`, where `width` is the
+/// display width of `out` so far.
+fn write_at(out: &mut String, width: &mut usize, at: usize, text: ColoredString, len: usize) {
+    out.push_str(&" ".repeat(at.saturating_sub(*width)));
+    out.push_str(&text.to_string());
+    *width = (*width).max(at) + len;
+}
+
+/// The underlines and labels of `annotations` for one source line.
+fn write_underlines(
+    s: &mut impl std::fmt::Write,
+    tab: &str,
+    line_num: usize,
+    line: &str,
+    annotations: &[&Annotation],
+    coloring: Color,
+) -> std::fmt::Result {
+    let mut marked = annotations
+        .iter()
+        .filter_map(|a| Some((*a, a.columns(line_num, line)?)))
+        .collect::<Vec<_>>();
+    if marked.is_empty() {
+        return Ok(());
+    }
+    // Primary annotations last, so they are drawn over secondary ones
+    marked.sort_by_key(|(a, _)| a.primary);
+    let width = marked.iter().map(|(_, (_, to))| *to).max().unwrap_or(0);
+    let mut cells: Vec<Option<&Annotation>> = vec![None; width];
+    for (a, (from, to)) in &marked {
+        for cell in &mut cells[*from..*to] {
+            *cell = Some(a);
+        }
     }
+    let mut underline = String::new();
+    let mut col = 0;
+    while col < width {
+        let primary = cells[col].map(|a| a.primary);
+        let run = cells[col..]
+            .iter()
+            .take_while(|c| c.map(|a| a.primary) == primary)
+            .count();
+        match cells[col] {
+            None => underline.push_str(&" ".repeat(run)),
+            Some(a) => {
+                let marker = if a.primary { "^" } else { "-" };
+                underline.push_str(&marker.repeat(run).color(a.color(coloring)).to_string());
+            }
+        }
+        col += run;
+    }
+
+    // Labels are shown on the last line of their annotation
+    let mut labeled = marked
+        .iter()
+        .filter(|(a, _)| a.label.is_some() && a.highlight().1.line as usize == line_num)
+        .map(|(a, (from, to))| (*a, *from, *to))
+        .collect::<Vec<_>>();
+    labeled.sort_by_key(|(_, from, to)| (*to, *from));
+    if let Some((a, _, to)) = labeled.last() {
+        if *to == width {
+            let label = a.label.unwrap_or_default().color(a.color(coloring));
+            underline.push_str(&format!(" {label}"));
+            labeled.pop();
+        }
+    }
+    writeln!(s, "{tab} {} {underline}", "|".blue())?;
+
+    // The remaining labels, rightmost first
+    labeled.sort_by_key(|(_, from, _)| std::cmp::Reverse(*from));
+    let connectors = |annotations: &[(&Annotation, usize, usize)]| {
+        let mut out = String::new();
+        let mut width = 0;
+        for (a, from, _) in annotations.iter().rev() {
+            write_at(&mut out, &mut width, *from, "|".color(a.color(coloring)), 1);
+        }
+        (out, width)
+    };
+    for (i, (a, from, _)) in labeled.iter().enumerate() {
+        if i == 0 {
+            writeln!(s, "{tab} {} {}", "|".blue(), connectors(&labeled).0)?;
+        }
+        let (mut out, mut width) = connectors(&labeled[i + 1..]);
+        let label = a.label.unwrap_or_default();
+        write_at(
+            &mut out,
+            &mut width,
+            *from,
+            label.color(a.color(coloring)),
+            line_length_while(label, |_| true),
+        );
+        writeln!(s, "{tab} {} {out}", "|".blue())?;
+    }
+    Ok(())
 }
 
 /// Facility to create structured diagnostics including spans and multi-part
@@ -317,6 +670,11 @@ impl DiagnosticPart {
 /// spans from a node and `with_span_<severity>` adds messages with custom
 /// spans.
 ///
+/// Additional spans can be shown in the code frame of a part with the
+/// `with_<span|node>_label` functions, for instance the source and the
+/// intermediate hops of a flow whose sink is the main span. They are added to
+/// the most recently added part.
+///
 /// Make sure to call [`Self::emit`] after construction, otherwise the
 /// diagnostic is not shown.
 #[derive(Debug)]
@@ -336,11 +694,8 @@ impl<'a, A: ?Sized> DiagnosticBuilder<'a, A> {
         DiagnosticBuilder {
             diagnostic: Diagnostic {
                 context: vec![],
-                main: DiagnosticPart {
-                    message,
-                    severity,
-                    span: span.map(Into::into),
-                },
+                code: None,
+                main: DiagnosticPart::new(message, severity, span.map(Into::into)),
                 children: vec![],
             },
             base,
@@ -353,13 +708,47 @@ impl<'a, A: ?Sized> DiagnosticBuilder<'a, A> {
         severity: Severity,
         span: Option<impl Into<HighlightedSpan>>,
     ) -> &mut Self {
-        self.diagnostic.children.push(DiagnosticPart {
-            message: message.into(),
+        self.diagnostic.children.push(DiagnosticPart::new(
+            message.into(),
             severity,
-            span: span.map(Into::into),
+            span.map(Into::into),
+        ));
+        self
+    }
+
+    /// The part that labels are added to
+    fn last_part(&mut self) -> &mut DiagnosticPart {
+        self.diagnostic
+            .children
+            .last_mut()
+            .unwrap_or(&mut self.diagnostic.main)
+    }
+
+    /// Show `label` next to the underlined span of the most recently added
+    /// part.
+    pub fn with_primary_label(&mut self, label: impl Into<String>) -> &mut Self {
+        self.last_part().primary_label = Some(label.into());
+        self
+    }
+
+    /// Underline `span` in the code frame of the most recently added part and
+    /// annotate it with `label`. Spans in the same file as the part's own span
+    /// are shown in the same code frame.
+    pub fn with_span_label(&mut self, span: Span, label: impl Into<String>) -> &mut Self {
+        self.last_part().labels.push(SpanLabel {
+            span: span.into(),
+            label: label.into(),
         });
         self
     }
+
+    /// Attach a code that identifies this kind of diagnostic, e.g. for
+    /// filtering in scripts. It is shown as `error[CODE]` and included in
+    /// the machine readable formats.
+    pub fn with_code(&mut self, code: impl Into<String>) -> &mut Self {
+        self.diagnostic.code = Some(code.into());
+        self
+    }
 }
 
 impl<'a, A: HasDiagnosticsBase + ?Sized> DiagnosticBuilder<'a, A> {
@@ -413,12 +802,54 @@ impl<'a, A: HasDiagnosticsBase + ?Sized> DiagnosticBuilder<'a, A> {
         self.with_node(Severity::Note, node, message.into())
     }
 
+    /// Append a note for the source of `path` and for every hop along it, each
+    /// with the span of the node that is reached.
+    pub fn with_flow_path(&mut self, path: &FlowPath) -> &mut Self {
+        let ctx = self.base.as_ctx();
+        self.with_node_note(
+            path.source(),
+            format!("flow starts at {}", ctx.describe_node(path.source())),
+        );
+        for (edge, node) in path.hops() {
+            self.with_node_note(
+                node,
+                format!("{} flow to {}", edge.kind, ctx.describe_node(node)),
+            );
+        }
+        self
+    }
+
+    /// Underline the span of `node` in the code frame of the most recently
+    /// added part and annotate it with `label`, see [`Self::with_span_label`].
+    pub fn with_node_label(&mut self, node: GlobalNode, label: impl Into<String>) -> &mut Self {
+        let span = highlighted_node_span(self.base.as_ctx(), node);
+        self.last_part().labels.push(SpanLabel {
+            span,
+            label: label.into(),
+        });
+        self
+    }
+
+    /// Label the source of `path` and every hop along it in the code frame of
+    /// the most recently added part. Unlike [`Self::with_flow_path`] this
+    /// shows the whole flow in one frame. The node of the part itself, e.g.
+    /// the sink of a `struct_node_error`, is not labeled again.
+    pub fn with_flow_path_labels(&mut self, path: &FlowPath) -> &mut Self {
+        let own = self.last_part().node.as_ref().map(|n| n.node);
+        self.with_node_label(path.source(), "flow starts here");
+        for (edge, node) in path.hops() {
+            if Some(node) != own {
+                self.with_node_label(node, format!("{} flow", edge.kind));
+            }
+        }
+        self
+    }
+
     fn with_node(&mut self, severity: Severity, node: GlobalNode, message: String) -> &mut Self {
-        self.with_child(
-            message,
-            severity,
-            Some(highlighted_node_span(self.base.as_ctx(), node)),
-        )
+        let ctx = self.base.as_ctx();
+        self.with_child(message, severity, Some(highlighted_node_span(ctx, node)));
+        self.diagnostic.children.last_mut().unwrap().node = Some(InvolvedNode::new(ctx, node));
+        self
     }
 }
 
@@ -427,7 +858,10 @@ impl<'a, A: HasDiagnosticsBase + ?Sized> DiagnosticBuilder<'a, A> {
 /// As a user you should only be using methods from [`Diagnostics`]. It
 /// may however be helpful to look at the implementors of this trait, as those
 /// are also implementors of [`Diagnostics`].
-pub trait HasDiagnosticsBase {
+///
+/// Implementors must be [`Send`] and [`Sync`] so that policies can be
+/// evaluated in parallel, see [`Context::par_controller_contexts`].
+pub trait HasDiagnosticsBase: Send + Sync {
     /// Base function for recording new diagnostics.
     ///
     /// This should be used by implementors of new wrappers, users should use
@@ -460,16 +894,6 @@ impl<T: HasDiagnosticsBase> HasDiagnosticsBase for &'_ T {
     }
 }
 
-impl<T: HasDiagnosticsBase> HasDiagnosticsBase for Rc<T> {
-    fn as_ctx(&self) -> &Context {
-        (**self).as_ctx()
-    }
-
-    fn record(&self, diagnostic: Diagnostic) {
-        (**self).record(diagnostic)
-    }
-}
-
 /// User-facing methods to emit diagnostics.
 ///
 /// This is how any types implementing [`HasDiagnosticsBase`] should actually be
@@ -684,12 +1108,14 @@ fn struct_node_diagnostic<B: HasDiagnosticsBase + ?Sized>(
     severity: Severity,
     msg: impl Into<String>,
 ) -> DiagnosticBuilder<'_, B> {
-    DiagnosticBuilder::init(
+    let mut builder = DiagnosticBuilder::init(
         msg.into(),
         severity,
         Some(highlighted_node_span(base.as_ctx(), node)),
         base,
-    )
+    );
+    builder.diagnostic.main.node = Some(InvolvedNode::new(base.as_ctx(), node));
+    builder
 }
 
 const TAB_SIZE: usize = 4;
@@ -707,6 +1133,22 @@ fn line_length_while(s: &str, mut cont: impl FnMut(char) -> bool) -> usize {
         .1
 }
 
+/// A span from column `start` to `end` of `line` in `src/main.rs`, which
+/// does not exist on disk.
+#[cfg(test)]
+fn test_span(line: u32, start: u32, end: u32) -> Span {
+    use paralegal_spdg::SourceFileInfo;
+    Span {
+        source_file: SourceFileInfo {
+            file_path: "src/main.rs".to_owned(),
+            abs_file_path: "/nonexistent/src/main.rs".into(),
+        }
+        .intern(),
+        start: SpanCoord { line, col: start },
+        end: SpanCoord { line, col: end },
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::diagnostics::{line_length_while, TAB_SIZE};
@@ -725,6 +1167,73 @@ mod tests {
             2 * TAB_SIZE + 1
         );
     }
+
+    #[test]
+    fn labels_share_one_code_frame() {
+        use crate::diagnostics::test_span as span;
+        use crate::diagnostics::{DiagnosticPart, Severity, SpanLabel};
+        use paralegal_spdg::{EmbeddedSource, ProgramDescription};
+        let source = "fn main() {
+    let x = source();
+    let y = x + 1;
+    check(y);
+    // nothing
+    // nothing
+    sink(y);
+}";
+        let mut desc = ProgramDescription::default();
+        desc.sources.insert(
+            span(1, 1, 1).source_file,
+            EmbeddedSource {
+                lines: (1..)
+                    .zip(source.lines())
+                    .map(|(n, l)| (n, l.to_owned()))
+                    .collect(),
+            },
+        );
+        let label = |span, label: &str| SpanLabel {
+            span,
+            label: label.to_owned(),
+        };
+        let mut part = DiagnosticPart::new(
+            "leak".to_owned(),
+            Severity::Error,
+            Some(span(7, 5, 12).into()),
+        );
+        part.primary_label = Some("data reaches the sink".to_owned());
+        part.labels = vec![
+            label(span(2, 13, 21).into(), "flow starts here"),
+            label(span(2, 9, 10).into(), "x defined"),
+            label(span(3, 13, 18).into(), "data flow"),
+        ];
+
+        let mut out = String::new();
+        part.write(&mut out, &desc.source_reader(), None).unwrap();
+        // Whether colors are used depends on the terminal, remove them
+        let mut rest = out.as_str();
+        let mut plain = String::new();
+        while let Some(esc) = rest.find('\x1b') {
+            plain.push_str(&rest[..esc]);
+            let end = rest[esc..].find('m').unwrap();
+            rest = &rest[esc + end + 1..];
+        }
+        plain.push_str(rest);
+        let expected = "error: leak
+ --> src/main.rs:7:5
+  |
+2 |     let x = source();
+  |         -   -------- flow starts here
+  |         |
+  |         x defined
+3 |     let y = x + 1;
+  |             ----- data flow
+...
+7 |     sink(y);
+  |     ^^^^^^^ data reaches the sink
+  |
+";
+        assert_eq!(plain, expected);
+    }
 }
 
 impl<T: HasDiagnosticsBase> Diagnostics for T {}
@@ -750,6 +1259,12 @@ impl std::ops::Deref for PolicyContext {
 }
 
 impl PolicyContext {
+    fn new(name: impl Into<Identifier>, inner: Arc<dyn HasDiagnosticsBase>) -> Self {
+        let name = name.into();
+        inner.as_ctx().diagnostics.enter_policy(name);
+        PolicyContext { name, inner }
+    }
+
     /// Add a named combinator to the diagnostic context.
     ///
     /// See the [module level documentation][self] for more information on
@@ -784,11 +1299,20 @@ impl PolicyContext {
     pub fn controller_contexts(self: &Arc<Self>) -> impl Iterator<Item = Arc<ControllerContext>> {
         ControllerContext::for_all(self.clone() as Arc<_>)
     }
+
+    /// Run `policy` for every controller in parallel, see
+    /// [`Context::par_controller_contexts`].
+    pub fn par_controller_contexts<A: Send>(
+        self: &Arc<Self>,
+        policy: impl Fn(Arc<ControllerContext>) -> A + Sync,
+    ) -> Vec<A> {
+        ControllerContext::par_for_all(self.clone() as Arc<_>, policy)
+    }
 }
 
 impl HasDiagnosticsBase for PolicyContext {
     fn record(&self, mut diagnostic: Diagnostic) {
-        diagnostic.context.push(format!("[policy: {}]", self.name));
+        diagnostic.context.push(ContextFrame::Policy(self.name));
         self.inner.record(diagnostic)
     }
 
@@ -842,10 +1366,7 @@ impl ControllerContext {
         name: impl Into<Identifier>,
         policy: impl FnOnce(Arc<PolicyContext>) -> A,
     ) -> A {
-        policy(Arc::new(PolicyContext {
-            name: name.into(),
-            inner: self as Arc<_>,
-        }))
+        policy(Arc::new(PolicyContext::new(name, self as Arc<_>)))
     }
 
     /// Access the id for the controller of this context
@@ -855,13 +1376,59 @@ impl ControllerContext {
 
     /// Access the current controller contents
     pub fn current(&self) -> &SPDG {
-        &self.inner.as_ctx().desc().controllers[&self.id]
+        self.inner.as_ctx().controller(self.id)
+    }
+
+    fn par_for_all<A: Send>(
+        ctx: Arc<dyn HasDiagnosticsBase>,
+        policy: impl Fn(Arc<Self>) -> A + Sync,
+    ) -> Vec<A> {
+        let summaries = ctx.as_ctx().summaries();
+        let mut ids = summaries.keys().copied().collect::<Vec<_>>();
+        ids.sort_by_cached_key(|id| (summaries[id].name, format!("{id:?}")));
+        let buffers = ids
+            .iter()
+            .map(|_| {
+                Arc::new(BufferedContext {
+                    inner: ctx.clone(),
+                    buffer: Default::default(),
+                })
+            })
+            .collect::<Vec<_>>();
+        let results = ids.iter().map(|_| Mutex::new(None)).collect::<Vec<_>>();
+        let next = AtomicUsize::new(0);
+        let threads = std::thread::available_parallelism()
+            .map_or(1, |n| n.get())
+            .min(ids.len());
+        std::thread::scope(|scope| {
+            for _ in 0..threads {
+                scope.spawn(|| loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(&id) = ids.get(i) else {
+                        break;
+                    };
+                    let result = policy(Arc::new(Self {
+                        id,
+                        inner: buffers[i].clone(),
+                    }));
+                    *results[i].lock().unwrap() = Some(result);
+                });
+            }
+        });
+        for buffered in buffers {
+            for diagnostic in buffered.buffer.lock().unwrap().drain(..) {
+                ctx.record(diagnostic);
+            }
+        }
+        results
+            .into_iter()
+            .map(|r| r.into_inner().unwrap().unwrap())
+            .collect()
     }
 
     fn for_all(ctx: Arc<dyn HasDiagnosticsBase>) -> impl Iterator<Item = Arc<Self>> {
         ctx.as_ctx()
-            .desc()
-            .controllers
+            .summaries()
             .keys()
             .copied()
             .collect::<Vec<_>>()
@@ -876,8 +1443,8 @@ impl ControllerContext {
 
 impl HasDiagnosticsBase for ControllerContext {
     fn record(&self, mut diagnostic: Diagnostic) {
-        let name = self.as_ctx().desc().controllers[&self.id].name;
-        diagnostic.context.push(format!("[controller: {}]", name));
+        let name = self.as_ctx().summaries()[&self.id].name;
+        diagnostic.context.push(ContextFrame::Controller(name));
         self.inner.record(diagnostic)
     }
 
@@ -886,6 +1453,23 @@ impl HasDiagnosticsBase for ControllerContext {
     }
 }
 
+/// Holds back the diagnostics of one controller during
+/// [`ControllerContext::par_for_all`].
+struct BufferedContext {
+    inner: Arc<dyn HasDiagnosticsBase>,
+    buffer: Mutex<Vec<Diagnostic>>,
+}
+
+impl HasDiagnosticsBase for BufferedContext {
+    fn record(&self, diagnostic: Diagnostic) {
+        self.buffer.lock().unwrap().push(diagnostic)
+    }
+
+    fn as_ctx(&self) -> &Context {
+        self.inner.as_ctx()
+    }
+}
+
 /// A context for combinators.
 ///
 /// You may call any method and access any field defined on [`Context`]. In
@@ -929,7 +1513,7 @@ impl CombinatorContext {
 
 impl HasDiagnosticsBase for CombinatorContext {
     fn record(&self, mut diagnostic: Diagnostic) {
-        diagnostic.context.push(format!("{}", self.name));
+        diagnostic.context.push(ContextFrame::Combinator(self.name));
         self.inner.record(diagnostic)
     }
 
@@ -948,10 +1532,7 @@ impl Context {
         name: impl Into<Identifier>,
         policy: impl FnOnce(Arc<PolicyContext>) -> A,
     ) -> A {
-        policy(Arc::new(PolicyContext {
-            name: name.into(),
-            inner: self as Arc<_>,
-        }))
+        policy(Arc::new(PolicyContext::new(name, self as Arc<_>)))
     }
 
     /// Run the computation in the diagnostic context of this controller
@@ -985,40 +1566,127 @@ impl Context {
     pub fn controller_contexts(self: &Arc<Self>) -> impl Iterator<Item = Arc<ControllerContext>> {
         ControllerContext::for_all(self.clone() as Arc<_>)
     }
+
+    /// Run `policy` for every controller on multiple threads and return the
+    /// results in the order of the controller names.
+    ///
+    /// The diagnostics recorded by `policy` are held back until all
+    /// controllers are done and then recorded in the same order, so that the
+    /// output is the same as when running the controllers one after the
+    /// other, independent of how the threads were scheduled.
+    pub fn par_controller_contexts<A: Send>(
+        self: &Arc<Self>,
+        policy: impl Fn(Arc<ControllerContext>) -> A + Sync,
+    ) -> Vec<A> {
+        ControllerContext::par_for_all(self.clone() as Arc<_>, policy)
+    }
+}
+
+/// How [`Context::emit_diagnostics_as`] renders diagnostics.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DiagnosticsFormat {
+    /// Human readable text with source excerpts, as rustc prints them
+    #[default]
+    Text,
+    /// A single [SARIF 2.1.0](https://sarifweb.azurewebsites.net/) log with
+    /// one result per diagnostic, for code scanning tools
+    Sarif,
+    /// One JSON object per line and diagnostic, for scripts. See the
+    /// [module level documentation][self] for the fields.
+    JsonLines,
 }
 
 /// Base database of emitted diagnostics.
 #[derive(Debug, Default)]
-pub(crate) struct DiagnosticsRecorder(std::sync::Mutex<Vec<Diagnostic>>);
+pub(crate) struct DiagnosticsRecorder {
+    diagnostics: Mutex<Vec<Diagnostic>>,
+    /// Every policy entered with `named_policy`, including those that did
+    /// not record anything
+    policies: Mutex<BTreeSet<Identifier>>,
+}
 
-struct DisplayDiagnostic<'a>(&'a Diagnostic);
+struct DisplayDiagnostic<'a>(&'a Diagnostic, &'a SourceReader<'a>);
 
 impl<'a> std::fmt::Display for DisplayDiagnostic<'a> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        self.0.write(f)
+        self.0.write(f, self.1)
     }
 }
 
+/// Render `diagnostics` in `format`. Spans are rendered with the source code
+/// from `desc`, see [`ProgramDescription::source_reader`].
+fn write_diagnostics(
+    w: &mut impl Write,
+    diagnostics: &[Diagnostic],
+    desc: &ProgramDescription,
+    format: DiagnosticsFormat,
+) -> std::io::Result<()> {
+    match format {
+        DiagnosticsFormat::Text => {
+            let sources = desc.source_reader();
+            for diag in diagnostics {
+                writeln!(w, "{}", DisplayDiagnostic(diag, &sources))?;
+            }
+        }
+        DiagnosticsFormat::JsonLines => {
+            for diag in diagnostics {
+                serde_json::to_writer(&mut *w, &json::to_json(diag))?;
+                writeln!(w)?;
+            }
+        }
+        DiagnosticsFormat::Sarif => {
+            serde_json::to_writer_pretty(&mut *w, &sarif::to_sarif(diagnostics, desc))?;
+            writeln!(w)?;
+        }
+    }
+    Ok(())
+}
+
+fn passed(diagnostics: &[Diagnostic]) -> bool {
+    diagnostics
+        .iter()
+        .all(|diag| !diag.main.severity.must_abort())
+}
+
 impl DiagnosticsRecorder {
+    fn enter_policy(&self, name: Identifier) {
+        self.policies.lock().unwrap().insert(name);
+    }
+
+    /// Drain the queued diagnostics and the names of the entered policies.
+    fn take(&self) -> (Vec<Diagnostic>, BTreeSet<Identifier>) {
+        (
+            std::mem::take(&mut *self.diagnostics.lock().unwrap()),
+            std::mem::take(&mut *self.policies.lock().unwrap()),
+        )
+    }
+
     /// Emit queued diagnostics, draining the internal queue of diagnostics.
+    /// Spans are rendered with the source code from `desc`, see
+    /// [`ProgramDescription::source_reader`].
     ///
     /// A return `true` means the program may continue, on `false` it should be
     /// aborted.
-    pub(crate) fn emit(&self, mut w: impl Write) -> std::io::Result<bool> {
-        let w = &mut w;
-        let mut can_continue = true;
-        for diag in self.0.lock().unwrap().drain(..) {
-            writeln!(w, "{}", DisplayDiagnostic(&diag))?;
-            can_continue &= !diag.main.severity.must_abort();
-        }
-        Ok(can_continue)
+    pub(crate) fn emit(
+        &self,
+        mut w: impl Write,
+        desc: &ProgramDescription,
+        format: DiagnosticsFormat,
+    ) -> std::io::Result<bool> {
+        let diagnostics = std::mem::take(&mut *self.diagnostics.lock().unwrap());
+        write_diagnostics(&mut w, &diagnostics, desc, format)?;
+        Ok(passed(&diagnostics))
     }
 }
 
 impl HasDiagnosticsBase for Context {
     /// Record a diagnostic message.
     fn record(&self, diagnostic: Diagnostic) {
-        self.diagnostics.0.lock().unwrap().push(diagnostic);
+        self.diagnostics
+            .diagnostics
+            .lock()
+            .unwrap()
+            .push(diagnostic);
     }
 
     fn as_ctx(&self) -> &Context {
@@ -0,0 +1,264 @@
+//! Known violations that should not fail a policy.
+//!
+//! A [`Baseline`] is created from the diagnostics of one run with
+//! [`Context::baseline`] and stored with [`Baseline::write`]. In later runs
+//! [`Context::apply_baseline`] removes every diagnostic that is also in the
+//! baseline, so that only new violations are reported and fail the policy.
+//! Baseline entries that no longer match any diagnostic are reported as
+//! warnings, so the file can be cleaned up once a violation is fixed.
+//!
+//! Diagnostics are matched by a [`BaselineEntry`], which consists of
+//!
+//! - the outermost policy the diagnostic was recorded in,
+//! - its code (see [`DiagnosticBuilder::with_code`](super::DiagnosticBuilder::with_code)),
+//! - a fingerprint of its location: the [stable id](crate::Context::stable_id)
+//!   of the node it is about or, if there is none, its span and
+//! - its message, which is only compared if the diagnostic has no code.
+//!
+//! Stable ids do not change when unrelated code is edited, spans do. Prefer
+//! node diagnostics (e.g. [`Diagnostics::struct_node_error`]) and codes for
+//! violations that should be baselined.
+//!
+//! Only errors and warnings are part of a baseline.
+
+use std::{collections::HashMap, io, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use super::{Diagnostic, DiagnosticPart, Diagnostics, Severity};
+use crate::Context;
+
+/// Identifies one diagnostic in a [`Baseline`], see the
+/// [module level documentation](self).
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct BaselineEntry {
+    /// The outermost policy the diagnostic was recorded in
+    pub policy: Option<String>,
+    /// The code of the diagnostic
+    pub code: Option<String>,
+    /// `node:<controller>:<stable id>` for diagnostics about a node,
+    /// `span:<file>:<line>:<column>` for diagnostics with a span, otherwise
+    /// empty
+    pub fingerprint: String,
+    /// The message of the diagnostic
+    pub message: String,
+}
+
+impl BaselineEntry {
+    fn new(diagnostic: &Diagnostic) -> Self {
+        BaselineEntry {
+            policy: diagnostic.policy().map(|name| name.as_str().to_owned()),
+            code: diagnostic.code.clone(),
+            fingerprint: fingerprint(&diagnostic.main),
+            message: diagnostic.main.message.clone(),
+        }
+    }
+
+    /// This entry with only the fields that are compared. The message is
+    /// only relevant if there is no code.
+    fn key(&self) -> Self {
+        let mut key = self.clone();
+        if key.code.is_some() {
+            key.message.clear();
+        }
+        key
+    }
+}
+
+impl std::fmt::Display for BaselineEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(policy) = &self.policy {
+            write!(f, "[policy: {policy}] ")?;
+        }
+        if let Some(code) = &self.code {
+            write!(f, "[{code}] ")?;
+        }
+        if !self.fingerprint.is_empty() {
+            write!(f, "{} ", self.fingerprint)?;
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+fn fingerprint(part: &DiagnosticPart) -> String {
+    if let Some(node) = &part.node {
+        format!("node:{}:{}", node.controller, node.stable_id)
+    } else if let Some(span) = &part.span {
+        let start = span
+            .highlight
+            .as_ref()
+            .map_or(span.span.start, |hl| hl.start);
+        format!(
+            "span:{}:{}:{}",
+            span.span.source_file.file_path, start.line, start.col
+        )
+    } else {
+        String::new()
+    }
+}
+
+fn is_baselined(diagnostic: &Diagnostic) -> bool {
+    matches!(
+        diagnostic.main.severity,
+        Severity::Error | Severity::Warning
+    )
+}
+
+/// A set of known violations, see the [module level documentation](self).
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Baseline {
+    entries: Vec<BaselineEntry>,
+}
+
+impl Baseline {
+    fn new(diagnostics: &[Diagnostic]) -> Self {
+        let mut entries = diagnostics
+            .iter()
+            .filter(|diag| is_baselined(diag))
+            .map(BaselineEntry::new)
+            .collect::<Vec<_>>();
+        entries.sort();
+        Self { entries }
+    }
+
+    /// The known violations. An entry may occur more than once if a
+    /// violation was reported multiple times.
+    pub fn entries(&self) -> &[BaselineEntry] {
+        &self.entries
+    }
+
+    /// Read a baseline written by [`Self::write`].
+    pub fn read(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = std::fs::File::open(path)?;
+        Ok(serde_json::from_reader(io::BufReader::new(file))?)
+    }
+
+    /// Write this baseline as JSON. The entries are sorted so that the file
+    /// can be reviewed and diffed.
+    pub fn write(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let file = std::fs::File::create(path)?;
+        serde_json::to_writer_pretty(io::BufWriter::new(file), self)?;
+        Ok(())
+    }
+
+    /// Remove the diagnostics that are in this baseline from `diagnostics`
+    /// and return the entries that matched none of them.
+    fn suppress(&self, diagnostics: &mut Vec<Diagnostic>) -> (usize, Vec<BaselineEntry>) {
+        let mut remaining: HashMap<BaselineEntry, usize> = HashMap::new();
+        for entry in &self.entries {
+            *remaining.entry(entry.key()).or_default() += 1;
+        }
+        let before = diagnostics.len();
+        diagnostics.retain(|diag| {
+            if !is_baselined(diag) {
+                return true;
+            }
+            match remaining.get_mut(&BaselineEntry::new(diag).key()) {
+                Some(count) if *count > 0 => {
+                    *count -= 1;
+                    false
+                }
+                _ => true,
+            }
+        });
+        let suppressed = before - diagnostics.len();
+        let mut stale = vec![];
+        for entry in &self.entries {
+            if let Some(count) = remaining.get_mut(&entry.key()) {
+                if *count > 0 {
+                    *count -= 1;
+                    stale.push(entry.clone());
+                }
+            }
+        }
+        (suppressed, stale)
+    }
+}
+
+/// The result of [`Context::apply_baseline`]
+#[derive(Clone, Debug)]
+pub struct BaselineOutcome {
+    /// How many diagnostics were removed because they are in the baseline
+    pub suppressed: usize,
+    /// Baseline entries that did not match any diagnostic
+    pub stale: Vec<BaselineEntry>,
+}
+
+impl Context {
+    /// A baseline of the diagnostics recorded so far, see the
+    /// [`baseline`](self) module. The diagnostics are not removed.
+    pub fn baseline(&self) -> Baseline {
+        Baseline::new(&self.diagnostics.diagnostics.lock().unwrap())
+    }
+
+    /// Remove all recorded diagnostics that are in `baseline`, so that
+    /// emitting the remaining diagnostics only fails on new violations.
+    ///
+    /// Records a warning for every entry of `baseline` that no longer
+    /// occurs.
+    pub fn apply_baseline(&self, baseline: &Baseline) -> BaselineOutcome {
+        let (suppressed, stale) =
+            baseline.suppress(&mut self.diagnostics.diagnostics.lock().unwrap());
+        for entry in &stale {
+            let mut warning =
+                self.struct_warning(format!("Baselined violation no longer occurs: {entry}"));
+            warning.with_help("remove it from the baseline");
+            warning.emit();
+        }
+        BaselineOutcome { suppressed, stale }
+    }
+}
+
+#[test]
+fn baseline_suppresses_known_violations() {
+    use super::ContextFrame;
+    use paralegal_spdg::Identifier;
+    let span = |line| super::test_span(line, 1, 10);
+    let diagnostic = |severity, code: Option<&str>, message: &str, line| Diagnostic {
+        context: vec![
+            ContextFrame::Controller(Identifier::new_intern("main")),
+            ContextFrame::Policy(Identifier::new_intern("no-leak")),
+        ],
+        code: code.map(str::to_owned),
+        main: DiagnosticPart::new(message.to_owned(), severity, Some(span(line).into())),
+        children: vec![],
+    };
+    let known = [
+        diagnostic(Severity::Error, Some("L001"), "leak of a", 3),
+        diagnostic(Severity::Error, Some("L001"), "leak of a", 3),
+        diagnostic(Severity::Error, None, "unchecked", 5),
+        diagnostic(Severity::Warning, None, "fixed since", 7),
+        diagnostic(Severity::Note, None, "not baselined", 9),
+    ];
+    let baseline = Baseline::new(&known);
+    assert_eq!(baseline.entries().len(), 4);
+    assert_eq!(baseline.entries()[3].fingerprint, "span:src/main.rs:3:1");
+
+    let path = std::env::temp_dir().join(format!("paralegal-baseline-{}", std::process::id()));
+    baseline.write(&path).unwrap();
+    let baseline = Baseline::read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let mut diagnostics = vec![
+        // Codes are compared instead of messages
+        diagnostic(Severity::Error, Some("L001"), "leak of b", 3),
+        diagnostic(Severity::Error, Some("L001"), "leak of a", 3),
+        // Reported once more than in the baseline
+        diagnostic(Severity::Error, Some("L001"), "leak of a", 3),
+        diagnostic(Severity::Error, None, "unchecked", 5),
+        diagnostic(Severity::Error, None, "unchecked again", 5),
+    ];
+    let (suppressed, stale) = baseline.suppress(&mut diagnostics);
+    assert_eq!(suppressed, 3);
+    let remaining = diagnostics
+        .iter()
+        .map(|d| d.main.message.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(remaining, ["leak of a", "unchecked again"]);
+    assert_eq!(stale.len(), 1);
+    assert_eq!(stale[0].message, "fixed since");
+    assert_eq!(
+        stale[0].to_string(),
+        "[policy: no-leak] span:src/main.rs:7:1 fixed since"
+    );
+}
@@ -0,0 +1,109 @@
+//! Rendering diagnostics as JSON lines, see the
+//! [module level documentation](super#machine-readable-output) for the
+//! fields.
+
+use serde_json::{json, Value};
+
+use paralegal_spdg::SpanCoord;
+
+use super::{Diagnostic, DiagnosticPart, HighlightedSpan, InvolvedNode};
+
+fn coord(coord: SpanCoord) -> Value {
+    json!({ "line": coord.line, "col": coord.col })
+}
+
+fn span(span: &HighlightedSpan) -> Value {
+    json!({
+        "file": span.span.source_file.file_path,
+        "start": coord(span.span.start),
+        "end": coord(span.span.end),
+        "highlight": span.highlight.as_ref().map(|hl| json!({
+            "start": coord(hl.start),
+            "end": coord(hl.end),
+        })),
+    })
+}
+
+fn node(node: &InvolvedNode) -> Value {
+    json!({
+        "controller": node.controller.as_str(),
+        "stable_id": node.stable_id,
+        "index": node.node.local_node().index(),
+        "description": node.description,
+    })
+}
+
+fn part(part: &DiagnosticPart) -> Value {
+    json!({
+        "severity": part.severity.as_ref(),
+        "message": part.message,
+        "span": part.span.as_ref().map(span),
+        "node": part.node.as_ref().map(node),
+        "label": part.primary_label,
+        "labels": part
+            .labels
+            .iter()
+            .map(|l| json!({ "span": span(&l.span), "label": l.label }))
+            .collect::<Vec<_>>(),
+    })
+}
+
+/// The JSON object for one diagnostic
+pub(super) fn to_json(diagnostic: &Diagnostic) -> Value {
+    let mut record = part(&diagnostic.main);
+    record["code"] = json!(diagnostic.code);
+    record["context"] = diagnostic
+        .context
+        .iter()
+        .rev()
+        .map(|frame| json!({ "kind": frame.kind(), "name": frame.name().as_str() }))
+        .collect();
+    record["children"] = diagnostic.children.iter().map(part).collect();
+    record
+}
+
+#[test]
+fn diagnostic_to_json() {
+    use super::{ContextFrame, Severity};
+    use paralegal_spdg::Identifier;
+    let span = super::test_span(3, 1, 20);
+    let diagnostic = Diagnostic {
+        context: vec![
+            ContextFrame::Controller(Identifier::new_intern("main")),
+            ContextFrame::Policy(Identifier::new_intern("no-leak")),
+        ],
+        code: Some("L001".to_owned()),
+        main: DiagnosticPart::new(
+            "leak".to_owned(),
+            Severity::Error,
+            Some(HighlightedSpan::new(
+                span,
+                SpanCoord { line: 3, col: 5 },
+                SpanCoord { line: 3, col: 9 },
+            )),
+        ),
+        children: vec![DiagnosticPart::new(
+            "add a check".to_owned(),
+            Severity::Help,
+            None,
+        )],
+    };
+    let record = to_json(&diagnostic);
+    assert_eq!(record["severity"], "error");
+    assert_eq!(record["code"], "L001");
+    assert_eq!(
+        record["context"],
+        json!([
+            { "kind": "policy", "name": "no-leak" },
+            { "kind": "controller", "name": "main" },
+        ])
+    );
+    assert_eq!(record["span"]["file"], "src/main.rs");
+    assert_eq!(
+        record["span"]["highlight"]["start"],
+        json!({ "line": 3, "col": 5 })
+    );
+    assert_eq!(record["node"], Value::Null);
+    assert_eq!(record["children"][0]["severity"], "help");
+    assert_eq!(record["children"][0]["span"], Value::Null);
+}
@@ -0,0 +1,186 @@
+//! The results of a policy run as a value, see [`PolicyReport`].
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::Write,
+};
+
+use serde_json::{json, Value};
+
+use paralegal_spdg::{Identifier, ProgramDescription};
+
+use super::{json, passed, write_diagnostics, Diagnostic, DiagnosticsFormat};
+use crate::Context;
+
+/// Diagnostics grouped by the controller they were recorded for, `None` for
+/// those recorded outside of any controller.
+type ByController<'a> = BTreeMap<Option<Identifier>, Vec<&'a Diagnostic>>;
+
+/// All diagnostics recorded by a policy run and whether the policies hold.
+///
+/// Created by [`Context::report`] or
+/// [`GraphLocation::check`](crate::GraphLocation::check). Unlike
+/// [`Context::emit_diagnostics_may_exit`] creating a report never exits the
+/// process, so policies can be checked from within a test harness or a
+/// server.
+#[derive(Debug)]
+pub struct PolicyReport {
+    diagnostics: Vec<Diagnostic>,
+    policies: BTreeSet<Identifier>,
+    /// Only holds the embedded sources, for rendering spans after the
+    /// [`Context`] is gone.
+    sources: ProgramDescription,
+}
+
+impl PolicyReport {
+    fn new(
+        diagnostics: Vec<Diagnostic>,
+        policies: BTreeSet<Identifier>,
+        desc: &ProgramDescription,
+    ) -> Self {
+        let sources = ProgramDescription {
+            sources: desc.sources.clone(),
+            ..Default::default()
+        };
+        Self {
+            diagnostics,
+            policies,
+            sources,
+        }
+    }
+
+    /// Whether no errors were recorded.
+    pub fn passed(&self) -> bool {
+        passed(&self.diagnostics)
+    }
+
+    /// All diagnostics in the order they were recorded
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    /// Whether each named policy passed, by name. A policy fails if an error
+    /// was recorded in it. This includes every policy that was entered with
+    /// `named_policy`, also those that recorded nothing.
+    pub fn verdicts(&self) -> BTreeMap<Identifier, bool> {
+        let mut verdicts = self
+            .policies
+            .iter()
+            .map(|name| (*name, true))
+            .collect::<BTreeMap<_, _>>();
+        for diag in &self.diagnostics {
+            if let Some(policy) = diag.policy() {
+                let passed = verdicts.entry(policy).or_insert(true);
+                *passed &= !diag.main.severity.must_abort();
+            }
+        }
+        verdicts
+    }
+
+    /// The diagnostics grouped by the outermost policy and then by the
+    /// outermost controller they were recorded in. The `None` keys hold the
+    /// diagnostics recorded outside of any policy or controller.
+    pub fn grouped(&self) -> BTreeMap<Option<Identifier>, ByController<'_>> {
+        let mut groups: BTreeMap<_, ByController> = BTreeMap::new();
+        for diag in &self.diagnostics {
+            groups
+                .entry(diag.policy())
+                .or_default()
+                .entry(diag.controller())
+                .or_default()
+                .push(diag);
+        }
+        groups
+    }
+
+    /// Write the diagnostics in `format`, like
+    /// [`Context::emit_diagnostics_as`].
+    pub fn render(&self, mut w: impl Write, format: DiagnosticsFormat) -> std::io::Result<()> {
+        write_diagnostics(&mut w, &self.diagnostics, &self.sources, format)
+    }
+
+    /// The diagnostics as human readable text
+    pub fn to_text(&self) -> String {
+        let mut out = vec![];
+        self.render(&mut out, DiagnosticsFormat::Text)
+            .expect("writing to a vector does not fail");
+        String::from_utf8(out).expect("diagnostics are valid UTF-8")
+    }
+
+    /// The whole report as one JSON object with the overall verdict
+    /// (`passed`), the verdict of every named policy (`policies`, each with
+    /// `name` and `passed`) and the `diagnostics`. Each diagnostic has the
+    /// fields of a JSON line, see [`DiagnosticsFormat::JsonLines`].
+    pub fn to_json(&self) -> Value {
+        let policies = self
+            .verdicts()
+            .into_iter()
+            .map(|(name, passed)| json!({ "name": name.as_str(), "passed": passed }))
+            .collect::<Vec<_>>();
+        json!({
+            "passed": self.passed(),
+            "policies": policies,
+            "diagnostics": self.diagnostics.iter().map(json::to_json).collect::<Vec<_>>(),
+        })
+    }
+}
+
+impl Context {
+    /// Drain the recorded diagnostics into a [`PolicyReport`].
+    ///
+    /// Use this instead of [`Self::emit_diagnostics_may_exit`] to decide
+    /// yourself what to do with a failing policy.
+    pub fn report(&self) -> PolicyReport {
+        let (diagnostics, policies) = self.diagnostics.take();
+        PolicyReport::new(diagnostics, policies, self.desc())
+    }
+}
+
+#[test]
+fn report_groups_by_policy_and_controller() {
+    use super::{ContextFrame, DiagnosticPart, Severity};
+    let [policy_a, policy_b, main] = ["a", "b", "main"].map(Identifier::new_intern);
+    let diagnostic = |severity, context| Diagnostic {
+        context,
+        code: None,
+        main: DiagnosticPart::new("message".to_owned(), severity, None),
+        children: vec![],
+    };
+    let diagnostics = vec![
+        diagnostic(
+            Severity::Error,
+            vec![
+                ContextFrame::Controller(main),
+                ContextFrame::Policy(policy_a),
+            ],
+        ),
+        diagnostic(Severity::Warning, vec![ContextFrame::Policy(policy_a)]),
+        diagnostic(Severity::Warning, vec![ContextFrame::Policy(policy_b)]),
+        diagnostic(Severity::Warning, vec![]),
+    ];
+    let policies = [policy_a, policy_b, Identifier::new_intern("c")]
+        .into_iter()
+        .collect();
+    let report = PolicyReport::new(diagnostics, policies, &ProgramDescription::default());
+
+    assert!(!report.passed());
+    let verdicts = report.verdicts();
+    assert_eq!(
+        verdicts
+            .iter()
+            .map(|(k, v)| (k.as_str(), *v))
+            .collect::<Vec<_>>(),
+        [("a", false), ("b", true), ("c", true)]
+    );
+    let groups = report.grouped();
+    assert_eq!(groups.len(), 3);
+    assert_eq!(groups[&Some(policy_a)][&Some(main)].len(), 1);
+    assert_eq!(groups[&Some(policy_a)][&None].len(), 1);
+    assert_eq!(groups[&None][&None].len(), 1);
+
+    let json = report.to_json();
+    assert_eq!(json["passed"], false);
+    assert_eq!(json["policies"][1], json!({ "name": "b", "passed": true }));
+    assert_eq!(json["diagnostics"].as_array().unwrap().len(), 4);
+    assert_eq!(report.to_text().matches("message").count(), 4);
+}
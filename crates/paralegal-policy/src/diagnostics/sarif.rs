@@ -0,0 +1,216 @@
+//! Rendering diagnostics as [SARIF 2.1.0](https://docs.oasis-open.org/sarif/sarif/v2.1.0/sarif-v2.1.0.html),
+//! the format consumed by code scanning dashboards.
+//!
+//! Each [`Diagnostic`] becomes one result.
+//!
+//! - The rule is named after the policies and combinators the diagnostic was
+//!   recorded in, outermost first and joined with `/`.
+//! - The main span is the location.
+//! - Child parts with a span and labeled spans become related locations.
+//! - The full context stack and all child messages are kept in the result's
+//!   properties.
+
+use serde_json::{json, Value};
+
+use paralegal_spdg::{embed::SourceReader, ProgramDescription};
+
+use super::{ContextFrame, Diagnostic, HighlightedSpan, Severity};
+
+const SCHEMA: &str = "https://json.schemastore.org/sarif-2.1.0.json";
+
+/// Rule for diagnostics recorded outside of any named policy or combinator.
+const DEFAULT_RULE: &str = "paralegal";
+
+fn level(severity: Severity) -> &'static str {
+    match severity {
+        Severity::Error => "error",
+        Severity::Warning => "warning",
+        Severity::Note | Severity::Help => "note",
+    }
+}
+
+fn rule_id(diagnostic: &Diagnostic) -> String {
+    let names = diagnostic
+        .context
+        .iter()
+        .rev()
+        .filter_map(|frame| match frame {
+            ContextFrame::Policy(name) | ContextFrame::Combinator(name) => Some(name.as_str()),
+            ContextFrame::Controller(_) => None,
+        })
+        .collect::<Vec<_>>();
+    if names.is_empty() {
+        DEFAULT_RULE.to_owned()
+    } else {
+        names.join("/")
+    }
+}
+
+fn properties(diagnostic: &Diagnostic) -> Value {
+    let mut policies = vec![];
+    let mut controllers = vec![];
+    let mut combinators = vec![];
+    for frame in diagnostic.context.iter().rev() {
+        match frame {
+            ContextFrame::Policy(name) => policies.push(name.as_str()),
+            ContextFrame::Controller(name) => controllers.push(name.as_str()),
+            ContextFrame::Combinator(name) => combinators.push(name.as_str()),
+        }
+    }
+    let children = diagnostic
+        .children
+        .iter()
+        .map(|c| json!({ "severity": c.severity.as_ref(), "message": c.message }))
+        .collect::<Vec<_>>();
+    json!({
+        "code": diagnostic.code,
+        "policies": policies,
+        "controllers": controllers,
+        "combinators": combinators,
+        "children": children,
+    })
+}
+
+fn physical_location(span: &HighlightedSpan, sources: &SourceReader) -> Value {
+    let (start, end) = span
+        .highlight
+        .as_ref()
+        .map_or((span.span.start, span.span.end), |hl| (hl.start, hl.end));
+    let mut region = json!({
+        "startLine": start.line,
+        "startColumn": start.col,
+        "endLine": end.line,
+        "endColumn": end.col,
+    });
+    if let Some(lines) = sources.lines(span.span.source_file, start.line..=end.line) {
+        region["snippet"] = json!({ "text": lines.join("\n") });
+    }
+    json!({
+        "physicalLocation": {
+            "artifactLocation": { "uri": span.span.source_file.file_path },
+            "region": region,
+        }
+    })
+}
+
+fn related_locations(diagnostic: &Diagnostic, sources: &SourceReader) -> Vec<Value> {
+    let children = diagnostic.children.iter().filter_map(|child| {
+        let text = format!("{}: {}", child.severity.as_ref(), child.message);
+        Some((child.span.as_ref()?, text))
+    });
+    let labels = std::iter::once(&diagnostic.main)
+        .chain(&diagnostic.children)
+        .flat_map(|part| &part.labels)
+        .map(|l| (&l.span, l.label.clone()));
+    children
+        .chain(labels)
+        .enumerate()
+        .map(|(id, (span, text))| {
+            let mut location = physical_location(span, sources);
+            location["id"] = json!(id);
+            location["message"] = json!({ "text": text });
+            location
+        })
+        .collect()
+}
+
+/// Build a SARIF log with one run containing `diagnostics`. Source snippets
+/// are taken from `desc`, see [`ProgramDescription::source_reader`].
+pub(super) fn to_sarif(diagnostics: &[Diagnostic], desc: &ProgramDescription) -> Value {
+    let sources = desc.source_reader();
+    let mut rules: Vec<String> = vec![];
+    let results = diagnostics
+        .iter()
+        .map(|diagnostic| {
+            let rule = rule_id(diagnostic);
+            let rule_index = rules.iter().position(|r| *r == rule).unwrap_or_else(|| {
+                rules.push(rule.clone());
+                rules.len() - 1
+            });
+            let mut result = json!({
+                "ruleId": rule,
+                "ruleIndex": rule_index,
+                "level": level(diagnostic.main.severity),
+                "message": { "text": diagnostic.main.message },
+                "properties": properties(diagnostic),
+            });
+            if let Some(span) = &diagnostic.main.span {
+                result["locations"] = json!([physical_location(span, &sources)]);
+            }
+            let related = related_locations(diagnostic, &sources);
+            if !related.is_empty() {
+                result["relatedLocations"] = json!(related);
+            }
+            result
+        })
+        .collect::<Vec<_>>();
+    let rules = rules
+        .iter()
+        .map(|id| json!({ "id": id, "shortDescription": { "text": id } }))
+        .collect::<Vec<_>>();
+    json!({
+        "$schema": SCHEMA,
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "paralegal-policy",
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "rules": rules,
+                }
+            },
+            "results": results,
+        }]
+    })
+}
+
+#[test]
+fn diagnostics_map_to_sarif() {
+    use super::DiagnosticPart;
+    use paralegal_spdg::{Identifier, Span};
+    let desc = ProgramDescription::default();
+    let span = super::test_span(3, 5, 12);
+    let part = |severity, message: &str, span: Option<Span>| {
+        DiagnosticPart::new(message.to_owned(), severity, span.map(Into::into))
+    };
+    let diagnostics = [
+        Diagnostic {
+            context: vec![
+                ContextFrame::Combinator(Identifier::new_intern("reach")),
+                ContextFrame::Controller(Identifier::new_intern("main")),
+                ContextFrame::Policy(Identifier::new_intern("no-leak")),
+            ],
+            code: Some("L001".to_owned()),
+            main: part(Severity::Error, "leak", Some(span.clone())),
+            children: vec![
+                part(Severity::Note, "from here", Some(span)),
+                part(Severity::Help, "add a check", None),
+            ],
+        },
+        Diagnostic {
+            context: vec![],
+            code: None,
+            main: part(Severity::Warning, "vacuous", None),
+            children: vec![],
+        },
+    ];
+
+    let sarif = to_sarif(&diagnostics, &desc);
+    assert_eq!(sarif["version"], "2.1.0");
+    let run = &sarif["runs"][0];
+    let rules = run["tool"]["driver"]["rules"].as_array().unwrap();
+    assert_eq!(rules.len(), 2);
+    let [leak, vacuous] = [&run["results"][0], &run["results"][1]];
+    assert_eq!(leak["ruleId"], "no-leak/reach");
+    assert_eq!(leak["level"], "error");
+    assert_eq!(leak["properties"]["controllers"], json!(["main"]));
+    assert_eq!(leak["properties"]["code"], "L001");
+    assert_eq!(leak["properties"]["children"].as_array().unwrap().len(), 2);
+    let region = &leak["locations"][0]["physicalLocation"]["region"];
+    assert_eq!(region["startLine"], 3);
+    assert_eq!(region["endColumn"], 12);
+    assert_eq!(leak["relatedLocations"].as_array().unwrap().len(), 1);
+    assert_eq!(vacuous["ruleId"], DEFAULT_RULE);
+    assert_eq!(vacuous["level"], "warning");
+    assert!(vacuous.get("locations").is_none());
+}
@@ -1,63 +1,232 @@
-use paralegal_spdg::{Node as SPDGNode, SPDGImpl, SPDG};
+use paralegal_spdg::{traverse::EdgeSelection, Node as SPDGNode, SPDGImpl, SPDG};
 
 use bitvec::vec::BitVec;
+use petgraph::visit::{EdgeRef, IntoEdgeReferences};
 
-use std::fmt;
-
-#[cfg(test)]
-use paralegal_spdg::traverse::EdgeSelection;
+use std::{fmt, sync::OnceLock};
 
 /// Precomputed indices for common queries of a PDG.
+///
+/// Each [`ReachabilityIndex`] is built on first use by
+/// [`Self::reachability`], so policies that only query data flow do not pay
+/// for the control flow indices.
+#[derive(Default)]
 pub struct CtrlFlowsTo {
-    /// The densely packed transitive closure of the
-    /// [`paralegal_spdg::EdgeKind::Data`] edges.
-    pub data_flows_to: Vec<BitVec>,
+    data: OnceLock<ReachabilityIndex>,
+    control: OnceLock<ReachabilityIndex>,
+    both: OnceLock<ReachabilityIndex>,
 }
 
 impl CtrlFlowsTo {
-    /// Constructs the transitive closure from a [`SPDG`].
+    /// Constructs the reachability indices for all edge selections from a
+    /// [`SPDG`].
     pub fn build(spdg: &SPDG) -> Self {
-        use petgraph::prelude::*;
-        let domain_size = spdg.graph.node_count();
-        // Connect each function-argument sink to its corresponding function sources.
-        // This lets us compute the transitive closure by following through the `sink_to_source` map.
-
-        fn iterate(flows_to: &mut [BitVec]) {
-            let mut changed = true;
-            // Safety: We never resize/reallocate any of the vectors, so
-            // mutating and reading them simultaneously is fine.
-            let unsafe_flow_ref: &'_ [BitVec] =
-                unsafe { std::mem::transmute::<&&mut [BitVec], &&[BitVec]>(&flows_to) };
-            while changed {
-                changed = false;
-                for src_idx in 0..flows_to.len() {
-                    for intermediate_idx in unsafe_flow_ref[src_idx].iter_ones() {
-                        for sink_idx in unsafe_flow_ref[intermediate_idx].iter_ones() {
-                            changed |= !flows_to[src_idx][sink_idx];
-                            flows_to[src_idx].set(sink_idx, true);
-                        }
-                    }
+        let flows_to = Self::default();
+        for edges in [
+            EdgeSelection::Data,
+            EdgeSelection::Control,
+            EdgeSelection::Both,
+        ] {
+            flows_to.reachability(spdg, edges);
+        }
+        flows_to
+    }
+
+    /// The index over the edges in `edges`, built on the first call. `spdg`
+    /// must be the same graph on every call.
+    pub fn reachability(&self, spdg: &SPDG, edges: EdgeSelection) -> &ReachabilityIndex {
+        let cell = match edges {
+            EdgeSelection::Data => &self.data,
+            EdgeSelection::Control => &self.control,
+            EdgeSelection::Both => &self.both,
+        };
+        cell.get_or_init(|| ReachabilityIndex::build(spdg, edges))
+    }
+}
+
+/// Graphs whose condensation has at most this many components get a
+/// precomputed transitive closure, which needs one bit for each pair of
+/// components.
+const DENSE_COMPONENT_LIMIT: usize = 1 << 13;
+
+/// Answers whether there is a non-empty path between two nodes of a
+/// controller that uses only edges of one [`EdgeSelection`].
+///
+/// The index is built on the condensation of the graph: every strongly
+/// connected component becomes one vertex of a DAG, numbered in reverse
+/// topological order, so a component only reaches components with a smaller
+/// number. For small condensations the transitive closure over components is
+/// precomputed. Otherwise queries search the condensation and skip every
+/// component numbered lower than the target, since it cannot reach it.
+pub struct ReachabilityIndex {
+    /// The component of each node
+    component: Vec<u32>,
+    /// The nodes of each component
+    members: Vec<Vec<SPDGNode>>,
+    /// The components directly reachable from each component, excluding
+    /// itself
+    successors: Vec<Vec<u32>>,
+    /// The inverse of `successors`
+    predecessors: Vec<Vec<u32>>,
+    /// Components with a cycle, i.e. whose nodes reach themselves
+    cyclic: BitVec,
+    /// For each component, the components reachable via at least one edge
+    closure: Option<Vec<BitVec>>,
+}
+
+impl ReachabilityIndex {
+    /// Build the index, precomputing the closure if the graph is small enough.
+    pub fn build(spdg: &SPDG, edges: EdgeSelection) -> Self {
+        Self::build_with(spdg, edges, |components| {
+            components <= DENSE_COMPONENT_LIMIT
+        })
+    }
+
+    fn build_with(
+        spdg: &SPDG,
+        edges: EdgeSelection,
+        precompute: impl FnOnce(usize) -> bool,
+    ) -> Self {
+        let graph = edges.filter_graph(&spdg.graph);
+        // `kosaraju_scc` is iterative, which matters for the deep graphs of
+        // large controllers, and returns the components in reverse
+        // topological order.
+        let members = petgraph::algo::kosaraju_scc(&graph);
+        let mut component = vec![0; spdg.graph.node_count()];
+        for (idx, nodes) in members.iter().enumerate() {
+            for n in nodes {
+                component[n.index()] = idx as u32;
+            }
+        }
+        let mut successors = vec![vec![]; members.len()];
+        let mut cyclic = BitVec::repeat(false, members.len());
+        for (idx, nodes) in members.iter().enumerate() {
+            cyclic.set(idx, nodes.len() > 1);
+        }
+        for e in graph.edge_references() {
+            let (from, to) = (component[e.source().index()], component[e.target().index()]);
+            if from == to {
+                cyclic.set(from as usize, true);
+            } else {
+                successors[from as usize].push(to);
+            }
+        }
+        let mut predecessors = vec![vec![]; members.len()];
+        for (from, succ) in successors.iter_mut().enumerate() {
+            succ.sort_unstable();
+            succ.dedup();
+            for to in succ.iter() {
+                predecessors[*to as usize].push(from as u32);
+            }
+        }
+        let closure = precompute(members.len()).then(|| {
+            let mut closure: Vec<BitVec> = Vec::with_capacity(members.len());
+            for (idx, succ) in successors.iter().enumerate() {
+                let mut row = BitVec::repeat(false, members.len());
+                row.set(idx, cyclic[idx]);
+                for s in succ {
+                    row.set(*s as usize, true);
+                    row |= &closure[*s as usize];
                 }
+                closure.push(row);
             }
+            closure
+        });
+        Self {
+            component,
+            members,
+            successors,
+            predecessors,
+            cyclic,
+            closure,
         }
+    }
 
-        let mut data_flows_to = vec![BitVec::repeat(false, domain_size); domain_size];
-
-        // Initialize the `flows_to` relation with the data provided by `Ctrl::data_flow`.
-        for edge in spdg
-            .graph
-            .edge_references()
-            .filter(|e| e.weight().is_data())
-        {
-            data_flows_to[edge.source().index()].set(edge.target().index(), true);
+    /// Whether there is a path of at least one edge from `src` to `sink`.
+    pub fn reaches(&self, src: SPDGNode, sink: SPDGNode) -> bool {
+        let from = self.component[src.index()];
+        let to = self.component[sink.index()];
+        if from == to {
+            return self.cyclic[from as usize];
+        }
+        if to > from {
+            return false;
+        }
+        if let Some(closure) = &self.closure {
+            return closure[from as usize][to as usize];
         }
+        let mut seen = BitVec::<usize>::repeat(false, self.members.len());
+        let mut stack = vec![from];
+        while let Some(c) = stack.pop() {
+            for &s in &self.successors[c as usize] {
+                if s == to {
+                    return true;
+                }
+                if s > to && !seen.replace(s as usize, true) {
+                    stack.push(s);
+                }
+            }
+        }
+        false
+    }
 
-        iterate(&mut data_flows_to);
+    /// All nodes reachable from `src` via at least one edge. Contains `src`
+    /// only if it lies on a cycle.
+    pub fn reachable_from(&self, src: SPDGNode) -> impl Iterator<Item = SPDGNode> + '_ {
+        let from = self.component[src.index()] as usize;
+        let components = if let Some(closure) = &self.closure {
+            closure[from].iter_ones().collect::<Vec<_>>()
+        } else {
+            self.search(from, &self.successors)
+        };
+        self.nodes_of(components)
+    }
 
-        CtrlFlowsTo {
-            //callsites_to_callargs,
-            data_flows_to,
+    /// All nodes that reach `sink` via at least one edge. Contains `sink`
+    /// only if it lies on a cycle.
+    pub fn reaching(&self, sink: SPDGNode) -> impl Iterator<Item = SPDGNode> + '_ {
+        let to = self.component[sink.index()] as usize;
+        let components = if let Some(closure) = &self.closure {
+            // Only components numbered at least `to` can reach it
+            (to..self.members.len())
+                .filter(|c| closure[*c][to])
+                .collect::<Vec<_>>()
+        } else {
+            self.search(to, &self.predecessors)
+        };
+        self.nodes_of(components)
+    }
+
+    /// The components reachable from `start` via at least one edge in
+    /// `edges`, which is either `successors` or `predecessors`.
+    fn search(&self, start: usize, edges: &[Vec<u32>]) -> Vec<usize> {
+        let mut seen = BitVec::<usize>::repeat(false, self.members.len());
+        seen.set(start, self.cyclic[start]);
+        let mut stack = vec![start];
+        while let Some(c) = stack.pop() {
+            for &next in &edges[c] {
+                if !seen.replace(next as usize, true) {
+                    stack.push(next as usize);
+                }
+            }
         }
+        seen.iter_ones().collect()
+    }
+
+    fn nodes_of(&self, components: Vec<usize>) -> impl Iterator<Item = SPDGNode> + '_ {
+        components
+            .into_iter()
+            .flat_map(|c| self.members[c].iter().copied())
+    }
+}
+
+impl fmt::Debug for ReachabilityIndex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReachabilityIndex")
+            .field("nodes", &self.component.len())
+            .field("components", &self.members.len())
+            .field("precomputed", &self.closure.is_some())
+            .finish()
     }
 }
 
@@ -95,7 +264,9 @@ impl<'a> Iterator for DataAndControlInfluencees<'a> {
 impl fmt::Debug for CtrlFlowsTo {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("CtrlFlowsTo")
-            .field("data_flows_to", &self.data_flows_to)
+            .field("data", &self.data)
+            .field("control", &self.control)
+            .field("both", &self.both)
             .finish()
     }
 }
@@ -159,3 +330,52 @@ fn test_flows_to() {
     assert!(ctx.flows_to(src_b, &sink, EdgeSelection::Both));
     assert!(ctx.flows_to(src_b, &sink, EdgeSelection::Data));
 }
+
+#[test]
+fn test_reachability_strategies_agree() {
+    let ctx = crate::test_utils::test_ctx();
+    for (_, spdg) in ctx.all_controllers() {
+        for edges in [
+            EdgeSelection::Data,
+            EdgeSelection::Control,
+            EdgeSelection::Both,
+        ] {
+            let dense = ReachabilityIndex::build_with(spdg, edges, |_| true);
+            let sparse = ReachabilityIndex::build_with(spdg, edges, |_| false);
+            for src in spdg.graph.node_indices() {
+                let mut reached = dense.reachable_from(src).collect::<Vec<_>>();
+                reached.sort();
+                let mut sparse_reached = sparse.reachable_from(src).collect::<Vec<_>>();
+                sparse_reached.sort();
+                assert_eq!(reached, sparse_reached);
+                for sink in spdg.graph.node_indices() {
+                    assert_eq!(
+                        dense.reaching(sink).any(|n| n == src),
+                        sparse.reaching(sink).any(|n| n == src)
+                    );
+                    let expected = spdg.graph.neighbors(src).any(|n| {
+                        spdg.graph
+                            .edges_connecting(src, n)
+                            .any(|e| edges.conforms(e.weight().kind))
+                            && paralegal_spdg::traverse::generic_flows_to([n], edges, spdg, [sink])
+                    });
+                    assert_eq!(dense.reaches(src, sink), expected);
+                    assert_eq!(sparse.reaches(src, sink), expected);
+                    assert_eq!(reached.contains(&sink), expected);
+                    assert_eq!(dense.reaching(sink).any(|n| n == src), expected);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_indices_are_built_lazily() {
+    let ctx = crate::test_utils::test_ctx();
+    let (_, spdg) = ctx.all_controllers().next().unwrap();
+    let flows_to = CtrlFlowsTo::default();
+    flows_to.reachability(spdg, EdgeSelection::Data);
+    assert!(flows_to.data.get().is_some());
+    assert!(flows_to.control.get().is_none());
+    assert!(flows_to.both.get().is_none());
+}
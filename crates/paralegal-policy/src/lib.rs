@@ -39,6 +39,10 @@
 //!    the closure returns it automatically invokes
 //!    [`Context::emit_diagnostics`].
 //!
+//!    [`.check()`](GraphLocation::check) runs the closure the same way, but
+//!    returns the diagnostics as a [`PolicyReport`] instead of printing them
+//!    and exiting, for embedding policies in a test harness or a server.
+//!
 //! For information about how to specify policies see the [`Context`] struct.
 //!
 //! *Note:* This crate defines both the interface to the property checkers (via
@@ -51,13 +55,14 @@
 
 extern crate core;
 
-use anyhow::{ensure, Result};
+use anyhow::{bail, ensure, Result};
+use diagnostics::baseline::Baseline;
+use itertools::Itertools;
 pub use paralegal_spdg;
 pub use paralegal_spdg::{
     traverse::EdgeSelection, GlobalNode, IntoIterGlobalNodes, ProgramDescription,
 };
 use std::{
-    fs::File,
     path::{Path, PathBuf},
     process::Command,
     sync::Arc,
@@ -72,9 +77,10 @@ mod test_utils;
 
 pub use self::{
     context::*,
-    diagnostics::{CombinatorContext, Diagnostics, PolicyContext},
+    diagnostics::{CombinatorContext, Diagnostics, DiagnosticsFormat, PolicyContext, PolicyReport},
     flows_to::CtrlFlowsTo,
     flows_to::DataAndControlInfluencees,
+    flows_to::ReachabilityIndex,
 };
 
 /// Configuration of the `cargo paralegal-flow` command.
@@ -125,6 +131,30 @@ impl SPDGGenCommand {
         self
     }
 
+    /// Pass `--binary-output` to the command, so that the graph is written in
+    /// the compact binary encoding. [`GraphLocation`] detects the encoding
+    /// automatically.
+    pub fn binary_output(&mut self) -> &mut Self {
+        self.0.arg("--binary-output");
+        self
+    }
+
+    /// Pass `--sharded-output` to the command, so that the graph is written as
+    /// a directory with one file per controller. Combine with
+    /// [`GraphLocation::lazy`] to only read the controllers a policy uses.
+    pub fn sharded_output(&mut self) -> &mut Self {
+        self.0.arg("--sharded-output");
+        self
+    }
+
+    /// Pass `--embed-sources files` to the command, so that the graph carries
+    /// the source code its spans point into and diagnostics can be rendered
+    /// on machines without the source tree.
+    pub fn embed_sources(&mut self) -> &mut Self {
+        self.0.args(["--embed-sources", "files"]);
+        self
+    }
+
     /// Consume the created command and execute it in the specified directory.
     ///
     /// Errors if executing the underlying [`Command`] fails or if it does not
@@ -144,51 +174,207 @@ impl SPDGGenCommand {
 /// Can be created programmatically and automatically by running
 /// [`SPDGGenCommand::run`] or you can create one manually if you can `cargo
 /// paralegal-flow` by hand with [`Self::custom`].
-pub struct GraphLocation(PathBuf);
+///
+/// Graphs of several crates can be checked together by combining their
+/// locations with [`Self::merged`].
+///
+/// A location may also be a directory with a sharded graph (see
+/// [`paralegal_spdg::artifact::sharded`]). Such graphs are read completely by
+/// default, or on demand with [`Self::lazy`].
+pub struct GraphLocation {
+    paths: Vec<PathBuf>,
+    config: ContextConfig,
+    lazy: bool,
+    format: DiagnosticsFormat,
+    baseline: Option<BaselineMode>,
+}
+
+/// What [`GraphLocation::with_context`] does with a baseline file.
+enum BaselineMode {
+    /// Suppress the violations in the file
+    Apply(PathBuf),
+    /// Replace the file with the violations of this run
+    Write(PathBuf),
+}
 
 impl GraphLocation {
     /// Use the default graph file name in the specified directory.
     pub fn std(dir: impl AsRef<Path>) -> Self {
-        Self(dir.as_ref().join(paralegal_spdg::FLOW_GRAPH_OUT_NAME))
+        Self::custom(dir.as_ref().join(paralegal_spdg::FLOW_GRAPH_OUT_NAME))
     }
 
     /// Use a completely custom path (directory and file name).
     pub fn custom(path: PathBuf) -> Self {
-        Self(path)
+        Self {
+            paths: vec![path],
+            config: Default::default(),
+            lazy: false,
+            format: Default::default(),
+            baseline: None,
+        }
+    }
+
+    /// Combine multiple graph files, for instance one for each crate in a
+    /// workspace, into one location. The graphs are merged with
+    /// [`ProgramDescription::merge`] when the context is built.
+    pub fn merged(locations: impl IntoIterator<Item = GraphLocation>) -> Self {
+        Self {
+            paths: locations.into_iter().flat_map(|l| l.paths).collect(),
+            config: Default::default(),
+            lazy: false,
+            format: Default::default(),
+            baseline: None,
+        }
+    }
+
+    /// Use these options when building the [`Context`].
+    pub fn with_config(mut self, config: ContextConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Check the graph with [`ProgramDescription::validate`] when building the
+    /// [`Context`] and record broken invariants as errors.
+    pub fn validated(self) -> Self {
+        self.with_config(ContextConfig { validate: true })
+    }
+
+    /// Read controller graphs only when a policy first accesses them, see
+    /// [`Context::new_lazy`]. Requires that this location is a single sharded
+    /// graph.
+    pub fn lazy(mut self) -> Self {
+        self.lazy = true;
+        self
+    }
+
+    /// Render the diagnostics emitted by [`Self::with_context`] in `format`,
+    /// e.g. as SARIF for a code scanning dashboard.
+    pub fn with_diagnostics_format(mut self, format: DiagnosticsFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Only fail on violations that are not in the baseline file at `path`,
+    /// see [`Context::apply_baseline`].
+    pub fn with_baseline(mut self, path: impl Into<PathBuf>) -> Self {
+        self.baseline = Some(BaselineMode::Apply(path.into()));
+        self
+    }
+
+    /// Record the violations found by [`Self::with_context`] as the new
+    /// baseline at `path` (see [`Context::baseline`]) instead of failing on
+    /// them. Use [`Self::with_baseline`] in later runs.
+    pub fn write_baseline(mut self, path: impl Into<PathBuf>) -> Self {
+        self.baseline = Some(BaselineMode::Write(path.into()));
+        self
     }
 
     /// Builds a context, then runs the property.
     ///
-    /// Emits any recorded diagnostic messages to stdout and aborts the program
-    /// if they were severe enough.
+    /// Emits any recorded diagnostic messages to stdout, in the format chosen
+    /// with [`Self::with_diagnostics_format`], and aborts the program if they
+    /// were severe enough. Violations in the baseline (see
+    /// [`Self::with_baseline`]) are not emitted.
+    ///
+    /// Use [`Self::check`] to handle the results yourself instead.
     pub fn with_context<A>(&self, prop: impl FnOnce(Arc<Context>) -> Result<A>) -> Result<A> {
+        let (result, report) = self.check(prop)?;
+        report.render(std::io::stdout(), self.format)?;
+        if !report.passed() {
+            std::process::exit(1)
+        }
+        Ok(result)
+    }
+
+    /// Builds a context, runs the property and returns its result together
+    /// with a [`PolicyReport`] of the recorded diagnostics. Violations in the
+    /// baseline (see [`Self::with_baseline`]) are not part of the report.
+    ///
+    /// Unlike [`Self::with_context`] this neither prints the diagnostics nor
+    /// exits the process if the policy fails.
+    pub fn check<A>(
+        &self,
+        prop: impl FnOnce(Arc<Context>) -> Result<A>,
+    ) -> Result<(A, PolicyReport)> {
         let ctx = Arc::new(self.build_context()?);
         assert_warning!(
             ctx,
-            !ctx.desc().controllers.is_empty(),
+            !ctx.summaries().is_empty(),
             "No controllers found. Your policy is likely to be vacuous."
         );
         let result = prop(ctx.clone())?;
-        ctx.emit_diagnostics_may_exit(std::io::stdout())?;
-        Ok(result)
+        match &self.baseline {
+            Some(BaselineMode::Apply(path)) => {
+                let baseline = anyhow::Context::with_context(Baseline::read(path), || {
+                    format!("Reading baseline from {}", path.display())
+                })?;
+                ctx.apply_baseline(&baseline);
+            }
+            Some(BaselineMode::Write(path)) => {
+                let baseline = ctx.baseline();
+                anyhow::Context::with_context(baseline.write(path), || {
+                    format!("Writing baseline to {}", path.display())
+                })?;
+                ctx.apply_baseline(&baseline);
+            }
+            None => (),
+        }
+        Ok((result, ctx.report()))
     }
 
     /// Read and parse this graph file, returning a [`Context`] suitable for
     /// property enforcement.
     ///
+    /// Both the JSON and the binary encoding are accepted, see
+    /// [`paralegal_spdg::artifact`]. Fails if the graph was written by an
+    /// extractor with an incompatible schema version, or if this location
+    /// combines multiple graphs that conflict with one another.
+    ///
     /// Prefer using [`Self::with_context`] which takes care of emitting any
     /// diagnostic messages after the property is done.
     pub fn build_context(&self) -> Result<Context> {
+        use paralegal_spdg::artifact::{read_from_file, sharded};
         let _ = simple_logger::init_with_env();
 
-        let desc = {
-            let mut f = File::open(&self.0)?;
-            anyhow::Context::with_context(
-                serde_json::from_reader::<_, ProgramDescription>(&mut f),
-                || format!("Reading SPDG (JSON) from {}", self.0.display()),
-            )?
-        };
-        Ok(Context::new(desc))
+        if self.lazy {
+            let [path] = self.paths.as_slice() else {
+                bail!(
+                    "Lazy loading requires exactly one graph, found {}",
+                    self.paths.len()
+                );
+            };
+            ensure!(
+                sharded::is_sharded(path),
+                "Lazy loading requires a sharded graph, {} is not one",
+                path.display()
+            );
+            let (index, reader) =
+                anyhow::Context::with_context(sharded::ShardReader::open(path), || {
+                    format!("Reading SPDG index from {}", path.display())
+                })?;
+            return Ok(Context::new_lazy(index, reader, self.config.clone()));
+        }
+
+        let mut descs = self.paths.iter().map(|path| {
+            let desc = if sharded::is_sharded(path) {
+                sharded::read_sharded(path)
+            } else {
+                read_from_file(path)
+            };
+            anyhow::Context::with_context(desc, || format!("Reading SPDG from {}", path.display()))
+        });
+        let mut desc = descs
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("No graph files to read"))??;
+        for other in descs {
+            let conflicts = desc.merge(other?);
+            ensure!(
+                conflicts.is_empty(),
+                "Merged graphs are in conflict:\n  {}",
+                conflicts.iter().join("\n  ")
+            );
+        }
+        Ok(Context::new_with_config(desc, self.config.clone()))
     }
 }
 
@@ -207,3 +393,15 @@ macro_rules! loc {
         concat!(file!(), ':', line!(), ':', column!(), ' ', $($t)+)
     };
 }
+
+#[test]
+fn merging_a_graph_with_itself_conflicts() {
+    // Ensures the graph has been generated
+    test_utils::test_ctx();
+    let merged = GraphLocation::merged([
+        GraphLocation::std("tests/test-crate"),
+        GraphLocation::std("tests/test-crate"),
+    ]);
+    let err = merged.build_context().err().unwrap();
+    assert!(err.to_string().contains("in conflict"));
+}
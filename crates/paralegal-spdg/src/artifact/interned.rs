@@ -0,0 +1,349 @@
+//! The layout of [`ProgramDescription`] in artifacts since schema version
+//! [`INTERNED_SCHEMA_VERSION`](super::INTERNED_SCHEMA_VERSION).
+//!
+//! Most nodes, edges and instructions share their call string and span with
+//! many others, and node descriptions repeat as well. In this layout each
+//! distinct call string, span and description is stored once in a table and
+//! referenced by its index. The in-memory types are not affected, the
+//! conversion happens in [`write`](super::write) and [`read`](super::read).
+
+use std::hash::Hash;
+
+use flowistry_pdg::rustc_portable::DefId;
+use petgraph::visit::EdgeRef;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    utils::{serde_map_via_vec, sort_by_serialized_key},
+    CallString, DefInfo, DefKind, EdgeInfo, EdgeKind, EmbeddedSource, Endpoint, GlobalLocation,
+    HashMap, Identifier, InstructionInfo, InstructionKind, Node, NodeInfo, NodeKind,
+    ProgramDescription, SourceFile, Span, StableNodeId, TypeInfoMap, Types, SPDG,
+};
+
+/// Index into one of the tables of [`InternedDescription`]
+type Idx = u32;
+
+/// Assigns each distinct value an index in order of first occurrence.
+struct Table<T> {
+    index: HashMap<T, Idx>,
+    entries: Vec<T>,
+}
+
+impl<T> Default for Table<T> {
+    fn default() -> Self {
+        Self {
+            index: Default::default(),
+            entries: vec![],
+        }
+    }
+}
+
+impl<T: Hash + Eq + Clone> Table<T> {
+    fn intern(&mut self, value: &T) -> Idx {
+        if let Some(idx) = self.index.get(value) {
+            return *idx;
+        }
+        let idx = self.entries.len() as Idx;
+        self.entries.push(value.clone());
+        self.index.insert(value.clone(), idx);
+        idx
+    }
+}
+
+fn lookup<T: Clone>(table: &[T], idx: Idx, what: &str) -> Result<T, String> {
+    table.get(idx as usize).cloned().ok_or_else(|| {
+        format!(
+            "{what} index {idx} is out of bounds, the table has {} entries",
+            table.len()
+        )
+    })
+}
+
+#[derive(Serialize, Deserialize)]
+struct InternedNode {
+    at: Idx,
+    description: Idx,
+    kind: NodeKind,
+    span: Idx,
+    stable_id: StableNodeId,
+}
+
+#[derive(Serialize, Deserialize)]
+struct InternedEdge {
+    kind: EdgeKind,
+    at: Idx,
+}
+
+#[derive(Serialize, Deserialize)]
+struct InternedSPDG {
+    name: Identifier,
+    graph: petgraph::Graph<InternedNode, InternedEdge>,
+    #[serde(serialize_with = "crate::utils::serialize_sorted_map")]
+    markers: HashMap<Node, Vec<Identifier>>,
+    arguments: Vec<Node>,
+    return_: Option<Node>,
+    #[serde(serialize_with = "crate::utils::serialize_sorted_map")]
+    type_assigns: HashMap<Node, Types>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct InternedInstruction {
+    kind: InstructionKind,
+    span: Idx,
+}
+
+#[derive(Serialize, Deserialize)]
+struct InternedDefInfo {
+    name: Identifier,
+    path: Vec<Identifier>,
+    kind: DefKind,
+    src_info: Idx,
+}
+
+/// A [`ProgramDescription`] with call strings, spans and node descriptions
+/// replaced by indices into shared tables.
+#[derive(Serialize, Deserialize)]
+pub(super) struct InternedDescription {
+    call_strings: Vec<CallString>,
+    spans: Vec<Span>,
+    descriptions: Vec<String>,
+    #[cfg_attr(feature = "rustc", serde(with = "crate::ser_localdefid_map"))]
+    #[cfg_attr(not(feature = "rustc"), serde(with = "serde_map_via_vec"))]
+    controllers: HashMap<Endpoint, InternedSPDG>,
+    #[cfg_attr(not(feature = "rustc"), serde(with = "serde_map_via_vec"))]
+    #[cfg_attr(feature = "rustc", serde(with = "crate::ser_defid_map"))]
+    type_info: TypeInfoMap,
+    #[serde(with = "serde_map_via_vec")]
+    instruction_info: HashMap<GlobalLocation, InternedInstruction>,
+    #[cfg_attr(not(feature = "rustc"), serde(with = "serde_map_via_vec"))]
+    #[cfg_attr(feature = "rustc", serde(with = "crate::ser_defid_map"))]
+    def_info: HashMap<DefId, InternedDefInfo>,
+    #[serde(with = "serde_map_via_vec")]
+    sources: HashMap<SourceFile, EmbeddedSource>,
+}
+
+impl InternedDescription {
+    /// Build the tables. Entries are interned in a deterministic order, so a
+    /// canonical description (see [`ProgramDescription::canonicalize`])
+    /// produces identical tables every time.
+    pub(super) fn new(desc: &ProgramDescription) -> Result<Self, serde_json::Error> {
+        let mut call_strings = Table::default();
+        let mut spans = Table::default();
+        let mut descriptions = Table::default();
+
+        let controllers = crate::export::sorted_controllers(desc)
+            .into_iter()
+            .map(|(id, ctrl)| {
+                let mut graph = petgraph::Graph::with_capacity(
+                    ctrl.graph.node_count(),
+                    ctrl.graph.edge_count(),
+                );
+                for info in ctrl.graph.node_weights() {
+                    graph.add_node(InternedNode {
+                        at: call_strings.intern(&info.at),
+                        description: descriptions.intern(&info.description),
+                        kind: info.kind,
+                        span: spans.intern(&info.span),
+                        stable_id: info.stable_id,
+                    });
+                }
+                for e in ctrl.graph.edge_references() {
+                    graph.add_edge(
+                        e.source(),
+                        e.target(),
+                        InternedEdge {
+                            kind: e.weight().kind,
+                            at: call_strings.intern(&e.weight().at),
+                        },
+                    );
+                }
+                let ctrl = InternedSPDG {
+                    name: ctrl.name,
+                    graph,
+                    markers: ctrl.markers.clone(),
+                    arguments: ctrl.arguments.clone(),
+                    return_: ctrl.return_,
+                    type_assigns: ctrl.type_assigns.clone(),
+                };
+                (id, ctrl)
+            })
+            .collect();
+
+        let mut instructions = desc.instruction_info.iter().collect::<Vec<_>>();
+        sort_by_serialized_key::<_, _, serde_json::Error>(&mut instructions)?;
+        let instruction_info = instructions
+            .into_iter()
+            .map(|(loc, info)| {
+                let info = InternedInstruction {
+                    kind: info.kind,
+                    span: spans.intern(&info.span),
+                };
+                (*loc, info)
+            })
+            .collect();
+
+        let mut defs = desc.def_info.iter().collect::<Vec<_>>();
+        defs.sort_by_key(|(id, _)| **id);
+        let def_info = defs
+            .into_iter()
+            .map(|(id, info)| {
+                let info = InternedDefInfo {
+                    name: info.name,
+                    path: info.path.clone(),
+                    kind: info.kind,
+                    src_info: spans.intern(&info.src_info),
+                };
+                (*id, info)
+            })
+            .collect();
+
+        Ok(Self {
+            call_strings: call_strings.entries,
+            spans: spans.entries,
+            descriptions: descriptions.entries,
+            controllers,
+            type_info: desc.type_info.clone(),
+            instruction_info,
+            def_info,
+            sources: desc.sources.clone(),
+        })
+    }
+
+    /// Resolve all indices. Fails if any of them is out of bounds.
+    pub(super) fn into_description(self) -> Result<ProgramDescription, String> {
+        let call_string = |idx| lookup(&self.call_strings, idx, "call string");
+        let span = |idx| lookup(&self.spans, idx, "span");
+        let description = |idx| lookup(&self.descriptions, idx, "description");
+
+        let mut controllers = HashMap::with_capacity(self.controllers.len());
+        for (id, ctrl) in self.controllers {
+            let mut graph =
+                petgraph::Graph::with_capacity(ctrl.graph.node_count(), ctrl.graph.edge_count());
+            for node in ctrl.graph.node_weights() {
+                graph.add_node(NodeInfo {
+                    at: call_string(node.at)?,
+                    description: description(node.description)?,
+                    kind: node.kind,
+                    span: span(node.span)?,
+                    stable_id: node.stable_id,
+                });
+            }
+            for e in ctrl.graph.edge_references() {
+                let info = EdgeInfo {
+                    kind: e.weight().kind,
+                    at: call_string(e.weight().at)?,
+                };
+                graph.add_edge(e.source(), e.target(), info);
+            }
+            let spdg = SPDG {
+                name: ctrl.name,
+                graph,
+                markers: ctrl.markers,
+                arguments: ctrl.arguments,
+                return_: ctrl.return_,
+                type_assigns: ctrl.type_assigns,
+            };
+            controllers.insert(id, spdg);
+        }
+
+        let instruction_info = self
+            .instruction_info
+            .into_iter()
+            .map(|(loc, info)| {
+                let info = InstructionInfo {
+                    kind: info.kind,
+                    span: span(info.span)?,
+                };
+                Ok((loc, info))
+            })
+            .collect::<Result<_, String>>()?;
+
+        let def_info = self
+            .def_info
+            .into_iter()
+            .map(|(id, info)| {
+                let info = DefInfo {
+                    name: info.name,
+                    path: info.path,
+                    kind: info.kind,
+                    src_info: span(info.src_info)?,
+                };
+                Ok((id, info))
+            })
+            .collect::<Result<_, String>>()?;
+
+        Ok(ProgramDescription {
+            controllers,
+            type_info: self.type_info,
+            instruction_info,
+            def_info,
+            sources: self.sources,
+        })
+    }
+}
+
+#[test]
+fn shared_call_strings_are_stored_once() {
+    use crate::{RichLocation, SourceFileInfo, SpanCoord};
+    let ctrl: Endpoint = serde_json::from_str(r#"{"local_def_index":{"private":0}}"#).unwrap();
+    let at = CallString::single(GlobalLocation {
+        function: ctrl,
+        location: RichLocation::Start,
+    });
+    let span = Span {
+        source_file: SourceFileInfo {
+            file_path: "src/main.rs".to_owned(),
+            abs_file_path: "/src/main.rs".into(),
+        }
+        .intern(),
+        start: SpanCoord { line: 1, col: 1 },
+        end: SpanCoord { line: 1, col: 10 },
+    };
+    let mut graph = crate::SPDGImpl::new();
+    let [a, b] = ["a", "b"].map(|description| {
+        graph.add_node(NodeInfo {
+            at,
+            description: description.to_owned(),
+            kind: NodeKind::Unspecified,
+            span: span.clone(),
+            stable_id: StableNodeId::from_parts([description]),
+        })
+    });
+    graph.add_edge(
+        a,
+        b,
+        EdgeInfo {
+            kind: EdgeKind::Data,
+            at,
+        },
+    );
+    let mut desc = ProgramDescription::default();
+    desc.instruction_info.insert(
+        at.leaf(),
+        InstructionInfo {
+            kind: InstructionKind::Start,
+            span,
+        },
+    );
+    desc.controllers.insert(
+        ctrl,
+        SPDG {
+            name: Identifier::new_intern("main"),
+            graph,
+            markers: Default::default(),
+            arguments: vec![a],
+            return_: Some(b),
+            type_assigns: Default::default(),
+        },
+    );
+
+    let interned = InternedDescription::new(&desc).unwrap();
+    assert_eq!(interned.call_strings.len(), 1);
+    assert_eq!(interned.spans.len(), 1);
+    assert_eq!(interned.descriptions.len(), 2);
+    let restored = interned.into_description().unwrap();
+    assert_eq!(
+        serde_json::to_value(restored).unwrap(),
+        serde_json::to_value(&desc).unwrap()
+    );
+}
@@ -0,0 +1,429 @@
+//! Reading and writing serialized [`ProgramDescription`]s.
+//!
+//! The extractor can emit the graph either as JSON (the default) or in a
+//! compact binary encoding based on [BARE](https://baremessages.org/). Binary
+//! artifacts start with [`BINARY_MAGIC`] so that [`read`] can tell the two
+//! formats apart without relying on the file name.
+//!
+//! Every artifact carries an [`ArtifactHeader`] recording the schema version
+//! and the commit of the extractor that wrote it. In the binary encoding the
+//! header directly follows the magic bytes, in JSON the top level object is
+//! `{"header": ..., "program": ...}`. Artifacts from before the header was
+//! introduced are treated as schema version `0`.
+//!
+//! When reading, JSON artifacts with an older but known schema version are
+//! upgraded to the current one, newer or unknown versions are rejected with an
+//! [`IncompatibleSchema`] error. The binary encoding is not self-describing, so
+//! binary artifacts must match the current version exactly.
+//!
+//! Since [`INTERNED_SCHEMA_VERSION`] the program is not stored with the layout
+//! of [`ProgramDescription`]'s own [`Serialize`] implementation, but with call
+//! strings, spans and node descriptions stored once in shared tables, which
+//! makes artifacts several times smaller.
+//!
+//! Very large programs can also be written as a directory with one artifact
+//! per controller, see [`sharded`].
+
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+use serde::{
+    de::{
+        value::MapAccessDeserializer, DeserializeSeed, Error as _, IgnoredAny, IntoDeserializer,
+        MapAccess, Visitor,
+    },
+    Deserialize, Serialize,
+};
+
+use crate::{ProgramDescription, StableNodeId};
+
+mod interned;
+pub mod sharded;
+
+use interned::InternedDescription;
+
+/// The first bytes of every binary encoded artifact.
+///
+/// JSON artifacts always start with `{` (possibly preceded by whitespace), so
+/// this can never be confused with a JSON file.
+pub const BINARY_MAGIC: &[u8; 8] = b"PLGLSPDG";
+
+/// The version of the serialized layout of [`ProgramDescription`] this build
+/// writes.
+///
+/// Must be incremented whenever a change to any of the serialized types makes
+/// older artifacts unreadable, and a migration from the previous version
+/// should be added to [`read`].
+///
+/// History:
+///
+/// 0. No header
+/// 1. Header added
+/// 2. [`NodeInfo::stable_id`](crate::NodeInfo::stable_id) added
+/// 3. [`ProgramDescription::sources`] added
+/// 4. Call strings, spans and descriptions interned, see
+///    [`INTERNED_SCHEMA_VERSION`]
+pub const CURRENT_SCHEMA_VERSION: u32 = 4;
+
+/// The first schema version that stores the program in the interned layout.
+pub const INTERNED_SCHEMA_VERSION: u32 = 4;
+
+/// The oldest schema version [`read`] is able to upgrade from JSON.
+pub const OLDEST_SUPPORTED_SCHEMA_VERSION: u32 = 0;
+
+/// The encodings a [`ProgramDescription`] can be stored in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default, strum::EnumIs, strum::AsRefStr)]
+pub enum ArtifactFormat {
+    /// Human readable, produced with `serde_json`
+    #[default]
+    Json,
+    /// Compact encoding, produced with `serde_bare` and prefixed with
+    /// [`BINARY_MAGIC`]
+    Binary,
+}
+
+impl ArtifactFormat {
+    /// The oldest schema version that can be read in this format.
+    pub fn oldest_supported_schema_version(self) -> u32 {
+        match self {
+            ArtifactFormat::Json => OLDEST_SUPPORTED_SCHEMA_VERSION,
+            ArtifactFormat::Binary => CURRENT_SCHEMA_VERSION,
+        }
+    }
+
+    /// Determine the format from the first bytes of an artifact.
+    pub fn detect(prefix: &[u8]) -> Self {
+        if prefix.starts_with(BINARY_MAGIC) {
+            ArtifactFormat::Binary
+        } else {
+            ArtifactFormat::Json
+        }
+    }
+}
+
+/// Metadata stored in front of the [`ProgramDescription`] in an artifact.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArtifactHeader {
+    /// Version of the serialized layout, see [`CURRENT_SCHEMA_VERSION`].
+    pub schema_version: u32,
+    /// Git commit of the `paralegal-flow` build that wrote the artifact.
+    pub extractor_commit: String,
+}
+
+impl ArtifactHeader {
+    /// A header for an artifact in the current schema.
+    pub fn new(extractor_commit: impl Into<String>) -> Self {
+        Self {
+            schema_version: CURRENT_SCHEMA_VERSION,
+            extractor_commit: extractor_commit.into(),
+        }
+    }
+
+    /// The implied header of artifacts that were written before headers were
+    /// introduced.
+    fn legacy() -> Self {
+        Self {
+            schema_version: 0,
+            extractor_commit: "unknown".to_owned(),
+        }
+    }
+
+    /// Ensure that this build can read (and if necessary upgrade) an artifact
+    /// in `format` with this header.
+    pub fn check_compatible(&self, format: ArtifactFormat) -> Result<(), IncompatibleSchema> {
+        if (format.oldest_supported_schema_version()..=CURRENT_SCHEMA_VERSION)
+            .contains(&self.schema_version)
+        {
+            Ok(())
+        } else {
+            Err(IncompatibleSchema {
+                header: self.clone(),
+                format,
+            })
+        }
+    }
+}
+
+/// The artifact was written with a schema version this build does not know
+/// how to read.
+#[derive(Debug, Clone)]
+pub struct IncompatibleSchema {
+    /// The header found in the artifact
+    pub header: ArtifactHeader,
+    /// The encoding of the artifact
+    pub format: ArtifactFormat,
+}
+
+impl std::fmt::Display for IncompatibleSchema {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "the SPDG artifact has schema version {} (written by paralegal-flow at commit {}), \
+            but this build can only read {} artifacts with versions {} to {CURRENT_SCHEMA_VERSION}. \
+            Re-run the extractor with a paralegal-flow built from the same revision as your policy.",
+            self.header.schema_version,
+            self.header.extractor_commit,
+            self.format.as_ref(),
+            self.format.oldest_supported_schema_version(),
+        )
+    }
+}
+
+impl std::error::Error for IncompatibleSchema {}
+
+/// Layout of JSON artifacts since schema version 1.
+#[derive(Serialize)]
+struct JsonEnvelope<'a> {
+    header: &'a ArtifactHeader,
+    program: &'a InternedDescription,
+}
+
+fn invalid_data(e: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+/// Serialize `desc` with the given header in the requested format.
+pub fn write(
+    header: &ArtifactHeader,
+    desc: &ProgramDescription,
+    format: ArtifactFormat,
+    mut out: impl Write,
+) -> io::Result<()> {
+    let program = &InternedDescription::new(desc)?;
+    match format {
+        ArtifactFormat::Json => serde_json::to_writer(&mut out, &JsonEnvelope { header, program })?,
+        ArtifactFormat::Binary => {
+            out.write_all(BINARY_MAGIC)?;
+            serde_bare::to_writer(&mut out, header).map_err(invalid_data)?;
+            serde_bare::to_writer(&mut out, program).map_err(invalid_data)?;
+        }
+    }
+    out.flush()
+}
+
+/// Deserialize a [`ProgramDescription`], detecting the format from the magic
+/// header and upgrading it to the current schema if necessary.
+pub fn read(input: impl Read) -> io::Result<ProgramDescription> {
+    read_with_header(input).map(|(_, desc)| desc)
+}
+
+/// Like [`read`] but also returns the header as it was found in the artifact.
+///
+/// Both formats are deserialized in a single pass while streaming from
+/// `input`. For JSON this relies on the header preceding the program, as
+/// [`write`] emits it.
+pub fn read_with_header(input: impl Read) -> io::Result<(ArtifactHeader, ProgramDescription)> {
+    let mut input = BufReader::new(input);
+    let mut prefix = Vec::with_capacity(BINARY_MAGIC.len());
+    (&mut input)
+        .take(BINARY_MAGIC.len() as u64)
+        .read_to_end(&mut prefix)?;
+    match ArtifactFormat::detect(&prefix) {
+        ArtifactFormat::Binary => read_binary(input),
+        ArtifactFormat::Json => read_json(prefix.as_slice().chain(input)),
+    }
+}
+
+fn read_binary(mut input: impl Read) -> io::Result<(ArtifactHeader, ProgramDescription)> {
+    let header: ArtifactHeader = serde_bare::from_reader(&mut input).map_err(invalid_data)?;
+    header
+        .check_compatible(ArtifactFormat::Binary)
+        .map_err(invalid_data)?;
+    let program: InternedDescription = serde_bare::from_reader(input).map_err(invalid_data)?;
+    let desc = program.into_description().map_err(invalid_data)?;
+    Ok((header, desc))
+}
+
+fn read_json(input: impl Read) -> io::Result<(ArtifactHeader, ProgramDescription)> {
+    let mut de = serde_json::Deserializer::from_reader(input);
+    let (header, mut desc) = serde::Deserializer::deserialize_map(&mut de, JsonArtifactVisitor)?;
+    de.end()?;
+    upgrade(header.schema_version, &mut desc);
+    Ok((header, desc))
+}
+
+/// Deserializes either a [`JsonEnvelope`], choosing the layout of the program
+/// by the version in the header, or a legacy artifact without a header.
+struct JsonArtifactVisitor;
+
+impl<'de> Visitor<'de> for JsonArtifactVisitor {
+    type Value = (ArtifactHeader, ProgramDescription);
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("an SPDG artifact")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let first = map.next_key::<String>()?;
+        if first.as_deref() != Some("header") {
+            // Legacy artifact without a header, the document is the program itself.
+            let desc =
+                ProgramDescription::deserialize(MapAccessDeserializer::new(Replay { first, map }))?;
+            return Ok((ArtifactHeader::legacy(), desc));
+        }
+        let header: ArtifactHeader = map.next_value()?;
+        header
+            .check_compatible(ArtifactFormat::Json)
+            .map_err(A::Error::custom)?;
+        if map.next_key::<String>()?.as_deref() != Some("program") {
+            return Err(A::Error::missing_field("program"));
+        }
+        let desc = if header.schema_version >= INTERNED_SCHEMA_VERSION {
+            map.next_value::<InternedDescription>()?
+                .into_description()
+                .map_err(A::Error::custom)?
+        } else {
+            map.next_value()?
+        };
+        while map.next_entry::<IgnoredAny, IgnoredAny>()?.is_some() {}
+        Ok((header, desc))
+    }
+}
+
+/// Yields a key that was already taken from `map` before the rest of it.
+struct Replay<A> {
+    first: Option<String>,
+    map: A,
+}
+
+impl<'de, A: MapAccess<'de>> MapAccess<'de> for Replay<A> {
+    type Error = A::Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Self::Error> {
+        match self.first.take() {
+            Some(key) => seed.deserialize(key.into_deserializer()).map(Some),
+            None => self.map.next_key_seed(seed),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.map.next_value_seed(seed)
+    }
+}
+
+/// Bring a description that was deserialized from an older schema version up
+/// to date. Fields added in later versions must be `#[serde(default)]` so that
+/// the old artifact parses and are then filled in here.
+fn upgrade(schema_version: u32, desc: &mut ProgramDescription) {
+    if schema_version < 2 {
+        assign_fallback_stable_ids(desc);
+    }
+}
+
+/// Artifacts before version 2 carry no [`StableNodeId`]s. Approximate them
+/// from the information that is available after the fact. These are
+/// deterministic for a given artifact, but less robust than the ones the
+/// extractor computes, because the call strings render with session
+/// dependent ids.
+fn assign_fallback_stable_ids(desc: &mut ProgramDescription) {
+    for ctrl in desc.controllers.values_mut() {
+        let name = ctrl.name;
+        for info in ctrl.graph.node_weights_mut() {
+            info.stable_id =
+                StableNodeId::from_parts([name.as_str(), &info.at.to_string(), &info.description]);
+        }
+        ctrl.disambiguate_stable_ids();
+    }
+}
+
+/// Write `desc` to the file at `path`, creating or truncating it.
+pub fn write_to_file(
+    header: &ArtifactHeader,
+    desc: &ProgramDescription,
+    format: ArtifactFormat,
+    path: impl AsRef<Path>,
+) -> io::Result<()> {
+    write(header, desc, format, BufWriter::new(File::create(path)?))
+}
+
+/// Read a [`ProgramDescription`] from the file at `path` in either format.
+pub fn read_from_file(path: impl AsRef<Path>) -> io::Result<ProgramDescription> {
+    read(File::open(path)?)
+}
+
+#[test]
+fn format_is_detected_on_read() {
+    let header = ArtifactHeader::new("test");
+    for format in [ArtifactFormat::Json, ArtifactFormat::Binary] {
+        let mut buf = vec![];
+        write(&header, &ProgramDescription::default(), format, &mut buf).unwrap();
+        assert_eq!(ArtifactFormat::detect(&buf), format);
+        let (read_header, desc) = read_with_header(buf.as_slice()).unwrap();
+        assert_eq!(read_header, header);
+        assert!(desc.controllers.is_empty());
+    }
+}
+
+#[test]
+fn populated_graph_round_trips() {
+    let desc = crate::test_utils::example_description();
+    let header = ArtifactHeader::new("test");
+    for format in [ArtifactFormat::Json, ArtifactFormat::Binary] {
+        let mut buf = vec![];
+        write(&header, &desc, format, &mut buf).unwrap();
+        let (read_header, read_desc) = read_with_header(buf.as_slice()).unwrap();
+        assert_eq!(read_header, header);
+        assert_eq!(
+            serde_json::to_value(&read_desc).unwrap(),
+            serde_json::to_value(&desc).unwrap(),
+            "{format:?}"
+        );
+    }
+}
+
+#[test]
+fn older_json_layouts_are_read() {
+    let desc = crate::test_utils::example_description();
+    let expected = serde_json::to_value(&desc).unwrap();
+
+    let legacy = serde_json::to_vec(&desc).unwrap();
+    let (header, read_desc) = read_with_header(legacy.as_slice()).unwrap();
+    assert_eq!(header, ArtifactHeader::legacy());
+    assert_eq!(read_desc.controllers.len(), 1);
+    assert_eq!(read_desc.sources, desc.sources);
+
+    let header = ArtifactHeader {
+        schema_version: INTERNED_SCHEMA_VERSION - 1,
+        extractor_commit: "abc123".to_owned(),
+    };
+    let envelope = serde_json::to_vec(&serde_json::json!({
+        "header": header,
+        "program": desc,
+    }))
+    .unwrap();
+    let (read_header, read_desc) = read_with_header(envelope.as_slice()).unwrap();
+    assert_eq!(read_header, header);
+    assert_eq!(serde_json::to_value(read_desc).unwrap(), expected);
+}
+
+#[test]
+fn schema_versions_are_checked() {
+    let legacy = serde_json::to_vec(&ProgramDescription::default()).unwrap();
+    let (header, _) = read_with_header(legacy.as_slice()).unwrap();
+    assert_eq!(header.schema_version, 0);
+
+    let future = ArtifactHeader {
+        schema_version: CURRENT_SCHEMA_VERSION + 1,
+        extractor_commit: "abc123".to_owned(),
+    };
+    let mut buf = vec![];
+    write(
+        &future,
+        &ProgramDescription::default(),
+        ArtifactFormat::Json,
+        &mut buf,
+    )
+    .unwrap();
+    let err = read(buf.as_slice()).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert!(err.to_string().contains("abc123"));
+}
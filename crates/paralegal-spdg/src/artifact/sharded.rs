@@ -0,0 +1,216 @@
+//! A sharded on-disk layout for large graphs.
+//!
+//! [`write_sharded`] creates a directory with an index and one file per
+//! controller. All files are regular artifacts (see [`write`](super::write)),
+//! so they share the encoding, header and version checks.
+//!
+//! - The index ([`INDEX_FILE_NAME`]) holds the type, instruction and def info
+//!   and a *stub* of every controller: the name, markers, arguments, return and
+//!   type assignments, but an empty graph.
+//! - [`SHARD_DIR_NAME`] holds one artifact per controller that contains only
+//!   that controller, with its complete graph.
+//!
+//! [`ShardReader::open`] reads only the index. The complete graphs are read
+//! with [`ShardReader::load`] when they are actually needed, so that checking
+//! a few controllers of a large application does not require deserializing
+//! all of them.
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use super::{read_from_file, write_to_file, ArtifactFormat, ArtifactHeader};
+use crate::{utils::raw_id, Endpoint, ProgramDescription, SPDG};
+
+/// Name of the index file in a sharded artifact directory.
+pub const INDEX_FILE_NAME: &str = "index";
+
+/// Name of the directory with the per-controller files.
+pub const SHARD_DIR_NAME: &str = "controllers";
+
+/// The name of the file that holds the graph of `id`, its def index.
+fn shard_file_name(id: Endpoint) -> String {
+    raw_id::local_def_index(id).to_string()
+}
+
+fn stub(ctrl: &SPDG) -> SPDG {
+    SPDG {
+        name: ctrl.name,
+        graph: Default::default(),
+        markers: ctrl.markers.clone(),
+        arguments: ctrl.arguments.clone(),
+        return_: ctrl.return_,
+        type_assigns: ctrl.type_assigns.clone(),
+    }
+}
+
+/// Whether `path` is a directory written by [`write_sharded`].
+pub fn is_sharded(path: impl AsRef<Path>) -> bool {
+    path.as_ref().join(INDEX_FILE_NAME).is_file()
+}
+
+/// Write `desc` as a sharded artifact into `dir`, creating it if necessary.
+pub fn write_sharded(
+    header: &ArtifactHeader,
+    desc: &ProgramDescription,
+    format: ArtifactFormat,
+    dir: impl AsRef<Path>,
+) -> io::Result<()> {
+    let dir = dir.as_ref();
+    if dir.exists() && !dir.is_dir() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} exists and is not a directory", dir.display()),
+        ));
+    }
+    let shard_dir = dir.join(SHARD_DIR_NAME);
+    std::fs::create_dir_all(&shard_dir)?;
+    for (&id, ctrl) in &desc.controllers {
+        let shard = ProgramDescription {
+            controllers: [(id, ctrl.clone())].into_iter().collect(),
+            ..Default::default()
+        };
+        write_to_file(header, &shard, format, shard_dir.join(shard_file_name(id)))?;
+    }
+    let index = ProgramDescription {
+        controllers: desc
+            .controllers
+            .iter()
+            .map(|(id, ctrl)| (*id, stub(ctrl)))
+            .collect(),
+        type_info: desc.type_info.clone(),
+        instruction_info: desc.instruction_info.clone(),
+        def_info: desc.def_info.clone(),
+        sources: desc.sources.clone(),
+    };
+    write_to_file(header, &index, format, dir.join(INDEX_FILE_NAME))
+}
+
+/// Read all shards in `dir` into one [`ProgramDescription`].
+pub fn read_sharded(dir: impl AsRef<Path>) -> io::Result<ProgramDescription> {
+    let (mut desc, reader) = ShardReader::open(dir)?;
+    for (id, ctrl) in desc.controllers.iter_mut() {
+        *ctrl = reader.load(*id)?;
+    }
+    Ok(desc)
+}
+
+/// Loads controller graphs from a directory written by [`write_sharded`].
+#[derive(Clone, Debug)]
+pub struct ShardReader {
+    dir: PathBuf,
+}
+
+impl ShardReader {
+    /// Read the index in `dir`. The returned description contains only stubs
+    /// of the controllers (see the [module level documentation](self)), the
+    /// complete graphs are read with [`Self::load`].
+    ///
+    /// Fails if the shard of any controller in the index is missing. The
+    /// shards are not read yet, so [`Self::load`] can still fail if one is
+    /// corrupt.
+    pub fn open(dir: impl AsRef<Path>) -> io::Result<(ProgramDescription, Self)> {
+        let reader = Self {
+            dir: dir.as_ref().to_path_buf(),
+        };
+        let desc: ProgramDescription = read_from_file(reader.dir.join(INDEX_FILE_NAME))?;
+        for id in desc.controllers.keys() {
+            let path = reader.shard_path(*id);
+            if !path.is_file() {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!(
+                        "the shard {} of controller {id:?} is missing",
+                        path.display()
+                    ),
+                ));
+            }
+        }
+        Ok((desc, reader))
+    }
+
+    fn shard_path(&self, id: Endpoint) -> PathBuf {
+        self.dir.join(SHARD_DIR_NAME).join(shard_file_name(id))
+    }
+
+    /// Read the complete graph of controller `id`. The shard only holds the
+    /// graph, check it against the index with
+    /// [`ProgramDescription::validate_controller`].
+    pub fn load(&self, id: Endpoint) -> io::Result<SPDG> {
+        let path = self.shard_path(id);
+        let mut shard = read_from_file(&path)?;
+        shard.controllers.remove(&id).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} does not contain controller {id:?}", path.display()),
+            )
+        })
+    }
+}
+
+#[test]
+fn shards_round_trip() {
+    let dir = std::env::temp_dir().join(format!("paralegal-shards-{}", std::process::id()));
+    let mut desc = ProgramDescription::default();
+    for (idx, name) in [(0, "a"), (1, "b")] {
+        let id: Endpoint =
+            serde_json::from_str(&format!(r#"{{"local_def_index":{{"private":{idx}}}}}"#)).unwrap();
+        let mut graph = crate::SPDGImpl::new();
+        let arg = graph.add_node(crate::NodeInfo {
+            at: crate::CallString::single(crate::GlobalLocation {
+                function: id,
+                location: crate::RichLocation::Start,
+            }),
+            description: name.to_owned(),
+            kind: crate::NodeKind::FormalParameter(0),
+            span: crate::Span {
+                source_file: crate::SourceFileInfo {
+                    file_path: "src/main.rs".to_owned(),
+                    abs_file_path: "/src/main.rs".into(),
+                }
+                .intern(),
+                start: crate::SpanCoord { line: 1, col: 1 },
+                end: crate::SpanCoord { line: 1, col: 2 },
+            },
+            stable_id: crate::StableNodeId::from_parts([name]),
+        });
+        desc.controllers.insert(
+            id,
+            SPDG {
+                name: crate::Identifier::new_intern(name),
+                graph,
+                markers: Default::default(),
+                arguments: vec![arg],
+                return_: None,
+                type_assigns: Default::default(),
+            },
+        );
+    }
+    let header = ArtifactHeader::new("test");
+    write_sharded(&header, &desc, ArtifactFormat::Json, &dir).unwrap();
+    assert!(is_sharded(&dir));
+
+    let (stubs, reader) = ShardReader::open(&dir).unwrap();
+    assert_eq!(stubs.controllers.len(), 2);
+    assert!(stubs
+        .controllers
+        .values()
+        .all(|c| c.graph.node_count() == 0));
+    let (id, ctrl) = desc.controllers.iter().next().unwrap();
+    assert_eq!(reader.load(*id).unwrap().graph.node_count(), 1);
+    assert_eq!(stubs.controllers[id].arguments, ctrl.arguments);
+
+    let read = read_sharded(&dir).unwrap();
+    let file = dir.join(INDEX_FILE_NAME);
+    let not_a_dir = write_sharded(&header, &desc, ArtifactFormat::Json, file).unwrap_err();
+    assert_eq!(not_a_dir.kind(), io::ErrorKind::AlreadyExists);
+    std::fs::remove_file(reader.shard_path(*id)).unwrap();
+    let missing = ShardReader::open(&dir).unwrap_err();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(missing.kind(), io::ErrorKind::NotFound);
+    assert_eq!(
+        serde_json::to_value(read).unwrap(),
+        serde_json::to_value(&desc).unwrap()
+    );
+}
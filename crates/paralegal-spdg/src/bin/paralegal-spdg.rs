@@ -0,0 +1,160 @@
+//! Command line utilities for inspecting graphs written by `paralegal-flow`.
+//!
+//! Both the JSON and the binary artifact encoding are accepted as input.
+
+use std::{fs::File, io::BufWriter, process::ExitCode};
+
+use paralegal_spdg::{
+    artifact, diff,
+    dot::{self, DotFilter, SliceDirection},
+    export, Identifier, ProgramDescription,
+};
+
+const USAGE: &str = "\
+Usage: paralegal-spdg <COMMAND>
+
+Commands:
+  diff <OLD> <NEW>  Print controllers, nodes, edges, markers and types that
+                    were added (+) or removed (-) between two graphs. Exits
+                    with 1 if there are differences.
+  graphml <GRAPH> <OUT>
+                    Write the graph as GraphML.
+  neo4j <GRAPH> <NODES> <EDGES>
+                    Write node and relationship CSV files for
+                    `neo4j-admin database import`.
+  cypher <GRAPH> <OUT>
+                    Write a Cypher script that creates the graph.
+  dot <GRAPH> <OUT> [OPTIONS]
+                    Render the graph in dot format. Options:
+                      --controller <NAME>  Only this controller (repeatable)
+                      --marker <MARKER>    Start from nodes with this marker
+                                           (repeatable)
+                      --direction <forward|backward|both>
+                                           Which way to walk from the start
+                      --depth <N>          At most N edges from the start
+  html <GRAPH> <OUT> [CONTROLLER...]
+                    Write an interactive, self-contained HTML report of the
+                    named controllers (default: all).
+  datalog <GRAPH> <DIR>
+                    Write Souffle `.facts` files and the `paralegal.dl` rule
+                    library into DIR.";
+
+fn main() -> ExitCode {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    match run(&args) {
+        Ok(code) => code,
+        Err(msg) => {
+            eprintln!("{msg}");
+            ExitCode::from(2)
+        }
+    }
+}
+
+fn run(args: &[String]) -> Result<ExitCode, String> {
+    match args {
+        [cmd, old, new] if cmd == "diff" => {
+            let diff = diff::diff(&load(old)?, &load(new)?);
+            print!("{diff}");
+            Ok(if diff.is_empty() {
+                ExitCode::SUCCESS
+            } else {
+                ExitCode::from(1)
+            })
+        }
+        [cmd, graph, out] if cmd == "graphml" => {
+            export::graphml::write(&load(graph)?, create(out)?).map_err(|e| e.to_string())?;
+            Ok(ExitCode::SUCCESS)
+        }
+        [cmd, graph, nodes, edges] if cmd == "neo4j" => {
+            export::neo4j::write_csv(&load(graph)?, create(nodes)?, create(edges)?)
+                .map_err(|e| e.to_string())?;
+            Ok(ExitCode::SUCCESS)
+        }
+        [cmd, graph, out] if cmd == "cypher" => {
+            export::neo4j::write_cypher(&load(graph)?, create(out)?).map_err(|e| e.to_string())?;
+            Ok(ExitCode::SUCCESS)
+        }
+        [cmd, graph, out, options @ ..] if cmd == "dot" => {
+            let desc = load(graph)?;
+            let filter = dot_filter(&desc, options)?;
+            dot::dump_filtered(&desc, create(out)?, &filter).map_err(|e| e.to_string())?;
+            Ok(ExitCode::SUCCESS)
+        }
+        [cmd, graph, out, controllers @ ..] if cmd == "html" => {
+            let desc = load(graph)?;
+            let names = controllers
+                .iter()
+                .map(|name| Identifier::new_intern(name))
+                .collect::<Vec<_>>();
+            export::html::write_for_selection(&desc, create(out)?, |id| {
+                names.is_empty() || names.contains(&desc.controllers[&id].name)
+            })
+            .map_err(|e| e.to_string())?;
+            Ok(ExitCode::SUCCESS)
+        }
+        [cmd, graph, dir] if cmd == "datalog" => {
+            let facts = export::datalog::Facts::new(&load(graph)?);
+            facts.write_to_dir(dir).map_err(|e| e.to_string())?;
+            std::fs::write(
+                std::path::Path::new(dir).join("paralegal.dl"),
+                export::datalog::RULES,
+            )
+            .map_err(|e| e.to_string())?;
+            Ok(ExitCode::SUCCESS)
+        }
+        _ => Err(USAGE.to_owned()),
+    }
+}
+
+fn dot_filter(desc: &ProgramDescription, options: &[String]) -> Result<DotFilter, String> {
+    let mut filter = DotFilter::default();
+    let mut controllers = vec![];
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        let value = options
+            .next()
+            .ok_or_else(|| format!("Missing value for {option}\n\n{USAGE}"))?;
+        match option.as_str() {
+            "--controller" => {
+                let name = Identifier::new_intern(value);
+                let found = desc
+                    .controllers
+                    .iter()
+                    .filter(|(_, ctrl)| ctrl.name == name)
+                    .map(|(id, _)| *id)
+                    .collect::<Vec<_>>();
+                if found.is_empty() {
+                    return Err(format!("No controller named {value}"));
+                }
+                controllers.extend(found);
+            }
+            "--marker" => filter.markers.push(Identifier::new_intern(value)),
+            "--direction" => {
+                filter.direction = match value.as_str() {
+                    "forward" => SliceDirection::Forward,
+                    "backward" => SliceDirection::Backward,
+                    "both" => SliceDirection::Both,
+                    _ => return Err(format!("Unknown direction {value}")),
+                }
+            }
+            "--depth" => {
+                filter.depth = Some(value.parse().map_err(|e| format!("Invalid depth: {e}"))?)
+            }
+            _ => return Err(format!("Unknown option {option}\n\n{USAGE}")),
+        }
+    }
+    if !controllers.is_empty() {
+        filter.controllers = Some(controllers);
+    }
+    Ok(filter)
+}
+
+fn load(path: &str) -> Result<ProgramDescription, String> {
+    artifact::read_from_file(path).map_err(|e| format!("Could not read {path}: {e}"))
+}
+
+fn create(path: &str) -> Result<BufWriter<File>, String> {
+    File::create(path)
+        .map(BufWriter::new)
+        .map_err(|e| format!("Could not create {path}: {e}"))
+}
@@ -0,0 +1,234 @@
+//! Bringing a [`ProgramDescription`] into a canonical form.
+//!
+//! The extractor builds graphs and collects metadata through hash maps, so
+//! node indices and the order of list entries vary between runs on the same
+//! code. After [`ProgramDescription::canonicalize`] they only depend on the
+//! analyzed code. Together with the sorted serialization of maps (see
+//! [`utils::sort_by_serialized_key`](crate::utils::sort_by_serialized_key))
+//! identical code yields byte-identical artifacts.
+
+use petgraph::{visit::EdgeRef, Direction};
+
+use crate::{Node, ProgramDescription, SPDGImpl, StableIdAllocator, SPDG};
+
+impl SPDG {
+    /// Make the [`StableNodeId`](crate::StableNodeId)s of this graph unique
+    /// with a [`StableIdAllocator`].
+    ///
+    /// Nodes are allocated ordered by their id and then by their content:
+    /// span, kind, description, call string and the call strings of their
+    /// edges. Which of the nodes with the same id keeps it therefore does not
+    /// depend on the order in which they were inserted.
+    pub fn disambiguate_stable_ids(&mut self) {
+        let mut order = self.graph.node_indices().collect::<Vec<_>>();
+        order.sort_by_cached_key(|n| {
+            let info = self.node_info(*n);
+            let edges = |direction| {
+                let mut edges = self
+                    .graph
+                    .edges_directed(*n, direction)
+                    .map(|e| {
+                        let other = match direction {
+                            Direction::Incoming => e.source(),
+                            Direction::Outgoing => e.target(),
+                        };
+                        (
+                            e.weight().kind.is_control(),
+                            e.weight().at.to_string(),
+                            self.graph[other].stable_id,
+                        )
+                    })
+                    .collect::<Vec<_>>();
+                edges.sort();
+                edges
+            };
+            (
+                info.stable_id,
+                (
+                    info.span.source_file.file_path.clone(),
+                    info.span.start,
+                    info.span.end,
+                ),
+                format!("{:?}", info.kind),
+                info.description.clone(),
+                info.at.to_string(),
+                edges(Direction::Incoming),
+                edges(Direction::Outgoing),
+            )
+        });
+        let mut allocator = StableIdAllocator::default();
+        for n in order {
+            let info = &mut self.graph[n];
+            info.stable_id = allocator.allocate(info.stable_id);
+        }
+    }
+
+    /// Renumber the nodes in order of their [`StableNodeId`](crate::StableNodeId)
+    /// and sort the edges and the marker and type lists.
+    ///
+    /// The order of [`Self::arguments`] is meaningful and is kept, only the
+    /// nodes in it are renumbered.
+    pub fn canonicalize(&mut self) {
+        let mut order = self.graph.node_indices().collect::<Vec<_>>();
+        order.sort_by_cached_key(|n| {
+            let info = self.node_info(*n);
+            (
+                info.stable_id,
+                info.at.to_string(),
+                info.description.clone(),
+            )
+        });
+        let mut renumbered = vec![Node::end(); order.len()];
+        for (new, old) in order.iter().enumerate() {
+            renumbered[old.index()] = Node::new(new);
+        }
+        let renumber = |n: Node| renumbered[n.index()];
+
+        let mut graph = SPDGImpl::with_capacity(self.graph.node_count(), self.graph.edge_count());
+        for old in &order {
+            graph.add_node(self.graph[*old].clone());
+        }
+        let mut edges = self
+            .graph
+            .edge_references()
+            .map(|e| (renumber(e.source()), renumber(e.target()), e.weight()))
+            .collect::<Vec<_>>();
+        edges.sort_by_cached_key(|(from, to, info)| {
+            (*from, *to, info.kind.is_control(), info.at.to_string())
+        });
+        for (from, to, info) in edges {
+            graph.add_edge(from, to, info.clone());
+        }
+        self.graph = graph;
+
+        self.markers = self
+            .markers
+            .drain()
+            .map(|(n, mut markers)| {
+                markers.sort();
+                markers.dedup();
+                (renumber(n), markers)
+            })
+            .collect();
+        self.type_assigns = self
+            .type_assigns
+            .drain()
+            .map(|(n, mut types)| {
+                types.0.sort();
+                types.0.dedup();
+                (renumber(n), types)
+            })
+            .collect();
+        for arg in &mut self.arguments {
+            *arg = renumber(*arg);
+        }
+        self.return_ = self.return_.map(renumber);
+    }
+}
+
+impl ProgramDescription {
+    /// Canonicalize every controller (see [`SPDG::canonicalize`]) and sort
+    /// the lists in [`Self::type_info`].
+    ///
+    /// Every node of every controller must exist, which is checked by
+    /// [`Self::validate`].
+    pub fn canonicalize(&mut self) {
+        for ctrl in self.controllers.values_mut() {
+            ctrl.canonicalize();
+        }
+        for info in self.type_info.values_mut() {
+            info.otypes.sort();
+            info.otypes.dedup();
+            info.markers.sort();
+            info.markers.dedup();
+        }
+    }
+}
+
+#[test]
+fn serialization_is_canonical() {
+    let json = r#"[
+        [{"krate":{"private":0},"index":{"private":3}},{"rendering":"A","otypes":[],"markers":["a"]}],
+        [{"krate":{"private":0},"index":{"private":1}},{"rendering":"B","otypes":[],"markers":["b"]}],
+        [{"krate":{"private":1},"index":{"private":2}},{"rendering":"C","otypes":[],"markers":["c"]}]
+    ]"#;
+    let serialize = || {
+        let mut desc = ProgramDescription::default();
+        let mut de = serde_json::Deserializer::from_str(json);
+        desc.type_info = crate::utils::serde_map_via_vec::deserialize(&mut de).unwrap();
+        desc.canonicalize();
+        serde_json::to_string(&desc).unwrap()
+    };
+    let first = serialize();
+    for _ in 0..10 {
+        assert_eq!(serialize(), first);
+    }
+}
+
+#[test]
+fn canonical_form_ignores_insertion_order() {
+    use crate::{test_utils::*, EdgeInfo, EdgeKind, NodeInfo, StableNodeId};
+    let example = example_description().controllers[&local_def_id(EXAMPLE_CONTROLLER)].clone();
+    let input = example.arguments[0];
+    // Two more nodes whose ids collide with the one of `input`. They differ
+    // only in their span.
+    let duplicate = |line| NodeInfo {
+        span: span(line),
+        ..example.node_info(input).clone()
+    };
+    let mut nodes = example.graph.node_weights().cloned().collect::<Vec<_>>();
+    nodes.extend([duplicate(7), duplicate(8)]);
+    let mut edges = example
+        .graph
+        .edge_references()
+        .map(|e| (e.source().index(), e.target().index(), e.weight().clone()))
+        .collect::<Vec<_>>();
+    for dup in [3, 4] {
+        let at = nodes[input.index()].at;
+        edges.push((
+            dup,
+            input.index(),
+            EdgeInfo {
+                kind: EdgeKind::Data,
+                at,
+            },
+        ));
+    }
+
+    let serialized = |insertion_order: [usize; 5]| {
+        let mut graph = SPDGImpl::new();
+        let mut added = vec![Node::end(); nodes.len()];
+        for i in insertion_order {
+            added[i] = graph.add_node(nodes[i].clone());
+        }
+        for (from, to, info) in edges.iter().rev() {
+            graph.add_edge(added[*from], added[*to], info.clone());
+        }
+        let old_to_new = |n: Node| added[n.index()];
+        let mut ctrl = SPDG {
+            graph,
+            arguments: example.arguments.iter().copied().map(old_to_new).collect(),
+            return_: example.return_.map(old_to_new),
+            markers: example
+                .markers
+                .iter()
+                .map(|(n, m)| (old_to_new(*n), m.clone()))
+                .collect(),
+            type_assigns: example
+                .type_assigns
+                .iter()
+                .map(|(n, t)| (old_to_new(*n), t.clone()))
+                .collect(),
+            name: example.name,
+        };
+        ctrl.disambiguate_stable_ids();
+        ctrl.canonicalize();
+        let input_id = ctrl.node_info(ctrl.arguments[0]).stable_id;
+        (input_id, serde_json::to_string(&ctrl).unwrap())
+    };
+    let (input_id, first) = serialized([0, 1, 2, 3, 4]);
+    // The earliest span keeps the id
+    assert_eq!(input_id, StableNodeId::from_parts(["main", "input"]));
+    assert_eq!(serialized([4, 3, 2, 1, 0]).1, first);
+    assert_eq!(serialized([3, 0, 4, 2, 1]).1, first);
+}
@@ -0,0 +1,392 @@
+//! Structural comparison of two [`ProgramDescription`]s.
+//!
+//! Node indices and `DefId`s are not stable between two runs of the extractor,
+//! so instead the entities in the graph are matched by the information that
+//! describes them in the source code:
+//!
+//! - Controllers are matched by their name. Names that are shared by more
+//!   than one controller in either version are reported in
+//!   [`ProgramDiff::ambiguous`] and not compared.
+//! - Nodes are matched by their [`StableNodeId`] (see [`NodeKey`]), which,
+//!   unlike the [`CallString`](crate::CallString) of the node, does not depend
+//!   on the compilation session.
+//! - Edges are matched by the stable ids of their endpoints and their
+//!   [`EdgeKind`]. Parallel edges of the same kind are treated as one.
+//! - Types are matched by their rendering.
+//!
+//! Use [`diff`] to compute a [`ProgramDiff`], whose [`Display`] implementation
+//! prints a human readable summary.
+
+use std::{
+    collections::HashSet,
+    fmt::{self, Display},
+    hash::Hash,
+};
+
+use itertools::Itertools;
+use petgraph::visit::EdgeRef;
+
+use crate::{EdgeKind, HashMap, Identifier, Node, ProgramDescription, StableNodeId, TypeId, SPDG};
+
+/// Identifies a node across two versions of a graph.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct NodeKey {
+    /// The stable id of the node
+    pub stable_id: StableNodeId,
+    /// The place the node represents
+    pub description: String,
+}
+
+impl Display for NodeKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.description, self.stable_id)
+    }
+}
+
+/// Identifies an edge across two versions of a graph.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct EdgeKey {
+    /// Source of the edge
+    pub from: NodeKey,
+    /// Target of the edge
+    pub to: NodeKey,
+    /// Data or control
+    pub kind: EdgeKind,
+}
+
+impl Display for EdgeKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} -> {} ({})", self.from, self.to, self.kind)
+    }
+}
+
+/// Elements that are only present in one of the two compared versions.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Changes<T> {
+    /// Present in the new but not in the old version
+    pub added: Vec<T>,
+    /// Present in the old but not in the new version
+    pub removed: Vec<T>,
+}
+
+impl<T> Default for Changes<T> {
+    fn default() -> Self {
+        Self {
+            added: vec![],
+            removed: vec![],
+        }
+    }
+}
+
+impl<T: Eq + Hash + Clone + Display> Changes<T> {
+    /// Compute the changes between two sets. The results are sorted by their
+    /// [`Display`] rendering, so that the output is deterministic.
+    pub fn between(old: &HashSet<T>, new: &HashSet<T>) -> Self {
+        let sorted = |it: std::collections::hash_set::Difference<'_, T, _>| {
+            let mut v = it.cloned().collect::<Vec<_>>();
+            v.sort_by_cached_key(ToString::to_string);
+            v
+        };
+        Self {
+            added: sorted(new.difference(old)),
+            removed: sorted(old.difference(new)),
+        }
+    }
+}
+
+impl<T> Changes<T> {
+    /// True if both versions were the same
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+
+    fn write(&self, f: &mut fmt::Formatter<'_>, what: &str, indent: &str) -> fmt::Result
+    where
+        T: Display,
+    {
+        for (sign, items) in [('+', &self.added), ('-', &self.removed)] {
+            for item in items {
+                writeln!(f, "{indent}{sign} {what} {item}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A marker that is attached to a node.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct MarkerAssignment {
+    /// The marked node
+    pub node: NodeKey,
+    /// The marker
+    pub marker: Identifier,
+}
+
+impl Display for MarkerAssignment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} on {}", self.marker, self.node)
+    }
+}
+
+/// A type that was assigned to a node.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TypeAssignment {
+    /// The node the type is assigned to
+    pub node: NodeKey,
+    /// Rendering of the type
+    pub ty: String,
+}
+
+impl Display for TypeAssignment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} on {}", self.ty, self.node)
+    }
+}
+
+/// Differences within one controller that is present in both versions.
+#[derive(Clone, Debug)]
+pub struct ControllerDiff {
+    /// Name of the controller
+    pub name: Identifier,
+    /// Changed nodes
+    pub nodes: Changes<NodeKey>,
+    /// Changed edges
+    pub edges: Changes<EdgeKey>,
+    /// Changed marker assignments
+    pub markers: Changes<MarkerAssignment>,
+    /// Changed type assignments
+    pub type_assigns: Changes<TypeAssignment>,
+}
+
+impl ControllerDiff {
+    /// True if this controller did not change
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+            && self.edges.is_empty()
+            && self.markers.is_empty()
+            && self.type_assigns.is_empty()
+    }
+}
+
+/// Result of [`diff`].
+#[derive(Clone, Debug)]
+pub struct ProgramDiff {
+    /// Controllers that were added or removed as a whole
+    pub controllers: Changes<Identifier>,
+    /// Controllers present in both versions that changed, sorted by name
+    pub changed: Vec<ControllerDiff>,
+    /// Names that are shared by multiple controllers in at least one version,
+    /// sorted. These controllers cannot be matched and are not compared.
+    pub ambiguous: Vec<Identifier>,
+}
+
+impl ProgramDiff {
+    /// True if no differences were found. Ambiguous controllers count as a
+    /// difference, because they could not be compared.
+    pub fn is_empty(&self) -> bool {
+        self.controllers.is_empty() && self.changed.is_empty() && self.ambiguous.is_empty()
+    }
+}
+
+impl Display for ProgramDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for name in &self.ambiguous {
+            writeln!(
+                f,
+                "! controller {name} is not unique, its versions were not compared"
+            )?;
+        }
+        self.controllers.write(f, "controller", "")?;
+        for ctrl in &self.changed {
+            writeln!(f, "controller {}", ctrl.name)?;
+            ctrl.nodes.write(f, "node", "  ")?;
+            ctrl.edges.write(f, "edge", "  ")?;
+            ctrl.markers.write(f, "marker", "  ")?;
+            ctrl.type_assigns.write(f, "type", "  ")?;
+        }
+        Ok(())
+    }
+}
+
+/// Precomputed lookup structures for one side of the comparison.
+struct Side<'a> {
+    desc: &'a ProgramDescription,
+    by_name: HashMap<Identifier, Vec<&'a SPDG>>,
+}
+
+impl<'a> Side<'a> {
+    fn new(desc: &'a ProgramDescription) -> Self {
+        let mut by_name: HashMap<_, Vec<_>> = HashMap::new();
+        for ctrl in desc.controllers.values() {
+            by_name.entry(ctrl.name).or_default().push(ctrl);
+        }
+        Self { desc, by_name }
+    }
+
+    fn ambiguous(&self) -> impl Iterator<Item = Identifier> + '_ {
+        self.by_name
+            .iter()
+            .filter(|(_, ctrls)| ctrls.len() > 1)
+            .map(|(name, _)| *name)
+    }
+
+    fn render_type(&self, ty: TypeId) -> String {
+        crate::export::render_type(self.desc, ty)
+    }
+
+    fn nodes(&self, ctrl: &SPDG) -> HashSet<NodeKey> {
+        ctrl.all_sources().map(|n| node_key(ctrl, n)).collect()
+    }
+
+    fn edges(&self, ctrl: &SPDG) -> HashSet<EdgeKey> {
+        ctrl.edges()
+            .map(|e| EdgeKey {
+                from: node_key(ctrl, e.source()),
+                to: node_key(ctrl, e.target()),
+                kind: e.weight().kind,
+            })
+            .collect()
+    }
+
+    fn markers(&self, ctrl: &SPDG) -> HashSet<MarkerAssignment> {
+        ctrl.markers
+            .iter()
+            .flat_map(|(&node, markers)| {
+                markers.iter().map(move |&marker| MarkerAssignment {
+                    node: node_key(ctrl, node),
+                    marker,
+                })
+            })
+            .collect()
+    }
+
+    fn type_assigns(&self, ctrl: &SPDG) -> HashSet<TypeAssignment> {
+        ctrl.type_assigns
+            .iter()
+            .flat_map(|(&node, types)| {
+                types.0.iter().map(move |&ty| TypeAssignment {
+                    node: node_key(ctrl, node),
+                    ty: self.render_type(ty),
+                })
+            })
+            .collect()
+    }
+}
+
+fn node_key(ctrl: &SPDG, node: Node) -> NodeKey {
+    let info = ctrl.node_info(node);
+    NodeKey {
+        stable_id: info.stable_id,
+        description: info.description.clone(),
+    }
+}
+
+/// Compare two program descriptions, reporting everything that is present in
+/// `new` but not in `old` as added and vice versa.
+///
+/// Controllers whose name is not unique are only reported in
+/// [`ProgramDiff::ambiguous`].
+pub fn diff(old: &ProgramDescription, new: &ProgramDescription) -> ProgramDiff {
+    let old = Side::new(old);
+    let new = Side::new(new);
+    let old_names = old.by_name.keys().copied().collect::<HashSet<_>>();
+    let new_names = new.by_name.keys().copied().collect::<HashSet<_>>();
+    let ambiguous = old
+        .ambiguous()
+        .chain(new.ambiguous())
+        .collect::<HashSet<_>>();
+    let changed = old_names
+        .intersection(&new_names)
+        .filter(|name| !ambiguous.contains(name))
+        .sorted()
+        .map(|name| {
+            let (o, n) = (old.by_name[name][0], new.by_name[name][0]);
+            ControllerDiff {
+                name: *name,
+                nodes: Changes::between(&old.nodes(o), &new.nodes(n)),
+                edges: Changes::between(&old.edges(o), &new.edges(n)),
+                markers: Changes::between(&old.markers(o), &new.markers(n)),
+                type_assigns: Changes::between(&old.type_assigns(o), &new.type_assigns(n)),
+            }
+        })
+        .filter(|d| !d.is_empty())
+        .collect();
+    ProgramDiff {
+        controllers: Changes::between(&old_names, &new_names),
+        changed,
+        ambiguous: ambiguous.into_iter().sorted().collect(),
+    }
+}
+
+#[test]
+fn diff_reports_changed_nodes_edges_and_markers() {
+    use crate::test_utils::example_description;
+    let old = example_description();
+    assert!(diff(&old, &example_description()).is_empty());
+
+    let mut new = example_description();
+    let ctrl = new.controllers.values_mut().next().unwrap();
+    let [input, checked, output] = [0, 1, 2].map(Node::new);
+    let logged = ctrl.graph.add_node(crate::NodeInfo {
+        description: "logged".to_owned(),
+        stable_id: StableNodeId::from_parts(["main", "logged"]),
+        ..ctrl.node_info(checked).clone()
+    });
+    let edge_info = ctrl.graph.edges(checked).next().unwrap().weight().clone();
+    ctrl.graph.add_edge(checked, logged, edge_info);
+    let control = ctrl.graph.find_edge(input, output).unwrap();
+    ctrl.graph.remove_edge(control);
+    ctrl.markers
+        .insert(input, vec![Identifier::new_intern("user_input")]);
+
+    let diff = diff(&old, &new);
+    assert!(diff.controllers.is_empty());
+    assert_eq!(diff.changed.len(), 1);
+    let changes = &diff.changed[0];
+    let old_ctrl = old.controllers.values().next().unwrap();
+    let new_ctrl = new.controllers.values().next().unwrap();
+    assert_eq!(changes.nodes.added, [node_key(new_ctrl, logged)]);
+    assert!(changes.nodes.removed.is_empty());
+    assert_eq!(
+        changes.edges.added,
+        [EdgeKey {
+            from: node_key(new_ctrl, checked),
+            to: node_key(new_ctrl, logged),
+            kind: EdgeKind::Data,
+        }]
+    );
+    assert_eq!(
+        changes.edges.removed,
+        [EdgeKey {
+            from: node_key(old_ctrl, input),
+            to: node_key(old_ctrl, output),
+            kind: EdgeKind::Control,
+        }]
+    );
+    let marker = |marker| MarkerAssignment {
+        node: node_key(old_ctrl, input),
+        marker: Identifier::new_intern(marker),
+    };
+    assert_eq!(changes.markers.added, [marker("user_input")]);
+    assert_eq!(changes.markers.removed, [marker("source")]);
+    assert!(changes.type_assigns.is_empty());
+}
+
+#[test]
+fn diff_reports_ambiguous_controllers() {
+    use crate::test_utils::{example_description, local_def_id};
+    let old = example_description();
+    let mut new = example_description();
+    let mut copy = new.controllers.values().next().unwrap().clone();
+    copy.graph.clear();
+    new.controllers.insert(local_def_id(1), copy);
+
+    let diff = diff(&old, &new);
+    assert_eq!(diff.ambiguous, [Identifier::new_intern("main")]);
+    assert!(diff.changed.is_empty());
+    assert!(diff.controllers.is_empty());
+    assert!(!diff.is_empty());
+    assert!(diff
+        .to_string()
+        .starts_with("! controller main is not unique"));
+}
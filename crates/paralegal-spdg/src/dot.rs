@@ -1,196 +1,351 @@
 //! Display SPDGs as dot graphs
+//!
+//! Every controller is drawn as a cluster, which contains one nested cluster
+//! per function at the leaf of the [`CallString`]s of its nodes. Nodes are
+//! shaped by their [`NodeKind`] and filled with a color per marker, control
+//! flow edges are dashed. Labels show the place description, the kind, the
+//! markers and the call string, rendered as function names and `file:line`.
+//!
+//! Use [`dump_filtered`] with a [`DotFilter`] to only render the interesting
+//! part of a large graph.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::{self, Write};
 
-use crate::{GlobalEdge, InstructionKind, Node, ProgramDescription};
-use dot::{CompassPoint, Edges, Id, LabelText, Nodes};
 use flowistry_pdg::rustc_portable::LocalDefId;
-use flowistry_pdg::{CallString, RichLocation};
-
-use petgraph::prelude::EdgeRef;
-use petgraph::visit::{IntoNodeReferences, NodeRef};
-use std::collections::HashMap;
-
-struct DotPrintableProgramDescription<'d> {
-    spdg: &'d ProgramDescription,
-    call_sites: HashMap<CallString, (LocalDefId, Vec<Node>)>,
-    selected_controllers: Vec<LocalDefId>,
-}
-
-impl<'d> DotPrintableProgramDescription<'d> {
-    pub fn new_for_selection(
-        spdg: &'d ProgramDescription,
-        mut selector: impl FnMut(LocalDefId) -> bool,
-    ) -> Self {
-        let selected_controllers: Vec<_> = spdg
-            .controllers
-            .keys()
-            .copied()
-            .filter(|l| selector(*l))
-            .collect();
-        let mut call_sites = HashMap::new();
-        for c in selected_controllers.iter() {
-            let ctrl = &spdg.controllers[c];
-            for w in ctrl.graph.node_references() {
-                let e = call_sites
-                    .entry(w.weight().at)
-                    .or_insert_with(|| (*c, vec![]));
-                e.1.push(w.id());
-            }
-        }
+use flowistry_pdg::{CallString, GlobalLocation, RichLocation};
+use itertools::Itertools;
+use petgraph::visit::EdgeRef;
+use petgraph::Direction;
+
+use crate::export::{node_records, sorted_controllers, NodeRecord};
+use crate::traverse::EdgeSelection;
+use crate::{GlobalNode, Identifier, Node, NodeKind, ProgramDescription, SPDG};
+
+/// Which way to walk from the start nodes of a [`DotFilter`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default, strum::EnumIs)]
+pub enum SliceDirection {
+    /// Nodes influenced by the start nodes
+    Forward,
+    /// Nodes influencing the start nodes
+    Backward,
+    /// Both of the above
+    #[default]
+    Both,
+}
+
+/// Selects which part of a [`ProgramDescription`] is rendered.
+///
+/// If neither `markers` nor `nodes` is set, all nodes of the selected
+/// controllers are rendered. Otherwise rendering starts from the nodes carrying
+/// one of the `markers` and from `nodes`, and includes everything reachable
+/// from them in `direction`, up to `depth` edges away. For instance
+///
+/// - `depth: Some(0)` shows only the marked nodes,
+/// - `depth: None` with [`SliceDirection::Backward`] shows the backward slice
+///   of the start nodes.
+#[derive(Clone, Debug)]
+pub struct DotFilter {
+    /// Only render these controllers. `None` renders all of them.
+    pub controllers: Option<Vec<LocalDefId>>,
+    /// Start from nodes with any of these markers, directly or via their type
+    pub markers: Vec<Identifier>,
+    /// Start from these nodes
+    pub nodes: Vec<GlobalNode>,
+    /// Which way to walk from the start nodes
+    pub direction: SliceDirection,
+    /// Edges that are followed from the start nodes and drawn
+    pub edge_selection: EdgeSelection,
+    /// Maximum number of edges between a start node and a rendered node.
+    /// `None` means unbounded.
+    pub depth: Option<usize>,
+}
+
+impl Default for DotFilter {
+    fn default() -> Self {
         Self {
-            call_sites,
-            spdg,
-            selected_controllers,
+            controllers: None,
+            markers: vec![],
+            nodes: vec![],
+            direction: SliceDirection::default(),
+            edge_selection: EdgeSelection::Both,
+            depth: None,
         }
     }
+}
+
+impl DotFilter {
+    fn has_start(&self) -> bool {
+        !self.markers.is_empty() || !self.nodes.is_empty()
+    }
 
-    fn format_call_string(&self, call_string: CallString) -> String {
-        use std::fmt::Write;
-        let mut s = String::new();
-        for loc in call_string.iter_from_root() {
-            if !s.is_empty() {
-                s.push('@');
+    fn selects_controller(&self, id: LocalDefId) -> bool {
+        self.controllers
+            .as_ref()
+            .map_or(true, |selected| selected.contains(&id))
+    }
+
+    /// The nodes of `ctrl` that should be rendered
+    fn retained(&self, id: LocalDefId, ctrl: &SPDG, records: &[NodeRecord]) -> HashSet<Node> {
+        if !self.has_start() {
+            return ctrl.all_sources().collect();
+        }
+        let start = records
+            .iter()
+            .filter(|r| r.markers.iter().any(|m| self.markers.contains(m)))
+            .map(|r| r.node)
+            .chain(
+                self.nodes
+                    .iter()
+                    .filter(|n| n.controller_id() == id)
+                    .map(|n| n.local_node()),
+            );
+        let mut distance = HashMap::new();
+        let mut queue = VecDeque::new();
+        for n in start {
+            if distance.insert(n, 0).is_none() {
+                queue.push_back(n);
             }
-            match loc.location {
-                RichLocation::Location(l) => {
-                    write!(s, "bb{}[{}]", l.block.index(), l.statement_index).unwrap()
+        }
+        let directions: &[Direction] = match self.direction {
+            SliceDirection::Forward => &[Direction::Outgoing],
+            SliceDirection::Backward => &[Direction::Incoming],
+            SliceDirection::Both => &[Direction::Outgoing, Direction::Incoming],
+        };
+        while let Some(n) = queue.pop_front() {
+            let d = distance[&n];
+            if self.depth.map_or(false, |max| d >= max) {
+                continue;
+            }
+            for &dir in directions {
+                for e in ctrl.graph.edges_directed(n, dir) {
+                    if !self.edge_selection.conforms(e.weight().kind) {
+                        continue;
+                    }
+                    let next = if dir == Direction::Outgoing {
+                        e.target()
+                    } else {
+                        e.source()
+                    };
+                    if let std::collections::hash_map::Entry::Vacant(v) = distance.entry(next) {
+                        v.insert(d + 1);
+                        queue.push_back(next);
+                    }
                 }
-                RichLocation::End => s.push_str("end"),
-                RichLocation::Start => s.push_str("start"),
             }
         }
-        s
+        distance.into_keys().collect()
     }
 }
 
-impl<'a, 'd> dot::GraphWalk<'a, CallString, GlobalEdge> for DotPrintableProgramDescription<'d> {
-    fn nodes(&'a self) -> Nodes<'a, CallString> {
-        self.call_sites.keys().copied().collect::<Vec<_>>().into()
-    }
-    fn edges(&'a self) -> Edges<'a, GlobalEdge> {
-        self.selected_controllers
-            .iter()
-            .flat_map(|&controller_id| {
-                let ctrl = &self.spdg.controllers[&controller_id];
-                ctrl.graph.edge_references().map(move |edge| GlobalEdge {
-                    controller_id,
-                    index: edge.id(),
-                })
-            })
-            .collect::<Vec<_>>()
-            .into()
-    }
+/// Escape a string for use inside a double quoted dot string. Line breaks
+/// become left justified line breaks.
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\l")
+}
 
-    fn source(&'a self, edge: &GlobalEdge) -> CallString {
-        let ctrl = &self.spdg.controllers[&edge.controller_id].graph;
-        let (from, _) = ctrl.edge_endpoints(edge.index).unwrap();
-        ctrl.node_weight(from).unwrap().at
-    }
+/// Colorbrewer "Set3", a qualitative scheme that keeps labels readable.
+const MARKER_COLORS: &[&str] = &[
+    "#8dd3c7", "#ffffb3", "#bebada", "#fb8072", "#80b1d3", "#fdb462", "#b3de69", "#fccde5",
+    "#d9d9d9", "#bc80bd", "#ccebc5", "#ffed6f",
+];
 
-    fn target(&'a self, edge: &GlobalEdge) -> CallString {
-        let ctrl = &self.spdg.controllers[&edge.controller_id].graph;
-        let (_, to) = ctrl.edge_endpoints(edge.index).unwrap();
-        ctrl.node_weight(to).unwrap().at
+fn node_shape(kind: NodeKind) -> &'static str {
+    match kind {
+        NodeKind::FormalParameter(_) => "invhouse",
+        NodeKind::FormalReturn => "house",
+        NodeKind::ActualParameter(_) => "box",
+        NodeKind::ActualReturn => "octagon",
+        NodeKind::Unspecified => "ellipse",
     }
 }
 
-impl<'a, 'd> dot::Labeller<'a, CallString, GlobalEdge> for DotPrintableProgramDescription<'d> {
-    fn graph_id(&'a self) -> Id<'a> {
-        Id::new("g").unwrap()
-    }
-    fn node_id(&'a self, n: &CallString) -> Id<'a> {
-        Id::new(format!("n{}", n.stable_id())).unwrap()
+struct Renderer<'d> {
+    desc: &'d ProgramDescription,
+    marker_colors: HashMap<Identifier, &'static str>,
+}
+
+impl<'d> Renderer<'d> {
+    /// `file:line` of an instruction
+    fn location(&self, loc: GlobalLocation) -> Option<String> {
+        let span = &self.desc.instruction_info.get(&loc)?.span;
+        Some(format!(
+            "{}:{}",
+            span.source_file.file_path, span.start.line
+        ))
     }
 
-    fn node_shape(&'a self, _node: &CallString) -> Option<LabelText<'a>> {
-        Some(LabelText::LabelStr("record".into()))
+    /// The name of the function executing each location in `at`, from the root.
+    /// The root is the controller, every other function is the one called at
+    /// the preceding location.
+    fn function_names(&self, ctrl: &SPDG, at: CallString) -> Vec<String> {
+        let mut names = vec![ctrl.name.to_string()];
+        let locations = at.iter_from_root().collect::<Vec<_>>();
+        for caller in &locations[..locations.len() - 1] {
+            let callee = self
+                .desc
+                .instruction_info
+                .get(caller)
+                .and_then(|info| info.kind.as_function_call())
+                .and_then(|call| self.desc.def_info.get(&call.id))
+                .map_or_else(|| "?".to_owned(), |info| info.name.to_string());
+            names.push(callee);
+        }
+        names
     }
 
-    fn node_label(&'a self, n: &CallString) -> LabelText<'a> {
-        let (ctrl_id, nodes) = &self.call_sites[n];
-        let ctrl = &self.spdg.controllers[ctrl_id];
-        let instruction = &self.spdg.instruction_info[&n.leaf()];
+    /// E.g. `controller (src/lib.rs:10) -> helper (src/lib.rs:22)`
+    fn call_string(&self, ctrl: &SPDG, at: CallString) -> String {
+        self.function_names(ctrl, at)
+            .into_iter()
+            .zip(at.iter_from_root())
+            .map(|(name, loc)| match (loc.location, self.location(loc)) {
+                (RichLocation::Start, _) => format!("{name} (start)"),
+                (RichLocation::End, _) => format!("{name} (end)"),
+                (RichLocation::Location(_), Some(src)) => format!("{name} ({src})"),
+                (RichLocation::Location(l), None) => {
+                    format!("{name} (bb{}[{}])", l.block.index(), l.statement_index)
+                }
+            })
+            .join(" -> ")
+    }
 
-        let write_label = || {
-            use std::fmt::Write;
-            let mut s = String::new();
+    fn node(&self, out: &mut impl Write, record: &NodeRecord, indent: &str) -> io::Result<()> {
+        let info = record.info;
+        let mut label = format!("{}\n{}\n", info.description, info.kind);
+        if !record.markers.is_empty() {
+            label.push_str(&format!("[{}]\n", record.markers.iter().join(", ")));
+        }
+        label.push_str(&self.call_string(record.controller, info.at));
+        label.push('\n');
+        let fill = record
+            .markers
+            .first()
+            .map_or("white", |m| self.marker_colors[m]);
+        writeln!(
+            out,
+            "{indent}n{} [label=\"{}\", shape={}, fillcolor=\"{fill}\"];",
+            info.stable_id,
+            escape(&label),
+            node_shape(info.kind),
+        )
+    }
 
-            write!(s, "{}|", self.format_call_string(*n))?;
+    fn controller(
+        &self,
+        out: &mut impl Write,
+        index: usize,
+        id: LocalDefId,
+        ctrl: &SPDG,
+        filter: &DotFilter,
+    ) -> io::Result<()> {
+        let records = node_records(self.desc, ctrl).collect::<Vec<_>>();
+        let retained = filter.retained(id, ctrl, &records);
+        if retained.is_empty() {
+            return Ok(());
+        }
+        // Group by leaf function in order of first occurrence
+        let by_function = records
+            .iter()
+            .filter(|r| retained.contains(&r.node))
+            .into_group_map_by(|r| r.info.at.leaf().function);
+        let functions = records
+            .iter()
+            .filter(|r| retained.contains(&r.node))
+            .map(|r| r.info.at.leaf().function)
+            .unique()
+            .collect::<Vec<_>>();
 
-            match instruction.kind {
-                InstructionKind::Statement => s.push('S'),
-                InstructionKind::FunctionCall(function) => {
-                    let info = &self.spdg.def_info[&function.id];
-                    write!(s, "{}", info.name)?
-                }
-                InstructionKind::Terminator => s.push('T'),
-                InstructionKind::Start => {
-                    s.push('*');
-                }
-                InstructionKind::Return => s.push_str("end"),
+        writeln!(out, "  subgraph cluster_{index} {{")?;
+        writeln!(out, "    label=\"{}\";", escape(ctrl.name.as_str()))?;
+        writeln!(out, "    style=bold;")?;
+        for (fn_index, function) in functions.iter().enumerate() {
+            let members = &by_function[function];
+            let at = members[0].info.at;
+            let name = self.function_names(ctrl, at).pop().unwrap();
+            let start = GlobalLocation {
+                function: *function,
+                location: RichLocation::Start,
             };
-
-            for &n in nodes {
-                let weight = ctrl.graph.node_weight(n).unwrap();
-                let markers = ctrl.markers.get(&n).into_iter().flatten();
-                let type_markers = ctrl
-                    .type_assigns
-                    .get(&n)
-                    .into_iter()
-                    .flat_map(|typ| typ.0.iter().flat_map(|t| &self.spdg.type_info[t].markers));
-                let mut all_markers = markers.chain(type_markers).copied().peekable();
-                let write_id_and_desc = |s: &mut String| {
-                    let idx = n.index();
-                    let desc = weight.description.replace('<', "&lt;").replace('>', "&gt;");
-                    write!(s, "<p{idx}> ({idx}) {desc}")
-                };
-                s.push('|');
-                if all_markers.peek().is_some() {
-                    s.push('{');
-                    write_id_and_desc(&mut s)?;
-                    for m in all_markers {
-                        write!(s, "| {m}")?;
-                    }
-                    s.push('}');
-                } else {
-                    write_id_and_desc(&mut s)?;
-                }
+            let label = match self.location(start) {
+                Some(src) => format!("{name} ({src})"),
+                None => name,
+            };
+            writeln!(out, "    subgraph cluster_{index}_{fn_index} {{")?;
+            writeln!(out, "      label=\"{}\";", escape(&label))?;
+            writeln!(out, "      style=\"rounded,dashed\";")?;
+            for record in members {
+                self.node(out, record, "      ")?;
             }
+            writeln!(out, "    }}")?;
+        }
+        writeln!(out, "  }}")?;
 
-            Ok::<_, std::fmt::Error>(s)
-        };
-
-        LabelText::LabelStr(write_label().unwrap().into())
-    }
-
-    fn edge_color(&'a self, e: &GlobalEdge) -> Option<dot::LabelText<'a>> {
-        let weight = self.spdg.controllers[&e.controller_id]
-            .graph
-            .edge_weight(e.index)
-            .unwrap();
-        weight
-            .is_control()
-            .then(|| LabelText::LabelStr("aqua".into()))
-    }
-
-    fn source_port_position(
-        &'a self,
-        edge: &GlobalEdge,
-    ) -> (Option<dot::Id<'a>>, Option<dot::CompassPoint>) {
-        let ctrl = &self.spdg.controllers[&edge.controller_id].graph;
-        let (from, _) = ctrl.edge_endpoints(edge.index).unwrap();
-        (Some(Id::new(format!("p{}", from.index())).unwrap()), None)
+        for e in ctrl.edges() {
+            if !filter.edge_selection.conforms(e.weight().kind)
+                || !retained.contains(&e.source())
+                || !retained.contains(&e.target())
+            {
+                continue;
+            }
+            let style = if e.weight().is_control() {
+                " [style=dashed, color=aqua]"
+            } else {
+                ""
+            };
+            writeln!(
+                out,
+                "  n{} -> n{}{style};",
+                ctrl.node_info(e.source()).stable_id,
+                ctrl.node_info(e.target()).stable_id,
+            )?;
+        }
+        Ok(())
     }
+}
 
-    fn target_port_position(&'a self, edge: &GlobalEdge) -> (Option<Id<'a>>, Option<CompassPoint>) {
-        let ctrl = &self.spdg.controllers[&edge.controller_id].graph;
-        let (_, to) = ctrl.edge_endpoints(edge.index).unwrap();
-        (Some(Id::new(format!("p{}", to.index())).unwrap()), None)
+/// Dump the parts of `spdg` selected by `filter` in a single dot expression.
+pub fn dump_filtered(
+    spdg: &ProgramDescription,
+    mut out: impl Write,
+    filter: &DotFilter,
+) -> io::Result<()> {
+    let marker_colors = spdg
+        .controllers
+        .values()
+        .flat_map(|ctrl| ctrl.markers.values().flatten())
+        .chain(spdg.type_info.values().flat_map(|info| &info.markers))
+        .copied()
+        .sorted()
+        .dedup()
+        .zip(MARKER_COLORS.iter().copied().cycle())
+        .collect();
+    let renderer = Renderer {
+        desc: spdg,
+        marker_colors,
+    };
+    writeln!(out, "digraph spdg {{")?;
+    writeln!(out, "  compound=true;")?;
+    writeln!(
+        out,
+        "  node [style=filled, fontname=\"monospace\", fontsize=10];"
+    )?;
+    for (index, (id, ctrl)) in sorted_controllers(spdg)
+        .into_iter()
+        .filter(|(id, _)| filter.selects_controller(*id))
+        .enumerate()
+    {
+        renderer.controller(&mut out, index, id, ctrl, filter)?;
     }
+    writeln!(out, "}}")?;
+    out.flush()
 }
 
 /// Dump all SPDGs in a single dot expression
 pub fn dump<W: std::io::Write>(spdg: &ProgramDescription, out: W) -> std::io::Result<()> {
-    dump_for_selection(spdg, out, |_| true)
+    dump_filtered(spdg, out, &DotFilter::default())
 }
 
 /// Dump the SPDG for one select controller in dot format
@@ -199,27 +354,158 @@ pub fn dump_for_controller(
     out: impl std::io::Write,
     controller_id: LocalDefId,
 ) -> std::io::Result<()> {
-    let mut found = false;
-    dump_for_selection(spdg, out, |l| {
-        let is_target = l == controller_id;
-        if is_target {
-            found = true;
-        }
-        is_target
-    })?;
     assert!(
-        found,
+        spdg.controllers.contains_key(&controller_id),
         "No such controller was found in the program description"
     );
-    Ok(())
+    dump_filtered(
+        spdg,
+        out,
+        &DotFilter {
+            controllers: Some(vec![controller_id]),
+            ..Default::default()
+        },
+    )
 }
 
 /// Dump a selection of controllers into a dot expression.
 pub fn dump_for_selection(
     spdg: &ProgramDescription,
-    mut out: impl std::io::Write,
-    selector: impl FnMut(LocalDefId) -> bool,
+    out: impl std::io::Write,
+    mut selector: impl FnMut(LocalDefId) -> bool,
 ) -> std::io::Result<()> {
-    let printable = DotPrintableProgramDescription::new_for_selection(spdg, selector);
-    dot::render(&printable, &mut out)
+    let controllers = spdg
+        .controllers
+        .keys()
+        .copied()
+        .filter(|l| selector(*l))
+        .collect();
+    dump_filtered(
+        spdg,
+        out,
+        &DotFilter {
+            controllers: Some(controllers),
+            ..Default::default()
+        },
+    )
+}
+
+#[test]
+fn dot_strings_are_escaped() {
+    assert_eq!(escape("a \"b\"\\\nc"), "a \\\"b\\\"\\\\\\lc");
+}
+
+#[cfg(test)]
+fn render_example(filter: &DotFilter) -> String {
+    let mut out = vec![];
+    dump_filtered(&crate::test_utils::example_description(), &mut out, filter).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+#[cfg(test)]
+fn example_node_id(description: &str) -> crate::StableNodeId {
+    crate::StableNodeId::from_parts(["main", description])
+}
+
+#[test]
+fn dot_renders_clusters_and_marker_colors() {
+    let dot = render_example(&DotFilter::default());
+    assert!(dot.contains("subgraph cluster_0 {\n    label=\"main\";"));
+    assert!(dot.contains("subgraph cluster_0_0 {\n      label=\"main (src/main.rs:1)\";"));
+    let node_line = |description| {
+        let prefix = format!("      n{} [", example_node_id(description));
+        dot.lines().find(|l| l.starts_with(&prefix)).unwrap()
+    };
+    // Colors are assigned in marker order: secret, sink, source
+    let input = node_line("input");
+    assert!(input.contains("[secret, source]"));
+    assert!(input.ends_with("shape=invhouse, fillcolor=\"#8dd3c7\"];"));
+    let output = node_line("output");
+    assert!(output.ends_with("shape=house, fillcolor=\"#ffffb3\"];"));
+    let checked = node_line("checked");
+    assert!(checked.contains("main (src/main.rs:2)"));
+    assert!(checked.ends_with("fillcolor=\"white\"];"));
+    assert_eq!(dot.matches(" -> n").count(), 3);
+    assert_eq!(dot.matches("[style=dashed, color=aqua]").count(), 1);
+}
+
+#[test]
+fn dot_filters_select_nodes() {
+    let rendered = |filter: DotFilter| {
+        let dot = render_example(&filter);
+        ["input", "checked", "output"]
+            .into_iter()
+            .filter(|d| dot.contains(&format!("n{} [", example_node_id(d))))
+            .collect::<Vec<_>>()
+    };
+    let sink = || vec![Identifier::new_intern("sink")];
+    assert_eq!(
+        rendered(DotFilter {
+            markers: sink(),
+            depth: Some(0),
+            ..Default::default()
+        }),
+        ["output"]
+    );
+    assert_eq!(
+        rendered(DotFilter {
+            markers: sink(),
+            direction: SliceDirection::Backward,
+            edge_selection: EdgeSelection::Data,
+            depth: Some(1),
+            ..Default::default()
+        }),
+        ["checked", "output"]
+    );
+    assert_eq!(
+        rendered(DotFilter {
+            markers: sink(),
+            direction: SliceDirection::Backward,
+            edge_selection: EdgeSelection::Data,
+            ..Default::default()
+        }),
+        ["input", "checked", "output"]
+    );
+    assert_eq!(
+        rendered(DotFilter {
+            markers: vec![Identifier::new_intern("source")],
+            direction: SliceDirection::Forward,
+            edge_selection: EdgeSelection::Control,
+            ..Default::default()
+        }),
+        ["input", "output"]
+    );
+    // `secret` is only attached through the type of `input`
+    assert_eq!(
+        rendered(DotFilter {
+            markers: vec![Identifier::new_intern("secret")],
+            direction: SliceDirection::Forward,
+            edge_selection: EdgeSelection::Data,
+            depth: Some(1),
+            ..Default::default()
+        }),
+        ["input", "checked"]
+    );
+    let main = crate::test_utils::local_def_id(crate::test_utils::EXAMPLE_CONTROLLER);
+    assert_eq!(
+        rendered(DotFilter {
+            nodes: vec![GlobalNode::from_local_node(main, Node::new(2))],
+            direction: SliceDirection::Forward,
+            ..Default::default()
+        }),
+        ["output"]
+    );
+    assert!(rendered(DotFilter {
+        controllers: Some(vec![crate::test_utils::local_def_id(1)]),
+        ..Default::default()
+    })
+    .is_empty());
+    assert_eq!(
+        rendered(DotFilter {
+            controllers: Some(vec![main]),
+            ..Default::default()
+        })
+        .len(),
+        3
+    );
 }
@@ -0,0 +1,199 @@
+//! Storing source code in the [`ProgramDescription`].
+//!
+//! Rendering a [`Span`] needs the text of the file it points into. Normally
+//! that text is read from [`SourceFileInfo::abs_file_path`], which only works
+//! on the machine that ran the extractor. [`ProgramDescription::embed_sources`]
+//! copies the text into [`ProgramDescription::sources`] so that an artifact
+//! can be checked and rendered elsewhere, for instance in a CI job that only
+//! receives the graph file. [`ProgramDescription::source_lines`] prefers the
+//! embedded text and only falls back to the file system if it is missing. A
+//! [`SourceReader`] does the same, but reads each file only once, for
+//! rendering many spans at a time.
+//!
+//! [`SourceFileInfo::abs_file_path`]: crate::SourceFileInfo::abs_file_path
+
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::ops::RangeInclusive;
+use std::rc::Rc;
+
+use crate::{EmbeddedSource, HashMap, ProgramDescription, SourceFile, Span};
+
+/// How much source code [`ProgramDescription::embed_sources`] stores.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, strum::EnumIs)]
+pub enum EmbedMode {
+    /// Only the lines covered by at least one span
+    Lines,
+    /// Each referenced file in its entirety
+    Files,
+}
+
+fn read_lines(file: SourceFile) -> Option<Rc<[String]>> {
+    let content = std::fs::read_to_string(&file.abs_file_path).ok()?;
+    Some(content.lines().map(str::to_owned).collect())
+}
+
+/// Source lines of the spans in a [`ProgramDescription`], created with
+/// [`ProgramDescription::source_reader`].
+///
+/// Files that are not embedded are read from disk on first use and kept until
+/// the reader is dropped. Use one reader per rendering pass, e.g. per report,
+/// so that changed files are read again next time.
+pub struct SourceReader<'a> {
+    desc: &'a ProgramDescription,
+    disk: RefCell<HashMap<SourceFile, Option<Rc<[String]>>>>,
+}
+
+impl<'a> SourceReader<'a> {
+    /// All lines of `file` as read from disk, `None` if it cannot be read.
+    pub(crate) fn disk_lines(&self, file: SourceFile) -> Option<Rc<[String]>> {
+        self.disk
+            .borrow_mut()
+            .entry(file)
+            .or_insert_with(|| read_lines(file))
+            .clone()
+    }
+
+    /// The text of `lines` (1-based, inclusive) of `file`, see
+    /// [`ProgramDescription::source_lines`].
+    pub fn lines(&self, file: SourceFile, lines: RangeInclusive<u32>) -> Option<Vec<Cow<'a, str>>> {
+        if let Some(embedded) = self.desc.sources.get(&file) {
+            let found = lines
+                .clone()
+                .map(|l| embedded.lines.get(&l).map(|s| Cow::Borrowed(s.as_str())))
+                .collect::<Option<Vec<_>>>();
+            if found.is_some() {
+                return found;
+            }
+        }
+        let all = self.disk_lines(file)?;
+        lines
+            .map(|l| {
+                all.get((l as usize).checked_sub(1)?)
+                    .map(|s| Cow::Owned(s.clone()))
+            })
+            .collect()
+    }
+}
+
+impl ProgramDescription {
+    /// All spans mentioned in this description: those of nodes,
+    /// instructions and definitions.
+    fn referenced_spans(&self) -> impl Iterator<Item = &Span> {
+        self.controllers
+            .values()
+            .flat_map(|ctrl| ctrl.graph.node_weights().map(|n| &n.span))
+            .chain(self.instruction_info.values().map(|i| &i.span))
+            .chain(self.def_info.values().map(|d| &d.src_info))
+    }
+
+    /// Read the files that spans in this description point into and store
+    /// their text in [`Self::sources`], replacing any previously embedded
+    /// text of the same file.
+    ///
+    /// Files that cannot be read are skipped and returned, so the caller can
+    /// decide whether that is worth a warning.
+    pub fn embed_sources(&mut self, mode: EmbedMode) -> Vec<SourceFile> {
+        let mut covered: HashMap<SourceFile, Vec<RangeInclusive<u32>>> = HashMap::new();
+        for span in self.referenced_spans() {
+            covered
+                .entry(span.source_file)
+                .or_default()
+                .push(span.start.line..=span.end.line);
+        }
+        let mut unreadable = vec![];
+        for (file, ranges) in covered {
+            let Ok(content) = std::fs::read_to_string(&file.abs_file_path) else {
+                unreadable.push(file);
+                continue;
+            };
+            let all_lines = (1..).zip(content.lines());
+            let lines = if mode.is_files() {
+                all_lines.map(|(n, l)| (n, l.to_owned())).collect()
+            } else {
+                all_lines
+                    .filter(|(n, _)| ranges.iter().any(|r| r.contains(n)))
+                    .map(|(n, l)| (n, l.to_owned()))
+                    .collect()
+            };
+            self.sources.insert(file, EmbeddedSource { lines });
+        }
+        unreadable.sort_by(|a, b| a.file_path.cmp(&b.file_path));
+        unreadable
+    }
+
+    /// The text of `lines` (1-based, inclusive) of `file`.
+    ///
+    /// Uses the embedded text if it contains all requested lines, otherwise
+    /// reads the file from disk. Returns `None` if neither is possible. Use
+    /// [`Self::source_reader`] to look up many spans without reading a file
+    /// more than once.
+    pub fn source_lines(
+        &self,
+        file: SourceFile,
+        lines: RangeInclusive<u32>,
+    ) -> Option<Vec<Cow<'_, str>>> {
+        self.source_reader().lines(file, lines)
+    }
+
+    /// A [`SourceReader`] for the spans in this description.
+    pub fn source_reader(&self) -> SourceReader<'_> {
+        SourceReader {
+            desc: self,
+            disk: Default::default(),
+        }
+    }
+}
+
+#[test]
+fn embedded_lines_are_preferred() {
+    use crate::SourceFileInfo;
+    let mut desc = ProgramDescription::default();
+    let file = SourceFileInfo {
+        file_path: "src/does_not_exist.rs".to_owned(),
+        abs_file_path: "/nonexistent/src/does_not_exist.rs".into(),
+    }
+    .intern();
+    assert_eq!(desc.source_lines(file, 1..=1), None);
+    assert_eq!(desc.embed_sources(EmbedMode::Files), vec![]);
+
+    desc.sources.insert(
+        file,
+        EmbeddedSource {
+            lines: [(2, "let x = 1;".to_owned()), (3, "x".to_owned())].into(),
+        },
+    );
+    assert_eq!(
+        desc.source_lines(file, 2..=3).unwrap(),
+        vec!["let x = 1;", "x"]
+    );
+    assert_eq!(desc.source_lines(file, 1..=2), None);
+}
+
+#[test]
+fn readers_read_files_once() {
+    use crate::SourceFileInfo;
+    let path = std::env::temp_dir().join(format!("paralegal-embed-{}.rs", std::process::id()));
+    std::fs::write(&path, "fn main() {\n    let x = 1;\n}\n").unwrap();
+    let file = SourceFileInfo {
+        file_path: "src/main.rs".to_owned(),
+        abs_file_path: path.clone(),
+    }
+    .intern();
+    let desc = ProgramDescription::default();
+    let reader = desc.source_reader();
+    assert_eq!(reader.lines(file, 2..=2).unwrap(), vec!["    let x = 1;"]);
+    std::fs::write(&path, "fn changed() {}\n").unwrap();
+    // The reader keeps what it read, a new one sees the change
+    assert_eq!(
+        reader.lines(file, 1..=3).unwrap(),
+        vec!["fn main() {", "    let x = 1;", "}"]
+    );
+    assert_eq!(reader.lines(file, 3..=4), None);
+    assert_eq!(
+        desc.source_reader().lines(file, 1..=1).unwrap(),
+        vec!["fn changed() {}"]
+    );
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(desc.source_lines(file, 1..=1), None);
+}
@@ -0,0 +1,194 @@
+//! Relations for Datalog engines.
+//!
+//! [`Facts::new`] extracts the following relations from a
+//! [`ProgramDescription`]:
+//!
+//! | Relation                                    | Meaning                                          |
+//! |---------------------------------------------|--------------------------------------------------|
+//! | `node(n, controller, description)`          | `n` is a node of `controller`                    |
+//! | `data_edge(from, to)`                       | Data flows from `from` to `to`                   |
+//! | `ctrl_edge(from, to)`                       | `from` controls whether `to` happens             |
+//! | `marked(n, marker)`                         | `n` carries `marker` directly or via its type    |
+//! | `has_type(n, type)`                         | `type` was assigned to `n`                       |
+//! | `otype(type, alias)`                        | `alias` is an output type of `type`              |
+//! | `arg_of_controller(n, controller, index)`   | `n` is the `index`th argument of `controller`    |
+//! | `call_site(n, at, function)`                | `n` occurs at the call string `at` of `function` |
+//!
+//! Nodes are identified by their [`StableNodeId`](crate::StableNodeId), types
+//! and functions by their def path and controllers by their name.
+//!
+//! The tuples can be fed directly into an embedded engine like Crepe, or
+//! written as tab separated `<relation>.facts` files for Soufflé with
+//! [`Facts::write_to_dir`]. [`RULES`] is a Soufflé library that declares these
+//! relations and defines `flows_to`, `has_ctrl_influence` and
+//! `always_happens_before` on top of them.
+
+use std::{
+    fmt::Display,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use itertools::Itertools;
+use petgraph::visit::EdgeRef;
+
+use super::{def_path, node_records, sorted_controllers};
+use crate::{EdgeKind, ProgramDescription};
+
+/// Soufflé declarations of the exported relations and the standard rules.
+pub const RULES: &str = include_str!("paralegal.dl");
+
+/// The relations extracted from a [`ProgramDescription`], see the
+/// [module level documentation](self) for their meaning.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Facts {
+    /// `node(n, controller, description)`
+    pub node: Vec<(String, String, String)>,
+    /// `data_edge(from, to)`
+    pub data_edge: Vec<(String, String)>,
+    /// `ctrl_edge(from, to)`
+    pub ctrl_edge: Vec<(String, String)>,
+    /// `marked(n, marker)`
+    pub marked: Vec<(String, String)>,
+    /// `has_type(n, type)`
+    pub has_type: Vec<(String, String)>,
+    /// `otype(type, alias)`
+    pub otype: Vec<(String, String)>,
+    /// `arg_of_controller(n, controller, index)`
+    pub arg_of_controller: Vec<(String, String, u32)>,
+    /// `call_site(n, at, function)`
+    pub call_site: Vec<(String, String, String)>,
+}
+
+impl Facts {
+    /// Extract all relations from `desc`. The tuples of each relation are
+    /// sorted, so the result is deterministic.
+    pub fn new(desc: &ProgramDescription) -> Self {
+        let mut facts = Facts::default();
+        for (_, ctrl) in sorted_controllers(desc) {
+            let controller = ctrl.name.to_string();
+            let id = |n| ctrl.node_info(n).stable_id.to_string();
+            for node in node_records(desc, ctrl) {
+                let n = node.id().to_string();
+                facts
+                    .node
+                    .push((n.clone(), controller.clone(), node.info.description.clone()));
+                facts
+                    .marked
+                    .extend(node.markers.iter().map(|m| (n.clone(), m.to_string())));
+                if let Some(call) = desc
+                    .instruction_info
+                    .get(&node.info.at.leaf())
+                    .and_then(|info| info.kind.as_function_call())
+                {
+                    facts.call_site.push((
+                        n.clone(),
+                        node.info.at.to_string(),
+                        def_path(desc, call.id),
+                    ));
+                }
+            }
+            for (n, types) in &ctrl.type_assigns {
+                facts
+                    .has_type
+                    .extend(types.0.iter().map(|t| (id(*n), def_path(desc, *t))));
+            }
+            for e in ctrl.edges() {
+                let pair = (id(e.source()), id(e.target()));
+                match e.weight().kind {
+                    EdgeKind::Data => facts.data_edge.push(pair),
+                    EdgeKind::Control => facts.ctrl_edge.push(pair),
+                }
+            }
+            facts.arg_of_controller.extend(
+                ctrl.arguments
+                    .iter()
+                    .enumerate()
+                    .map(|(i, n)| (id(*n), controller.clone(), i as u32)),
+            );
+        }
+        for (ty, info) in &desc.type_info {
+            facts.otype.extend(
+                info.otypes
+                    .iter()
+                    .map(|o| (def_path(desc, *ty), def_path(desc, *o))),
+            );
+        }
+        facts.sort();
+        facts
+    }
+
+    fn sort(&mut self) {
+        self.node.sort();
+        self.data_edge.sort();
+        self.ctrl_edge.sort();
+        self.marked.sort();
+        self.marked.dedup();
+        self.has_type.sort();
+        self.has_type.dedup();
+        self.otype.sort();
+        self.arg_of_controller.sort();
+        self.call_site.sort();
+    }
+
+    /// Write one `<relation>.facts` file per relation into `dir`, in the tab
+    /// separated format Soufflé reads by default. Tabs and line breaks inside
+    /// of values are replaced by spaces.
+    pub fn write_to_dir(&self, dir: impl AsRef<Path>) -> io::Result<()> {
+        let dir = dir.as_ref();
+        std::fs::create_dir_all(dir)?;
+        write_relation(dir, "node", &self.node, |(a, b, c)| [a, b, c])?;
+        write_relation(dir, "data_edge", &self.data_edge, |(a, b)| [a, b])?;
+        write_relation(dir, "ctrl_edge", &self.ctrl_edge, |(a, b)| [a, b])?;
+        write_relation(dir, "marked", &self.marked, |(a, b)| [a, b])?;
+        write_relation(dir, "has_type", &self.has_type, |(a, b)| [a, b])?;
+        write_relation(dir, "otype", &self.otype, |(a, b)| [a, b])?;
+        write_relation(
+            dir,
+            "arg_of_controller",
+            &self.arg_of_controller,
+            |(a, b, c)| [a, b, c],
+        )?;
+        write_relation(dir, "call_site", &self.call_site, |(a, b, c)| [a, b, c])
+    }
+}
+
+fn sanitize(value: &dyn Display) -> String {
+    value.to_string().replace(['\t', '\n', '\r'], " ")
+}
+
+fn write_relation<'a, R: 'a, const N: usize>(
+    dir: &Path,
+    name: &str,
+    rows: &'a [R],
+    fields: impl Fn(&'a R) -> [&'a dyn Display; N],
+) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(dir.join(format!("{name}.facts")))?);
+    for row in rows {
+        writeln!(
+            out,
+            "{}",
+            fields(row).iter().map(|f| sanitize(*f)).join("\t")
+        )?;
+    }
+    out.flush()
+}
+
+#[test]
+fn rules_declare_all_relations() {
+    for relation in [
+        "node",
+        "data_edge",
+        "ctrl_edge",
+        "marked",
+        "has_type",
+        "otype",
+        "arg_of_controller",
+        "call_site",
+    ] {
+        assert!(RULES.contains(&format!(".decl {relation}(")));
+        assert!(RULES.contains(&format!(".input {relation}\n")));
+    }
+    assert_eq!(Facts::new(&ProgramDescription::default()), Facts::default());
+}
@@ -0,0 +1,129 @@
+//! Export to [GraphML](http://graphml.graphdrawing.org/).
+//!
+//! All controllers are written into one directed graph. Nodes are identified by
+//! their [`StableNodeId`](crate::StableNodeId) and carry the name of their
+//! controller as an attribute, so viewers can filter or partition by it. Lists
+//! (markers and types) are joined with `", "`.
+
+use std::io::{self, Write};
+
+use itertools::Itertools;
+
+use super::{edge_records, node_records, sorted_controllers};
+use crate::ProgramDescription;
+
+/// Attribute keys as `(id, domain, attr.name)`.
+const KEYS: &[(&str, &str, &str)] = &[
+    ("label", "node", "label"),
+    ("controller", "node", "controller"),
+    ("kind", "node", "kind"),
+    ("at", "node", "at"),
+    ("span", "node", "span"),
+    ("markers", "node", "markers"),
+    ("types", "node", "types"),
+    ("edge_kind", "edge", "kind"),
+    ("edge_at", "edge", "at"),
+];
+
+/// Escape `s` for use in XML text and attribute values.
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn data(out: &mut impl Write, key: &str, value: &str) -> io::Result<()> {
+    writeln!(out, "      <data key=\"{key}\">{}</data>", escape(value))
+}
+
+/// Write `desc` as a GraphML document.
+pub fn write(desc: &ProgramDescription, mut out: impl Write) -> io::Result<()> {
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        out,
+        r#"<graphml xmlns="http://graphml.graphdrawing.org/xmlns" xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance" xsi:schemaLocation="http://graphml.graphdrawing.org/xmlns http://graphml.graphdrawing.org/xmlns/1.0/graphml.xsd">"#
+    )?;
+    for (id, domain, name) in KEYS {
+        writeln!(
+            out,
+            r#"  <key id="{id}" for="{domain}" attr.name="{name}" attr.type="string"/>"#
+        )?;
+    }
+    writeln!(out, r#"  <graph id="spdg" edgedefault="directed">"#)?;
+    let controllers = sorted_controllers(desc);
+    for (_, ctrl) in &controllers {
+        for node in node_records(desc, ctrl) {
+            writeln!(out, r#"    <node id="n{}">"#, node.id())?;
+            data(&mut out, "label", &node.info.description)?;
+            data(&mut out, "controller", node.controller.name.as_str())?;
+            data(&mut out, "kind", &node.info.kind.to_string())?;
+            data(&mut out, "at", &node.info.at.to_string())?;
+            data(&mut out, "span", &node.span())?;
+            data(&mut out, "markers", &node.markers.iter().join(", "))?;
+            data(&mut out, "types", &node.types.join(", "))?;
+            writeln!(out, "    </node>")?;
+        }
+    }
+    for (_, ctrl) in &controllers {
+        for edge in edge_records(ctrl) {
+            writeln!(
+                out,
+                r#"    <edge source="n{}" target="n{}">"#,
+                edge.from, edge.to
+            )?;
+            data(&mut out, "edge_kind", &edge.info.kind.to_string())?;
+            data(&mut out, "edge_at", &edge.info.at.to_string())?;
+            writeln!(out, "    </edge>")?;
+        }
+    }
+    writeln!(out, "  </graph>")?;
+    writeln!(out, "</graphml>")?;
+    out.flush()
+}
+
+#[test]
+fn graphml_is_well_formed_for_empty_description() {
+    let mut buf = vec![];
+    write(&ProgramDescription::default(), &mut buf).unwrap();
+    let s = String::from_utf8(buf).unwrap();
+    assert!(s.starts_with("<?xml"));
+    assert!(s.trim_end().ends_with("</graphml>"));
+    assert_eq!(s.matches("<key ").count(), KEYS.len());
+}
+
+#[test]
+fn graphml_contains_nodes_and_edges() {
+    let desc = crate::test_utils::example_description();
+    let mut buf = vec![];
+    write(&desc, &mut buf).unwrap();
+    let s = String::from_utf8(buf).unwrap();
+    assert_eq!(s.matches("<node ").count(), 3);
+    assert_eq!(s.matches("<edge ").count(), 3);
+    let input = crate::StableNodeId::from_parts(["main", "input"]);
+    assert!(s.contains(&format!(r#"<node id="n{input}">"#)));
+    assert!(s.contains(&format!(r#"<edge source="n{input}" "#)));
+    assert!(s.contains(r#"<data key="markers">secret, source</data>"#));
+    assert!(s.contains(r#"<data key="types">Secret</data>"#));
+    assert!(s.contains(r#"<data key="controller">main</data>"#));
+    assert_eq!(
+        s.matches(r#"<data key="edge_kind">Control</data>"#).count(),
+        1
+    );
+}
+
+#[test]
+fn xml_special_characters_are_escaped() {
+    assert_eq!(
+        escape(r#"<Vec<&'a str> as "T">"#),
+        "&lt;Vec&lt;&amp;&apos;a str&gt; as &quot;T&quot;&gt;"
+    );
+}
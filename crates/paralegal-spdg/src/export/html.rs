@@ -0,0 +1,367 @@
+//! A self-contained, interactive HTML report.
+//!
+//! [`write`] produces a single HTML file with the graph data, styles and
+//! scripts inlined, so the page works offline and can be sent around as is.
+//! The page draws each controller as a layered graph that can be zoomed
+//! (mouse wheel) and panned (drag). Clicking a node shows its [`NodeInfo`],
+//! markers, types and the source code of its span. The search box highlights
+//! nodes whose description or markers contain the query.
+//!
+//! Source snippets are taken from the sources embedded in the description, or
+//! read from disk when the report is generated. If neither is available the
+//! report simply shows no snippet for the span.
+//!
+//! [`NodeInfo`]: crate::NodeInfo
+
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+use itertools::Itertools;
+use petgraph::visit::EdgeRef;
+use petgraph::Graph;
+use serde::Serialize;
+
+use super::{node_records, sorted_controllers};
+use crate::{embed::SourceReader, rustc_portable::LocalDefId, ProgramDescription, Span, SPDG};
+
+const TEMPLATE: &str = include_str!("report.html");
+const DATA_PLACEHOLDER: &str = "/*@REPORT_DATA@*/null";
+
+/// Horizontal distance between two nodes in a layer
+const X_SPACING: usize = 240;
+/// Vertical distance between two layers
+const Y_SPACING: usize = 120;
+
+/// Lines of context shown around a span
+const CONTEXT_LINES: u32 = 2;
+
+#[derive(Serialize)]
+struct Report {
+    title: String,
+    controllers: Vec<ControllerView>,
+    /// Per file the lines (1-based) that are needed to show the spans
+    sources: BTreeMap<String, BTreeMap<u32, String>>,
+}
+
+#[derive(Serialize)]
+struct ControllerView {
+    name: String,
+    nodes: Vec<NodeView>,
+    edges: Vec<EdgeView>,
+}
+
+#[derive(Serialize)]
+struct NodeView {
+    id: String,
+    x: usize,
+    y: usize,
+    description: String,
+    kind: String,
+    at: String,
+    span: SpanView,
+    markers: Vec<String>,
+    types: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct SpanView {
+    file: String,
+    start_line: u32,
+    start_col: u32,
+    end_line: u32,
+    end_col: u32,
+}
+
+#[derive(Serialize)]
+struct EdgeView {
+    /// Index into the nodes of the controller
+    from: usize,
+    to: usize,
+    kind: String,
+    at: String,
+}
+
+/// Assign each node a `(layer, position)` such that edges mostly point
+/// downwards.
+///
+/// Back edges found by a depth first search are ignored, the remaining graph
+/// is layered by longest path and the nodes within a layer are ordered by the
+/// average position of their predecessors.
+fn layout<N, E>(graph: &Graph<N, E>) -> Vec<(usize, usize)> {
+    let n = graph.node_count();
+    // Iterative DFS to classify back edges: 0 = unvisited, 1 = on stack, 2 = done
+    let mut state = vec![0u8; n];
+    let mut back_edges = vec![false; graph.edge_count()];
+    for root in graph.node_indices() {
+        if state[root.index()] != 0 {
+            continue;
+        }
+        state[root.index()] = 1;
+        let mut stack = vec![(root, graph.edges(root).collect::<Vec<_>>().into_iter())];
+        while let Some((node, edges)) = stack.last_mut() {
+            let node = *node;
+            if let Some(e) = edges.next() {
+                match state[e.target().index()] {
+                    0 => {
+                        state[e.target().index()] = 1;
+                        let next = graph.edges(e.target()).collect::<Vec<_>>().into_iter();
+                        stack.push((e.target(), next));
+                    }
+                    1 => back_edges[e.id().index()] = true,
+                    _ => (),
+                }
+            } else {
+                state[node.index()] = 2;
+                stack.pop();
+            }
+        }
+    }
+
+    // Longest path layering via Kahn's algorithm on the remaining DAG
+    let forward = |e: &petgraph::graph::EdgeReference<E>| !back_edges[e.id().index()];
+    let mut in_degree = vec![0usize; n];
+    for e in graph.edge_references().filter(forward) {
+        in_degree[e.target().index()] += 1;
+    }
+    let mut layer = vec![0usize; n];
+    let mut ready = graph
+        .node_indices()
+        .filter(|i| in_degree[i.index()] == 0)
+        .collect::<Vec<_>>();
+    let mut topological = Vec::with_capacity(n);
+    while let Some(node) = ready.pop() {
+        topological.push(node);
+        for e in graph.edges(node).filter(forward) {
+            let t = e.target().index();
+            layer[t] = layer[t].max(layer[node.index()] + 1);
+            in_degree[t] -= 1;
+            if in_degree[t] == 0 {
+                ready.push(e.target());
+            }
+        }
+    }
+
+    // Order each layer by the barycenter of the predecessors in earlier layers
+    let mut position = vec![0usize; n];
+    let mut layers: BTreeMap<usize, Vec<_>> = BTreeMap::new();
+    for node in graph.node_indices() {
+        layers.entry(layer[node.index()]).or_default().push(node);
+    }
+    for members in layers.values_mut() {
+        let barycenter = |node: &petgraph::graph::NodeIndex| {
+            let preds = graph
+                .edges_directed(*node, petgraph::Direction::Incoming)
+                .filter(forward)
+                .map(|e| position[e.source().index()] as f64)
+                .collect::<Vec<_>>();
+            if preds.is_empty() {
+                node.index() as f64
+            } else {
+                preds.iter().sum::<f64>() / preds.len() as f64
+            }
+        };
+        let keys = members.iter().map(barycenter).collect::<Vec<_>>();
+        let mut order = (0..members.len()).collect::<Vec<_>>();
+        order.sort_by(|a, b| keys[*a].total_cmp(&keys[*b]).then(a.cmp(b)));
+        *members = order.into_iter().map(|i| members[i]).collect();
+        for (pos, node) in members.iter().enumerate() {
+            position[node.index()] = pos;
+        }
+    }
+    (0..n).map(|i| (layer[i], position[i])).collect()
+}
+
+fn span_view(span: &Span) -> SpanView {
+    SpanView {
+        file: span.source_file.file_path.clone(),
+        start_line: span.start.line,
+        start_col: span.start.col,
+        end_line: span.end.line,
+        end_col: span.end.col,
+    }
+}
+
+/// Collects the source lines needed for the spans of the report.
+struct SourceCollector<'a> {
+    desc: &'a ProgramDescription,
+    reader: SourceReader<'a>,
+    needed: BTreeMap<String, BTreeMap<u32, String>>,
+}
+
+impl<'a> SourceCollector<'a> {
+    fn new(desc: &'a ProgramDescription) -> Self {
+        Self {
+            desc,
+            reader: desc.source_reader(),
+            needed: Default::default(),
+        }
+    }
+
+    fn add(&mut self, span: &Span) {
+        let needed = self
+            .needed
+            .entry(span.source_file.file_path.clone())
+            .or_default();
+        let first = span.start.line.saturating_sub(CONTEXT_LINES).max(1);
+        let last = span.end.line + CONTEXT_LINES;
+        if let Some(embedded) = self.desc.sources.get(&span.source_file) {
+            needed.extend(
+                embedded
+                    .lines
+                    .range(first..=last)
+                    .map(|(n, l)| (*n, l.clone())),
+            );
+            return;
+        }
+        let Some(lines) = self.reader.disk_lines(span.source_file) else {
+            return;
+        };
+        for line in first..=last.min(lines.len() as u32) {
+            needed
+                .entry(line)
+                .or_insert_with(|| lines[line as usize - 1].clone());
+        }
+    }
+}
+
+fn controller_view(
+    desc: &ProgramDescription,
+    ctrl: &SPDG,
+    sources: &mut SourceCollector<'_>,
+) -> ControllerView {
+    let coordinates = layout(&ctrl.graph);
+    let nodes = node_records(desc, ctrl)
+        .map(|record| {
+            let (layer, position) = coordinates[record.node.index()];
+            sources.add(&record.info.span);
+            NodeView {
+                id: record.id().to_string(),
+                x: position * X_SPACING,
+                y: layer * Y_SPACING,
+                description: record.info.description.clone(),
+                kind: record.info.kind.to_string(),
+                at: record.info.at.to_string(),
+                span: span_view(&record.info.span),
+                markers: record.markers.iter().map(ToString::to_string).collect(),
+                types: record.types,
+            }
+        })
+        .collect();
+    let edges = ctrl
+        .edges()
+        .map(|e| EdgeView {
+            from: e.source().index(),
+            to: e.target().index(),
+            kind: e.weight().kind.to_string(),
+            at: e.weight().at.to_string(),
+        })
+        .collect();
+    ControllerView {
+        name: ctrl.name.to_string(),
+        nodes,
+        edges,
+    }
+}
+
+/// Write a report for all controllers.
+pub fn write(desc: &ProgramDescription, out: impl Write) -> io::Result<()> {
+    write_for_selection(desc, out, |_| true)
+}
+
+/// Write a report for the controllers accepted by `selector`.
+pub fn write_for_selection(
+    desc: &ProgramDescription,
+    mut out: impl Write,
+    mut selector: impl FnMut(LocalDefId) -> bool,
+) -> io::Result<()> {
+    let mut sources = SourceCollector::new(desc);
+    let controllers = sorted_controllers(desc)
+        .into_iter()
+        .filter(|(id, _)| selector(*id))
+        .map(|(_, ctrl)| controller_view(desc, ctrl, &mut sources))
+        .collect::<Vec<_>>();
+    let report = Report {
+        title: controllers.iter().map(|c| c.name.as_str()).join(", "),
+        controllers,
+        sources: sources.needed,
+    };
+    // Prevent the data from closing the surrounding script tag
+    let data = serde_json::to_string(&report)?.replace("</", "<\\/");
+    out.write_all(TEMPLATE.replacen(DATA_PLACEHOLDER, &data, 1).as_bytes())?;
+    out.flush()
+}
+
+#[test]
+fn layout_layers_follow_edges() {
+    let mut g = Graph::<(), ()>::new();
+    let [a, b, c, d] = [(); 4].map(|_| g.add_node(()));
+    g.extend_with_edges([(a, b), (a, c), (b, d), (c, d), (d, a)]);
+    let coords = layout(&g);
+    assert_eq!(coords[a.index()].0, 0);
+    assert_eq!(coords[b.index()].0, 1);
+    assert_eq!(coords[c.index()].0, 1);
+    assert_eq!(coords[d.index()].0, 2);
+    assert_ne!(coords[b.index()].1, coords[c.index()].1);
+}
+
+#[test]
+fn report_embeds_data() {
+    let mut buf = vec![];
+    write(&ProgramDescription::default(), &mut buf).unwrap();
+    let html = String::from_utf8(buf).unwrap();
+    assert!(TEMPLATE.contains(DATA_PLACEHOLDER));
+    assert!(!html.contains(DATA_PLACEHOLDER));
+    assert!(html.contains(r#"{"title":"","controllers":[],"sources":{}}"#));
+}
+
+#[test]
+fn report_contains_the_graph_and_sources() {
+    let desc = crate::test_utils::example_description();
+    let mut buf = vec![];
+    write(&desc, &mut buf).unwrap();
+    let html = String::from_utf8(buf).unwrap();
+    let (prefix, suffix) = TEMPLATE.split_once(DATA_PLACEHOLDER).unwrap();
+    let data = &html[prefix.len()..html.len() - suffix.len()];
+    let report: serde_json::Value = serde_json::from_str(data).unwrap();
+
+    assert_eq!(report["title"], "main");
+    let ctrl = &report["controllers"][0];
+    assert_eq!(ctrl["name"], "main");
+    let nodes = ctrl["nodes"].as_array().unwrap();
+    let descriptions = nodes
+        .iter()
+        .map(|n| n["description"].as_str().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(descriptions, ["input", "checked", "output"]);
+    let input = &nodes[0];
+    let input_id = crate::StableNodeId::from_parts(["main", "input"]);
+    assert_eq!(input["id"], input_id.to_string());
+    assert_eq!(input["markers"], serde_json::json!(["secret", "source"]));
+    assert_eq!(input["types"], serde_json::json!(["Secret"]));
+    assert_eq!(input["span"]["start_line"], 1);
+    assert_eq!(input["span"]["file"], "src/main.rs");
+
+    let edges = ctrl["edges"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|e| {
+            let end = |key: &str| descriptions[e[key].as_u64().unwrap() as usize];
+            (end("from"), end("to"), e["kind"].as_str().unwrap())
+        })
+        .sorted()
+        .collect::<Vec<_>>();
+    assert_eq!(
+        edges,
+        [
+            ("checked", "output", "Data"),
+            ("input", "checked", "Data"),
+            ("input", "output", "Control"),
+        ]
+    );
+
+    // Only the embedded lines exist, even though more context is requested
+    let lines = report["sources"]["src/main.rs"].as_object().unwrap();
+    assert_eq!(lines.keys().collect::<Vec<_>>(), ["1", "2", "3"]);
+    assert_eq!(lines["2"], "    let checked = check(input);");
+}
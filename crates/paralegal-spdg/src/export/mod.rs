@@ -0,0 +1,137 @@
+//! Export [`ProgramDescription`]s for consumption by external tools.
+//!
+//! - [`graphml`] writes [GraphML](http://graphml.graphdrawing.org/) for graph
+//!   viewers such as Gephi and yEd.
+//! - [`neo4j`] writes CSV files for `neo4j-admin database import` or a Cypher
+//!   script that creates the graph in a running database.
+//! - [`html`] writes a self-contained interactive report for non-engineers.
+//! - [`datalog`] produces relations for Datalog engines such as Soufflé or
+//!   Crepe, together with a library of rules mirroring the queries of
+//!   `paralegal-policy`.
+//!
+//! All exporters identify nodes by their [`StableNodeId`], so the output of two
+//! runs on the same code can be compared, and visit controllers and nodes in a
+//! deterministic order.
+
+use itertools::Itertools;
+use petgraph::visit::EdgeRef;
+
+use crate::{
+    rustc_portable::{DefId, LocalDefId},
+    EdgeInfo, Identifier, Node, NodeInfo, ProgramDescription, StableNodeId, TypeId, SPDG,
+};
+
+pub mod datalog;
+pub mod graphml;
+pub mod html;
+pub mod neo4j;
+
+/// Everything an exporter needs to know about a node.
+pub(crate) struct NodeRecord<'a> {
+    pub controller: &'a SPDG,
+    pub node: Node,
+    pub info: &'a NodeInfo,
+    /// Markers on the node itself and on its types, sorted and deduplicated
+    pub markers: Vec<Identifier>,
+    /// Rendered types assigned to the node
+    pub types: Vec<String>,
+}
+
+impl NodeRecord<'_> {
+    /// `file:line:col-line:col` of the span of this node
+    pub fn span(&self) -> String {
+        let span = &self.info.span;
+        format!(
+            "{}:{}:{}-{}:{}",
+            span.source_file.file_path,
+            span.start.line,
+            span.start.col,
+            span.end.line,
+            span.end.col
+        )
+    }
+
+    pub fn id(&self) -> StableNodeId {
+        self.info.stable_id
+    }
+}
+
+/// Everything an exporter needs to know about an edge.
+pub(crate) struct EdgeRecord<'a> {
+    pub from: StableNodeId,
+    pub to: StableNodeId,
+    pub info: &'a EdgeInfo,
+}
+
+/// All controllers, sorted by name.
+pub(crate) fn sorted_controllers(desc: &ProgramDescription) -> Vec<(LocalDefId, &SPDG)> {
+    desc.controllers
+        .iter()
+        .map(|(id, ctrl)| (*id, ctrl))
+        .sorted_by_key(|(_, ctrl)| ctrl.name)
+        .collect()
+}
+
+/// How the type is rendered in exports, falling back to its def path if it
+/// carries no markers.
+pub(crate) fn render_type(desc: &ProgramDescription, ty: TypeId) -> String {
+    if let Some(info) = desc.type_info.get(&ty) {
+        info.rendering.clone()
+    } else {
+        def_path(desc, ty)
+    }
+}
+
+/// The def path of `def_id` joined with `::`, or its debug rendering if
+/// there is no [`DefInfo`](crate::DefInfo) for it.
+pub(crate) fn def_path(desc: &ProgramDescription, def_id: DefId) -> String {
+    if let Some(info) = desc.def_info.get(&def_id) {
+        info.path.iter().join("::")
+    } else {
+        format!("{def_id:?}")
+    }
+}
+
+/// The nodes of `ctrl` in index order.
+pub(crate) fn node_records<'a>(
+    desc: &'a ProgramDescription,
+    ctrl: &'a SPDG,
+) -> impl Iterator<Item = NodeRecord<'a>> + 'a {
+    ctrl.all_sources().map(move |node| {
+        let types = ctrl
+            .type_assigns
+            .get(&node)
+            .map_or(&[] as &[_], |t| t.0.as_slice());
+        let markers = ctrl
+            .markers
+            .get(&node)
+            .into_iter()
+            .flatten()
+            .copied()
+            .chain(
+                types
+                    .iter()
+                    .filter_map(|t| desc.type_info.get(t))
+                    .flat_map(|info| info.markers.iter().copied()),
+            )
+            .sorted()
+            .dedup()
+            .collect();
+        NodeRecord {
+            controller: ctrl,
+            node,
+            info: ctrl.node_info(node),
+            markers,
+            types: types.iter().map(|t| render_type(desc, *t)).collect(),
+        }
+    })
+}
+
+/// The edges of `ctrl` in index order.
+pub(crate) fn edge_records(ctrl: &SPDG) -> impl Iterator<Item = EdgeRecord<'_>> + '_ {
+    ctrl.edges().map(move |e| EdgeRecord {
+        from: ctrl.node_info(e.source()).stable_id,
+        to: ctrl.node_info(e.target()).stable_id,
+        info: e.weight(),
+    })
+}
@@ -0,0 +1,178 @@
+//! Export for the [Neo4j](https://neo4j.com/) graph database.
+//!
+//! Two flavors are offered. [`write_csv`] produces a node and a relationship
+//! file in the format expected by `neo4j-admin database import`, e.g.
+//!
+//! ```text
+//! neo4j-admin database import full --nodes=nodes.csv --relationships=edges.csv
+//! ```
+//!
+//! [`write_cypher`] produces a script of Cypher statements that can be run
+//! against an existing database, e.g. with `cypher-shell -f graph.cypher`.
+//!
+//! In both cases nodes get the label `Node` and are identified by the `id`
+//! property, which holds the [`StableNodeId`](crate::StableNodeId).
+//! Relationships have the type `DATA` or `CONTROL`.
+
+use std::io::{self, Write};
+
+use itertools::Itertools;
+
+use super::{edge_records, node_records, sorted_controllers, EdgeRecord, NodeRecord};
+use crate::ProgramDescription;
+
+/// Separator for array values in CSV files, the default of `neo4j-admin`.
+const ARRAY_DELIMITER: char = ';';
+
+fn relationship_type(edge: &EdgeRecord) -> String {
+    edge.info.kind.to_string().to_uppercase()
+}
+
+/// Quote `s` as a CSV field.
+fn csv_field(s: &str) -> String {
+    format!("\"{}\"", s.replace('"', "\"\""))
+}
+
+/// Write the nodes and edges of `desc` as CSV files for `neo4j-admin database
+/// import`.
+pub fn write_csv(
+    desc: &ProgramDescription,
+    mut nodes: impl Write,
+    mut edges: impl Write,
+) -> io::Result<()> {
+    writeln!(
+        nodes,
+        "id:ID,controller,description,kind,at,span,markers:string[],types:string[],:LABEL"
+    )?;
+    writeln!(edges, ":START_ID,:END_ID,:TYPE,at")?;
+    for (_, ctrl) in sorted_controllers(desc) {
+        for node in node_records(desc, ctrl) {
+            let fields = [
+                node.id().to_string(),
+                node.controller.name.to_string(),
+                node.info.description.clone(),
+                node.info.kind.to_string(),
+                node.info.at.to_string(),
+                node.span(),
+                node.markers.iter().join(&ARRAY_DELIMITER.to_string()),
+                node.types.join(&ARRAY_DELIMITER.to_string()),
+                "Node".to_owned(),
+            ];
+            writeln!(nodes, "{}", fields.iter().map(|f| csv_field(f)).join(","))?;
+        }
+        for edge in edge_records(ctrl) {
+            writeln!(
+                edges,
+                "{},{},{},{}",
+                csv_field(&edge.from.to_string()),
+                csv_field(&edge.to.to_string()),
+                relationship_type(&edge),
+                csv_field(&edge.info.at.to_string()),
+            )?;
+        }
+    }
+    nodes.flush()?;
+    edges.flush()
+}
+
+/// Render `s` as a Cypher string literal.
+fn cypher_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn cypher_list<'a>(items: impl IntoIterator<Item = &'a str>) -> String {
+    format!("[{}]", items.into_iter().map(cypher_string).join(", "))
+}
+
+fn cypher_node_properties(node: &NodeRecord) -> String {
+    format!(
+        "{{id: {}, controller: {}, description: {}, kind: {}, at: {}, span: {}, markers: {}, types: {}}}",
+        cypher_string(&node.id().to_string()),
+        cypher_string(node.controller.name.as_str()),
+        cypher_string(&node.info.description),
+        cypher_string(&node.info.kind.to_string()),
+        cypher_string(&node.info.at.to_string()),
+        cypher_string(&node.span()),
+        cypher_list(node.markers.iter().map(|m| m.as_str())),
+        cypher_list(node.types.iter().map(String::as_str)),
+    )
+}
+
+/// Write a Cypher script that creates the graph of `desc`.
+///
+/// The script first creates an index on `Node.id`, so that matching the
+/// endpoints of relationships stays fast for large graphs.
+pub fn write_cypher(desc: &ProgramDescription, mut out: impl Write) -> io::Result<()> {
+    writeln!(
+        out,
+        "CREATE INDEX node_id IF NOT EXISTS FOR (n:Node) ON (n.id);"
+    )?;
+    let controllers = sorted_controllers(desc);
+    for (_, ctrl) in &controllers {
+        for node in node_records(desc, ctrl) {
+            writeln!(out, "CREATE (:Node {});", cypher_node_properties(&node))?;
+        }
+    }
+    for (_, ctrl) in &controllers {
+        for edge in edge_records(ctrl) {
+            writeln!(
+                out,
+                "MATCH (a:Node {{id: {}}}), (b:Node {{id: {}}}) CREATE (a)-[:{} {{at: {}}}]->(b);",
+                cypher_string(&edge.from.to_string()),
+                cypher_string(&edge.to.to_string()),
+                relationship_type(&edge),
+                cypher_string(&edge.info.at.to_string()),
+            )?;
+        }
+    }
+    out.flush()
+}
+
+#[test]
+fn csv_and_cypher_strings_are_escaped() {
+    assert_eq!(csv_field(r#"a "b", c"#), r#""a ""b"", c""#);
+    assert_eq!(cypher_string("say \"hi\"\\\n"), r#""say \"hi\"\\\n""#);
+    assert_eq!(cypher_list(["a", "b"]), r#"["a", "b"]"#);
+}
+
+#[test]
+fn csv_and_cypher_contain_the_graph() {
+    let desc = crate::test_utils::example_description();
+    let (mut nodes, mut edges) = (vec![], vec![]);
+    write_csv(&desc, &mut nodes, &mut edges).unwrap();
+    let (nodes, edges) = (
+        String::from_utf8(nodes).unwrap(),
+        String::from_utf8(edges).unwrap(),
+    );
+    assert_eq!(nodes.lines().count(), 4);
+    let input = crate::StableNodeId::from_parts(["main", "input"]);
+    let input_line = nodes
+        .lines()
+        .find(|l| l.starts_with(&format!("\"{input}\",")))
+        .unwrap();
+    assert!(input_line.contains(r#""main","input","Formal Parameter [0]""#));
+    assert!(input_line.ends_with(r#""secret;source","Secret","Node""#));
+    assert_eq!(edges.lines().count(), 4);
+    assert_eq!(edges.matches(",DATA,").count(), 2);
+    assert_eq!(edges.matches(",CONTROL,").count(), 1);
+
+    let mut cypher = vec![];
+    write_cypher(&desc, &mut cypher).unwrap();
+    let cypher = String::from_utf8(cypher).unwrap();
+    assert_eq!(cypher.matches("CREATE (:Node ").count(), 3);
+    assert_eq!(cypher.matches("]->(b);").count(), 3);
+    assert!(cypher.contains(r#"markers: ["secret", "source"], types: ["Secret"]"#));
+}
@@ -23,10 +23,20 @@ extern crate strum;
 
 pub use flowistry_pdg::*;
 
+pub mod artifact;
+mod canonical;
+pub mod diff;
 pub mod dot;
+pub mod embed;
+pub mod export;
+pub mod merge;
+mod stable_id;
+#[cfg(test)]
+mod test_utils;
 mod tiny_bitset;
 pub mod traverse;
 pub mod utils;
+pub mod validate;
 
 use internment::Intern;
 use itertools::Itertools;
@@ -36,6 +46,7 @@ use std::{fmt, hash::Hash, path::PathBuf};
 
 use utils::serde_map_via_vec;
 
+pub use crate::stable_id::{StableIdAllocator, StableNodeId};
 pub use crate::tiny_bitset::pretty as tiny_bitset_pretty;
 pub use crate::tiny_bitset::TinyBitSet;
 use flowistry_pdg::rustc_portable::LocalDefId;
@@ -52,12 +63,14 @@ pub type TypeId = DefId;
 /// Identifiers for functions
 pub type Function = Identifier;
 
-/// Name of the file used for emitting the JSON serialized
-/// [`ProgramDescription`].
+/// Name of the file used for emitting the serialized [`ProgramDescription`].
+///
+/// Despite the extension the file may also be in the binary format, see
+/// [`artifact`].
 pub const FLOW_GRAPH_OUT_NAME: &str = "flow-graph.json";
 
 #[allow(dead_code)]
-mod ser_localdefid_map {
+pub(crate) mod ser_localdefid_map {
     use serde::{Deserialize, Serialize};
 
     use flowistry_pdg::rustc_proxies;
@@ -69,10 +82,9 @@ mod ser_localdefid_map {
         map: &super::HashMap<super::LocalDefId, V>,
         serializer: S,
     ) -> Result<S::Ok, S::Error> {
-        map.iter()
-            .map(|(k, v)| (Helper(*k), v))
-            .collect::<Vec<_>>()
-            .serialize(serializer)
+        let mut entries = map.iter().map(|(k, v)| (Helper(*k), v)).collect::<Vec<_>>();
+        crate::utils::sort_by_serialized_key(&mut entries)?;
+        entries.serialize(serializer)
     }
 
     pub fn deserialize<'de, D: serde::Deserializer<'de>, V: serde::Deserialize<'de>>(
@@ -86,7 +98,7 @@ mod ser_localdefid_map {
 }
 
 #[cfg(feature = "rustc")]
-mod ser_defid_map {
+pub(crate) mod ser_defid_map {
     use serde::{Deserialize, Serialize};
 
     use flowistry_pdg::rustc_proxies;
@@ -98,10 +110,9 @@ mod ser_defid_map {
         map: &super::HashMap<super::DefId, V>,
         serializer: S,
     ) -> Result<S::Ok, S::Error> {
-        map.iter()
-            .map(|(k, v)| (Helper(*k), v))
-            .collect::<Vec<_>>()
-            .serialize(serializer)
+        let mut entries = map.iter().map(|(k, v)| (Helper(*k), v)).collect::<Vec<_>>();
+        crate::utils::sort_by_serialized_key(&mut entries)?;
+        entries.serialize(serializer)
     }
 
     pub fn deserialize<'de, D: serde::Deserializer<'de>, V: serde::Deserialize<'de>>(
@@ -172,11 +183,20 @@ impl SourceFileInfo {
     }
 }
 
+/// Source code of a [`SourceFile`] stored in the [`ProgramDescription`].
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize, Debug, Default)]
+pub struct EmbeddedSource {
+    /// Lines of the file by their (1-based) number. Depending on the
+    /// [`embed::EmbedMode`] this holds all lines or only those covered by a
+    /// span.
+    pub lines: std::collections::BTreeMap<u32, String>,
+}
+
 /// A "point" within a source file. Used to compose and compare spans.
 ///
 /// NOTE: The ordering of this type must be such that if point "a" is earlier in
 /// the file than "b", then "a" < "b".
-#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Debug, PartialOrd, Ord)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, Debug, PartialOrd, Ord)]
 pub struct SpanCoord {
     /// Line in the source file
     pub line: u32,
@@ -185,7 +205,7 @@ pub struct SpanCoord {
 }
 
 /// Encodes a source code location
-#[derive(Clone, PartialEq, Eq, Serialize, Deserialize, Debug)]
+#[derive(Clone, PartialEq, Eq, Hash, Serialize, Deserialize, Debug)]
 pub struct Span {
     /// Which file this comes from
     pub source_file: SourceFile,
@@ -240,7 +260,7 @@ impl InstructionKind {
 }
 
 /// Information about an instruction represented in the PDG
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
 pub struct InstructionInfo {
     /// Classification of the instruction
     pub kind: InstructionKind,
@@ -255,7 +275,7 @@ pub type TypeInfoMap = HashMap<TypeId, TypeDescription>;
 pub type ControllerMap = HashMap<Endpoint, SPDG>;
 
 /// The annotated program dependence graph.
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Default)]
 pub struct ProgramDescription {
     /// Entry points we analyzed and their PDGs
     #[cfg_attr(feature = "rustc", serde(with = "ser_localdefid_map"))]
@@ -276,10 +296,16 @@ pub struct ProgramDescription {
     #[cfg_attr(feature = "rustc", serde(with = "ser_defid_map"))]
     /// Metadata about the `DefId`s
     pub def_info: HashMap<DefId, DefInfo>,
+
+    /// Source code of the files that spans point into. Only filled if the
+    /// extractor was asked to embed sources, see
+    /// [`Self::embed_sources`].
+    #[serde(with = "serde_map_via_vec", default)]
+    pub sources: HashMap<SourceFile, EmbeddedSource>,
 }
 
 /// Metadata about a type
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct TypeDescription {
     /// How rustc would debug print this type
     pub rendering: String,
@@ -614,6 +640,10 @@ pub struct NodeInfo {
     pub kind: NodeKind,
     /// Span information for this node
     pub span: Span,
+    /// Identifies this node across rebuilds of the analyzed code. Unique
+    /// within the [`ProgramDescription`].
+    #[serde(default)]
+    pub stable_id: StableNodeId,
 }
 
 impl Display for NodeInfo {
@@ -683,7 +713,7 @@ impl EdgeInfo {
 
 /// The type of an edge
 #[derive(
-    Clone, Debug, Copy, Eq, PartialEq, Deserialize, Serialize, strum::EnumIs, strum::Display,
+    Clone, Debug, Copy, Eq, PartialEq, Hash, Deserialize, Serialize, strum::EnumIs, strum::Display,
 )]
 pub enum EdgeKind {
     /// The target can read data created by the source
@@ -703,6 +733,7 @@ pub struct SPDG {
     /// The PDG
     pub graph: SPDGImpl,
     /// Nodes to which markers are assigned.
+    #[serde(serialize_with = "utils::serialize_sorted_map")]
     pub markers: HashMap<Node, Vec<Identifier>>,
     /// The nodes that represent arguments to the entrypoint
     pub arguments: Vec<Node>,
@@ -711,6 +742,7 @@ pub struct SPDG {
     /// Stores the assignment of relevant (e.g. marked) types to nodes. Node
     /// that this contains multiple types for a single node, because it hold
     /// top-level types and subtypes that may be marked.
+    #[serde(serialize_with = "utils::serialize_sorted_map")]
     pub type_assigns: HashMap<Node, Types>,
 }
 
@@ -0,0 +1,368 @@
+//! Combining the [`ProgramDescription`]s of several extractor runs, for
+//! instance one per crate of a workspace.
+//!
+//! [`Endpoint`]s, [`DefId`]s and the functions in [`GlobalLocation`]s are only
+//! unique within the compilation session that produced an artifact, so the
+//! same id may refer to different objects in two artifacts. Before merging,
+//! the ids of the added description are therefore translated: an id whose
+//! [`DefInfo`] has the same def path and span as one in the other description
+//! is mapped to that id, because both refer to the same object. Every other id
+//! that is already in use is replaced by a fresh one.
+//!
+//! After the translation equal ids refer to the same object. A controller that
+//! is defined in both descriptions, or metadata that differs between them, is
+//! a real collision and reported as a [`MergeConflict`].
+
+use std::fmt::{self, Display};
+
+use flowistry_pdg::rustc_portable::{DefId, LocalDefId};
+
+use crate::{
+    utils::raw_id, CallString, DefInfo, Endpoint, GlobalLocation, HashMap, HashSet, Identifier,
+    InstructionKind, ProgramDescription, Span,
+};
+
+/// An identifier that is present in both merged descriptions but refers to
+/// different information.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, strum::EnumIs)]
+pub enum MergeConflict {
+    /// Both descriptions contain this controller.
+    Controller(Endpoint),
+    /// The `type_info` entries for this type differ.
+    TypeInfo(DefId),
+    /// The `instruction_info` entries for this location differ.
+    InstructionInfo(GlobalLocation),
+    /// The `def_info` entries for this id differ.
+    DefInfo(DefId),
+}
+
+impl Display for MergeConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MergeConflict::Controller(id) => write!(f, "controller {id:?} is defined twice"),
+            MergeConflict::TypeInfo(id) => write!(f, "conflicting type info for {id:?}"),
+            MergeConflict::InstructionInfo(loc) => {
+                write!(f, "conflicting instruction info for {loc:?}")
+            }
+            MergeConflict::DefInfo(id) => write!(f, "conflicting def info for {id:?}"),
+        }
+    }
+}
+
+/// Insert all entries of `from` into `into`. Entries that already exist are
+/// kept and if `same` says they differ from the new entry `conflict` is
+/// recorded.
+fn union_into<K: std::hash::Hash + Eq + Copy, V>(
+    into: &mut crate::HashMap<K, V>,
+    from: crate::HashMap<K, V>,
+    same: impl Fn(&V, &V) -> bool,
+    conflict: impl Fn(K) -> MergeConflict,
+    conflicts: &mut Vec<MergeConflict>,
+) {
+    use std::collections::hash_map::Entry;
+    for (k, v) in from {
+        match into.entry(k) {
+            Entry::Vacant(vacant) => {
+                vacant.insert(v);
+            }
+            Entry::Occupied(occupied) => {
+                if !same(occupied.get(), &v) {
+                    conflicts.push(conflict(k))
+                }
+            }
+        }
+    }
+}
+
+/// A def id as its crate number and index, see [`raw_id`].
+type RawId = (u32, u32);
+
+const LOCAL_CRATE: u32 = 0;
+
+/// Visits every id in a [`ProgramDescription`].
+struct Ids<F> {
+    f: F,
+    /// Call strings are interned, so each one only needs translating once.
+    call_strings: HashMap<CallString, CallString>,
+}
+
+impl<F: FnMut(RawId) -> RawId> Ids<F> {
+    fn new(f: F) -> Self {
+        Self {
+            f,
+            call_strings: HashMap::new(),
+        }
+    }
+
+    fn def_id(&mut self, id: DefId) -> DefId {
+        let (krate, index) = (self.f)(raw_id::def_id_parts(id));
+        raw_id::def_id(krate, index)
+    }
+
+    fn local(&mut self, id: LocalDefId) -> LocalDefId {
+        let (krate, index) = (self.f)((LOCAL_CRATE, raw_id::local_def_index(id)));
+        assert_eq!(krate, LOCAL_CRATE, "local ids must stay local");
+        raw_id::local_def_id(index)
+    }
+
+    fn location(&mut self, loc: GlobalLocation) -> GlobalLocation {
+        GlobalLocation {
+            function: self.local(loc.function),
+            ..loc
+        }
+    }
+
+    fn call_string(&mut self, cs: CallString) -> CallString {
+        if let Some(known) = self.call_strings.get(&cs) {
+            return *known;
+        }
+        let mut locations = cs.iter_from_root();
+        let root = locations.next().expect("call strings are not empty");
+        let mut translated = CallString::single(self.location(root));
+        for loc in locations {
+            translated = translated.push(self.location(loc));
+        }
+        self.call_strings.insert(cs, translated);
+        translated
+    }
+
+    /// Replace every id in `desc` by the result of `f`.
+    fn translate(&mut self, desc: &mut ProgramDescription) {
+        desc.controllers = std::mem::take(&mut desc.controllers)
+            .into_iter()
+            .map(|(id, mut ctrl)| {
+                for info in ctrl.graph.node_weights_mut() {
+                    info.at = self.call_string(info.at);
+                }
+                for info in ctrl.graph.edge_weights_mut() {
+                    info.at = self.call_string(info.at);
+                }
+                for types in ctrl.type_assigns.values_mut() {
+                    for ty in &mut types.0 {
+                        *ty = self.def_id(*ty);
+                    }
+                }
+                (self.local(id), ctrl)
+            })
+            .collect();
+        desc.type_info = std::mem::take(&mut desc.type_info)
+            .into_iter()
+            .map(|(id, mut info)| {
+                for ty in &mut info.otypes {
+                    *ty = self.def_id(*ty);
+                }
+                (self.def_id(id), info)
+            })
+            .collect();
+        desc.instruction_info = std::mem::take(&mut desc.instruction_info)
+            .into_iter()
+            .map(|(loc, mut info)| {
+                if let InstructionKind::FunctionCall(call) = &mut info.kind {
+                    call.id = self.def_id(call.id);
+                }
+                (self.location(loc), info)
+            })
+            .collect();
+        desc.def_info = std::mem::take(&mut desc.def_info)
+            .into_iter()
+            .map(|(id, info)| (self.def_id(id), info))
+            .collect();
+    }
+
+    /// Call `f` on every id in `desc` without changing it.
+    fn visit(&mut self, desc: &ProgramDescription) {
+        for (id, ctrl) in &desc.controllers {
+            self.local(*id);
+            let call_strings = ctrl
+                .graph
+                .node_weights()
+                .map(|info| info.at)
+                .chain(ctrl.graph.edge_weights().map(|info| info.at));
+            for cs in call_strings {
+                for loc in cs.iter() {
+                    self.local(loc.function);
+                }
+            }
+            for ty in ctrl.type_assigns.values().flat_map(|types| &types.0) {
+                self.def_id(*ty);
+            }
+        }
+        for (id, info) in &desc.type_info {
+            self.def_id(*id);
+            for ty in &info.otypes {
+                self.def_id(*ty);
+            }
+        }
+        for (loc, info) in &desc.instruction_info {
+            self.local(loc.function);
+            if let InstructionKind::FunctionCall(call) = info.kind {
+                self.def_id(call.id);
+            }
+        }
+        for id in desc.def_info.keys() {
+            self.def_id(*id);
+        }
+    }
+}
+
+fn all_ids(desc: &ProgramDescription) -> HashSet<RawId> {
+    let mut ids = HashSet::new();
+    Ids::new(|id| {
+        ids.insert(id);
+        id
+    })
+    .visit(desc);
+    ids
+}
+
+/// Identifies the object behind a [`DefInfo`] independently of the session.
+/// The def path alone is not enough, because it omits closures and other
+/// anonymous items.
+fn def_key(info: &DefInfo) -> (&[Identifier], &Span) {
+    (&info.path, &info.src_info)
+}
+
+/// How to translate the ids of `other` before merging it into `this`, see the
+/// [module level documentation](self).
+fn id_translation(this: &ProgramDescription, other: &ProgramDescription) -> HashMap<RawId, RawId> {
+    let by_def_key = this
+        .def_info
+        .iter()
+        .map(|(id, info)| (def_key(info), raw_id::def_id_parts(*id)))
+        .collect::<HashMap<_, _>>();
+    let used = all_ids(this);
+    let other_ids = all_ids(other);
+    let mut next_index = HashMap::<u32, u32>::new();
+    for (krate, index) in used.iter().chain(&other_ids) {
+        let next = next_index.entry(*krate).or_default();
+        *next = (*next).max(index + 1);
+    }
+    let mut translation = HashMap::new();
+    for (id, info) in &other.def_info {
+        let id = raw_id::def_id_parts(*id);
+        if let Some(same) = by_def_key.get(&def_key(info)) {
+            // Local ids are also used as `LocalDefId`s and must stay local
+            if (same.0 == LOCAL_CRATE) == (id.0 == LOCAL_CRATE) {
+                translation.insert(id, *same);
+            }
+        }
+    }
+    for id in other_ids {
+        if translation.contains_key(&id) || !used.contains(&id) {
+            continue;
+        }
+        let next = next_index.get_mut(&id.0).unwrap();
+        translation.insert(id, (id.0, *next));
+        *next += 1;
+    }
+    translation
+}
+
+impl ProgramDescription {
+    /// Add all controllers, type information, instruction information, def
+    /// information and embedded sources from `other` to `self`.
+    ///
+    /// The ids of `other` are translated first, see the
+    /// [module level documentation](crate::merge). Metadata entries that are
+    /// present in both with identical content are kept once. A controller
+    /// that is present in both, or a metadata entry whose content differs, is
+    /// a conflict. In that case the entry from `self` is kept and the conflict
+    /// is returned. If the returned vector is not empty the merged
+    /// description should be considered unreliable.
+    pub fn merge(&mut self, mut other: ProgramDescription) -> Vec<MergeConflict> {
+        let translation = id_translation(self, &other);
+        Ids::new(|id| translation.get(&id).copied().unwrap_or(id)).translate(&mut other);
+        let mut conflicts = vec![];
+        union_into(
+            &mut self.controllers,
+            other.controllers,
+            |_, _| false,
+            MergeConflict::Controller,
+            &mut conflicts,
+        );
+        union_into(
+            &mut self.type_info,
+            other.type_info,
+            PartialEq::eq,
+            MergeConflict::TypeInfo,
+            &mut conflicts,
+        );
+        union_into(
+            &mut self.instruction_info,
+            other.instruction_info,
+            PartialEq::eq,
+            MergeConflict::InstructionInfo,
+            &mut conflicts,
+        );
+        union_into(
+            &mut self.def_info,
+            other.def_info,
+            PartialEq::eq,
+            MergeConflict::DefInfo,
+            &mut conflicts,
+        );
+        // Embedded sources of the same file never conflict, one side may just
+        // have embedded more lines than the other.
+        for (file, source) in other.sources {
+            self.sources
+                .entry(file)
+                .or_default()
+                .lines
+                .extend(source.lines);
+        }
+        conflicts
+    }
+}
+
+#[test]
+fn merging_different_crates_keeps_both() {
+    use crate::test_utils::{def_id, example_description, EXAMPLE_CONTROLLER, EXAMPLE_TYPE};
+    let mut merged = example_description();
+    // The same program in another crate, which shares the type with the
+    // first one, but not the functions.
+    let mut other = example_description();
+    let other_crate = Identifier::new_intern("other");
+    for info in other.def_info.values_mut() {
+        if !info.kind.is_type() {
+            info.path[0] = other_crate;
+        }
+    }
+    other.controllers.values_mut().next().unwrap().name = Identifier::new_intern("other_main");
+
+    assert_eq!(merged.merge(other), []);
+    assert_eq!(merged.controllers.len(), 2);
+    assert_eq!(merged.def_info.len(), 5);
+    assert_eq!(merged.type_info.len(), 1);
+    assert_eq!(merged.instruction_info.len(), 6);
+    let ty = def_id(0, EXAMPLE_TYPE);
+    for (id, ctrl) in &merged.controllers {
+        let is_first = raw_id::local_def_index(*id) == EXAMPLE_CONTROLLER;
+        assert_eq!(is_first, ctrl.name.as_str() == "main");
+        // The controller's own locations are translated along with its id
+        assert!(ctrl
+            .graph
+            .node_weights()
+            .all(|info| info.at.leaf().function == *id));
+        assert!(merged
+            .instruction_info
+            .keys()
+            .any(|loc| loc.function == *id));
+        let path = &merged.def_info[&raw_id::def_id(0, raw_id::local_def_index(*id))].path;
+        assert_eq!(path[0].as_str(), if is_first { "crate" } else { "other" });
+        assert!(ctrl.type_assigns.values().all(|types| types.0 == [ty]));
+    }
+}
+
+#[test]
+fn merging_the_same_crate_conflicts() {
+    use crate::test_utils::{example_description, local_def_id, EXAMPLE_CONTROLLER};
+    let mut merged = example_description();
+    let mut other = example_description();
+    for info in other.type_info.values_mut() {
+        info.markers.push(Identifier::new_intern("other"));
+    }
+    let conflicts = merged.merge(other);
+    assert_eq!(conflicts.len(), 2);
+    assert!(conflicts.contains(&MergeConflict::Controller(local_def_id(EXAMPLE_CONTROLLER))));
+    assert!(conflicts.iter().any(MergeConflict::is_type_info));
+}
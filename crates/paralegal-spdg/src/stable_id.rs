@@ -0,0 +1,117 @@
+//! Node identifiers that survive recompilation.
+
+use std::{fmt, str::FromStr};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::HashMap;
+
+/// An identifier for a node in an [`SPDG`](crate::SPDG) that, unlike
+/// [`GlobalNode`](crate::GlobalNode), does not depend on the compilation
+/// session that produced the graph.
+///
+/// The extractor derives it from the def path of the controller, the
+/// [`CallString`](crate::CallString) of the node rendered through def paths and
+/// the description of the place the node represents. Rebuilding unchanged code
+/// therefore yields the same ids, which means they can be persisted, for
+/// instance in baselines or review comments.
+///
+/// It is rendered (and serialized) as 16 hexadecimal digits.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Debug, Default)]
+pub struct StableNodeId(u64);
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+fn fnv1a(mut hash: u64, bytes: &[u8]) -> u64 {
+    for b in bytes {
+        hash ^= *b as u64;
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+    hash
+}
+
+impl StableNodeId {
+    /// Hash `parts` in order.
+    ///
+    /// Uses FNV-1a instead of [`std::hash::Hash`] because the latter is not
+    /// guaranteed to be stable across Rust versions or platforms.
+    pub fn from_parts<P: AsRef<[u8]>>(parts: impl IntoIterator<Item = P>) -> Self {
+        Self(parts.into_iter().fold(FNV_OFFSET_BASIS, |hash, part| {
+            let part = part.as_ref();
+            // Length prefix so that moving bytes between parts changes the id
+            fnv1a(fnv1a(hash, &(part.len() as u64).to_le_bytes()), part)
+        }))
+    }
+
+    /// The raw value of this id
+    pub fn as_u64(self) -> u64 {
+        self.0
+    }
+}
+
+impl fmt::Display for StableNodeId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+impl FromStr for StableNodeId {
+    type Err = std::num::ParseIntError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        u64::from_str_radix(s, 16).map(Self)
+    }
+}
+
+impl Serialize for StableNodeId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for StableNodeId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// Makes ids unique within one graph.
+///
+/// Distinct nodes may be derived from the same information, for instance if
+/// the same place is used in several ways at one location. The first
+/// occurrence keeps its id, later ones are rehashed with their occurrence
+/// count. As long as nodes are allocated in a deterministic order the result
+/// is stable too, [`SPDG::disambiguate_stable_ids`](crate::SPDG::disambiguate_stable_ids)
+/// uses an order that only depends on the nodes themselves.
+#[derive(Default)]
+pub struct StableIdAllocator {
+    seen: HashMap<StableNodeId, u64>,
+}
+
+impl StableIdAllocator {
+    /// Return `base` if it has not been seen yet, otherwise a new id derived
+    /// from it.
+    pub fn allocate(&mut self, base: StableNodeId) -> StableNodeId {
+        let occurrence = self.seen.entry(base).or_insert(0);
+        let id = if *occurrence == 0 {
+            base
+        } else {
+            StableNodeId::from_parts([base.0.to_le_bytes(), occurrence.to_le_bytes()])
+        };
+        *occurrence += 1;
+        id
+    }
+}
+
+#[test]
+fn stable_id_roundtrips_through_display() {
+    let id = StableNodeId::from_parts(["crate::controller", "bb0[1]", "_1"]);
+    assert_eq!(id.to_string().len(), 16);
+    assert_eq!(id.to_string().parse::<StableNodeId>().unwrap(), id);
+    assert_ne!(
+        id,
+        StableNodeId::from_parts(["crate::controller", "bb0[1", "]_1"])
+    );
+}
@@ -0,0 +1,158 @@
+//! Fixtures shared by the unit tests of this crate.
+
+use crate::{
+    rustc_portable::Location, CallString, DefInfo, DefKind, EdgeInfo, EdgeKind, EmbeddedSource,
+    FunctionCallInfo, GlobalLocation, Identifier, InstructionInfo, InstructionKind, NodeInfo,
+    NodeKind, ProgramDescription, RichLocation, SourceFile, SourceFileInfo, Span, SpanCoord,
+    StableNodeId, TypeDescription, Types, SPDG,
+};
+
+pub(crate) use crate::utils::raw_id::{def_id, local_def_id};
+
+fn location(statement_index: usize) -> Location {
+    serde_json::from_value(serde_json::json!({
+        "block": { "private": 0 },
+        "statement_index": statement_index,
+    }))
+    .unwrap()
+}
+
+/// The file that all spans in [`example_description`] point into.
+pub(crate) fn source_file() -> SourceFile {
+    SourceFileInfo {
+        file_path: "src/main.rs".to_owned(),
+        abs_file_path: "/nonexistent/src/main.rs".into(),
+    }
+    .intern()
+}
+
+/// A span covering columns 5 to 10 of `line` in [`source_file`].
+pub(crate) fn span(line: u32) -> Span {
+    Span {
+        source_file: source_file(),
+        start: SpanCoord { line, col: 5 },
+        end: SpanCoord { line, col: 10 },
+    }
+}
+
+/// The controller in [`example_description`].
+pub(crate) const EXAMPLE_CONTROLLER: u32 = 0;
+/// The type that is assigned to the argument of [`EXAMPLE_CONTROLLER`].
+pub(crate) const EXAMPLE_TYPE: u32 = 10;
+/// The function that [`EXAMPLE_CONTROLLER`] calls.
+pub(crate) const EXAMPLE_CALLEE: u32 = 11;
+
+/// A program with the single controller `main`:
+///
+/// ```text
+/// fn main(input: Secret) -> Output {  // input is marked "source"
+///     let checked = check(input);
+///     return checked;                 // the return is marked "sink"
+/// }
+/// ```
+///
+/// `input` flows to `checked` and `checked` to the return. `input` also has
+/// control influence on the return.
+pub(crate) fn example_description() -> ProgramDescription {
+    let function = local_def_id(EXAMPLE_CONTROLLER);
+    let at = |location| CallString::single(GlobalLocation { function, location });
+    let start = at(RichLocation::Start);
+    let call = at(RichLocation::Location(location(1)));
+    let end = at(RichLocation::End);
+    let node = |at, description: &str, kind, line| NodeInfo {
+        at,
+        description: description.to_owned(),
+        kind,
+        span: span(line),
+        stable_id: StableNodeId::from_parts(["main", description]),
+    };
+
+    let mut graph = crate::SPDGImpl::new();
+    let input = graph.add_node(node(start, "input", NodeKind::FormalParameter(0), 1));
+    let checked = graph.add_node(node(call, "checked", NodeKind::ActualReturn, 2));
+    let output = graph.add_node(node(end, "output", NodeKind::FormalReturn, 3));
+    for (from, to, kind, at) in [
+        (input, checked, EdgeKind::Data, call),
+        (checked, output, EdgeKind::Data, end),
+        (input, output, EdgeKind::Control, end),
+    ] {
+        graph.add_edge(from, to, EdgeInfo { kind, at });
+    }
+
+    let ty = def_id(0, EXAMPLE_TYPE);
+    let callee = def_id(0, EXAMPLE_CALLEE);
+    let controller = SPDG {
+        name: Identifier::new_intern("main"),
+        graph,
+        markers: [
+            (input, vec![Identifier::new_intern("source")]),
+            (output, vec![Identifier::new_intern("sink")]),
+        ]
+        .into_iter()
+        .collect(),
+        arguments: vec![input],
+        return_: Some(output),
+        type_assigns: [(input, Types(vec![ty]))].into_iter().collect(),
+    };
+
+    let mut desc = ProgramDescription::default();
+    desc.controllers.insert(function, controller);
+    desc.type_info.insert(
+        ty,
+        TypeDescription {
+            rendering: "Secret".to_owned(),
+            otypes: vec![ty],
+            markers: vec![Identifier::new_intern("secret")],
+        },
+    );
+    for (kind, at, line) in [
+        (InstructionKind::Start, start, 1),
+        (
+            InstructionKind::FunctionCall(FunctionCallInfo {
+                is_inlined: false,
+                id: callee,
+            }),
+            call,
+            2,
+        ),
+        (InstructionKind::Return, end, 3),
+    ] {
+        desc.instruction_info.insert(
+            at.leaf(),
+            InstructionInfo {
+                kind,
+                span: span(line),
+            },
+        );
+    }
+    for (id, name, kind) in [
+        (def_id(0, EXAMPLE_CONTROLLER), "main", DefKind::Fn),
+        (ty, "Secret", DefKind::Type),
+        (callee, "check", DefKind::Fn),
+    ] {
+        let name = Identifier::new_intern(name);
+        desc.def_info.insert(
+            id,
+            DefInfo {
+                name,
+                path: vec![Identifier::new_intern("crate"), name],
+                kind,
+                src_info: span(1),
+            },
+        );
+    }
+    desc.sources.insert(
+        source_file(),
+        EmbeddedSource {
+            lines: [
+                (1, "fn main(input: Secret) -> Output {"),
+                (2, "    let checked = check(input);"),
+                (3, "    return checked;"),
+            ]
+            .into_iter()
+            .map(|(n, l)| (n, l.to_owned()))
+            .collect(),
+        },
+    );
+    desc
+}
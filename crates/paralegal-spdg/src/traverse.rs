@@ -1,15 +1,18 @@
 //! Utilities for traversing an SPDG
 
-use std::collections::HashSet;
+use std::collections::{hash_map::Entry, HashSet, VecDeque};
 
-use petgraph::visit::{Control, Data, DfsEvent, EdgeFiltered, EdgeRef, IntoEdgeReferences};
+use petgraph::graph::EdgeIndex;
+use petgraph::visit::{
+    Control, Data, Dfs, DfsEvent, EdgeFiltered, EdgeRef, IntoEdgeReferences, Reversed, Walker,
+};
 
-use crate::{EdgeInfo, EdgeKind, Node};
+use crate::{EdgeInfo, EdgeKind, HashMap, Node};
 
 use super::SPDG;
 
 /// Which type of edges should be considered for a given traversal
-#[derive(Clone, Copy, Eq, PartialEq, strum::EnumIs)]
+#[derive(Clone, Copy, Eq, PartialEq, Debug, strum::EnumIs)]
 pub enum EdgeSelection {
     /// Consider only edges with [`crate::EdgeKind::Data`]
     Data,
@@ -84,3 +87,505 @@ pub fn generic_flows_to(
     });
     matches!(result, Control::Break(()))
 }
+
+/// Depth first search from `from` that does not continue past nodes for which
+/// `avoid` holds. Calls `found` on every node that is reached via at least one
+/// edge and stops as soon as it returns `true`.
+///
+/// Nodes in `from` are expanded regardless of `avoid`.
+fn search_avoiding(
+    from: impl IntoIterator<Item = Node>,
+    edge_selection: EdgeSelection,
+    spdg: &SPDG,
+    avoid: impl Fn(Node) -> bool,
+    mut found: impl FnMut(Node) -> bool,
+) -> bool {
+    let mut stack = from.into_iter().collect::<Vec<_>>();
+    let mut seen = HashSet::new();
+    while let Some(n) = stack.pop() {
+        for e in spdg.graph.edges(n) {
+            if !edge_selection.conforms(e.weight().kind) {
+                continue;
+            }
+            let next = e.target();
+            if !seen.insert(next) {
+                continue;
+            }
+            if found(next) {
+                return true;
+            }
+            if !avoid(next) {
+                stack.push(next);
+            }
+        }
+    }
+    false
+}
+
+/// Like [`generic_flows_to`] but only considers paths where no node strictly
+/// between the endpoints satisfies `avoid`. This answers questions such as
+/// "does the data reach the sink without passing through a sanitizer".
+///
+/// Control and combined flows are reflexive, so with those selections a node
+/// in both `from` and `other` always counts. With [`EdgeSelection::Data`] a
+/// path must have at least one edge, so such a node only counts if it lies on
+/// a cycle.
+pub fn generic_flows_to_avoiding(
+    from: impl IntoIterator<Item = Node>,
+    edge_selection: EdgeSelection,
+    spdg: &SPDG,
+    other: impl IntoIterator<Item = Node>,
+    avoid: impl Fn(Node) -> bool,
+) -> bool {
+    let targets = other.into_iter().collect::<HashSet<_>>();
+    if targets.is_empty() {
+        return false;
+    }
+    let from = from.into_iter().collect::<Vec<_>>();
+    if !edge_selection.is_data() && from.iter().any(|n| targets.contains(n)) {
+        return true;
+    }
+    search_avoiding(from, edge_selection, spdg, avoid, |n| targets.contains(&n))
+}
+
+/// All nodes reachable from `from` via at least one edge admitted by
+/// `edge_selection` without passing through a node that satisfies `avoid`.
+/// Avoided nodes themselves are included if they are reached, but nothing
+/// beyond them.
+///
+/// As control and combined flows are reflexive, `from` itself is included
+/// unless `edge_selection` is [`EdgeSelection::Data`].
+pub fn reachable_from_avoiding(
+    from: impl IntoIterator<Item = Node>,
+    edge_selection: EdgeSelection,
+    spdg: &SPDG,
+    avoid: impl Fn(Node) -> bool,
+) -> HashSet<Node> {
+    let from = from.into_iter().collect::<Vec<_>>();
+    let mut reached = if edge_selection.is_data() {
+        HashSet::new()
+    } else {
+        from.iter().copied().collect()
+    };
+    search_avoiding(from, edge_selection, spdg, avoid, |n| {
+        reached.insert(n);
+        false
+    });
+    reached
+}
+
+/// All nodes reachable from `from` (including `from` itself) via edges
+/// admitted by `edge_selection`.
+pub fn reachable_from(
+    from: impl IntoIterator<Item = Node>,
+    edge_selection: EdgeSelection,
+    spdg: &SPDG,
+) -> HashSet<Node> {
+    let graph = edge_selection.filter_graph(&spdg.graph);
+    let mut dfs = Dfs::empty(&graph);
+    dfs.stack.extend(from);
+    dfs.iter(&graph).collect()
+}
+
+/// All nodes that can reach `to` (including `to` itself) via edges admitted by
+/// `edge_selection`.
+pub fn reaching(
+    to: impl IntoIterator<Item = Node>,
+    edge_selection: EdgeSelection,
+    spdg: &SPDG,
+) -> HashSet<Node> {
+    let filtered = edge_selection.filter_graph(&spdg.graph);
+    let graph = Reversed(&filtered);
+    let mut dfs = Dfs::empty(graph);
+    dfs.stack.extend(to);
+    dfs.iter(graph).collect()
+}
+
+/// A self-contained part of an [`SPDG`], e.g. as created by [`forward_slice`]
+/// or [`backward_slice`].
+#[derive(Clone, Debug)]
+pub struct Slice {
+    /// The sliced graph. Markers, type assignments and the return are carried
+    /// over for all nodes that are retained. Its `arguments` only contain the
+    /// retained arguments, so their positions may shift, see
+    /// [`Self::arguments`].
+    pub spdg: SPDG,
+    /// For each node in the slice (by index) the node it was created from in
+    /// the original graph.
+    pub original: Vec<Node>,
+    /// For each argument of the original graph (by position) the node in the
+    /// slice, if it was retained.
+    pub arguments: Vec<Option<Node>>,
+    translate: HashMap<Node, Node>,
+}
+
+impl Slice {
+    /// Find the node in the slice that corresponds to `node` in the original
+    /// graph, if it was retained.
+    pub fn node_for_original(&self, node: Node) -> Option<Node> {
+        self.translate.get(&node).copied()
+    }
+}
+
+/// The part of `spdg` that is influenced by `from` under `edge_selection`,
+/// including `from` itself.
+pub fn forward_slice(
+    from: impl IntoIterator<Item = Node>,
+    edge_selection: EdgeSelection,
+    spdg: &SPDG,
+) -> Slice {
+    let keep = reachable_from(from, edge_selection, spdg);
+    induced_subgraph(spdg, &keep, edge_selection)
+}
+
+/// The part of `spdg` that influences `to` under `edge_selection`, including
+/// `to` itself.
+pub fn backward_slice(
+    to: impl IntoIterator<Item = Node>,
+    edge_selection: EdgeSelection,
+    spdg: &SPDG,
+) -> Slice {
+    let keep = reaching(to, edge_selection, spdg);
+    induced_subgraph(spdg, &keep, edge_selection)
+}
+
+/// Keep only the entries of retained nodes, translating them to the new indices.
+fn restrict<V: Clone>(map: &HashMap<Node, V>, translate: &HashMap<Node, Node>) -> HashMap<Node, V> {
+    map.iter()
+        .filter_map(|(n, v)| Some((*translate.get(n)?, v.clone())))
+        .collect()
+}
+
+/// Create a new [`SPDG`] that contains only the nodes in `keep` and the edges
+/// between them that are admitted by `edge_selection`.
+///
+/// Node indices are compacted, but the relative order of nodes and edges is
+/// retained. Markers and type assignments of retained nodes are carried over.
+/// The `arguments` of the new [`SPDG`] only retain those arguments that are in
+/// `keep`, so positions may shift. [`Slice::arguments`] keeps them in place.
+pub fn induced_subgraph(spdg: &SPDG, keep: &HashSet<Node>, edge_selection: EdgeSelection) -> Slice {
+    let mut graph = crate::SPDGImpl::with_capacity(keep.len(), 0);
+    let mut original = Vec::with_capacity(keep.len());
+    let mut translate = HashMap::with_capacity(keep.len());
+    for n in spdg.graph.node_indices().filter(|n| keep.contains(n)) {
+        let new = graph.add_node(spdg.graph[n].clone());
+        translate.insert(n, new);
+        original.push(n);
+    }
+    for e in spdg.graph.edge_references() {
+        if let (Some(&from), Some(&to), true) = (
+            translate.get(&e.source()),
+            translate.get(&e.target()),
+            edge_selection.conforms(e.weight().kind),
+        ) {
+            graph.add_edge(from, to, e.weight().clone());
+        }
+    }
+    let arguments = spdg
+        .arguments
+        .iter()
+        .map(|n| translate.get(n).copied())
+        .collect::<Vec<_>>();
+    let spdg = SPDG {
+        name: spdg.name,
+        markers: restrict(&spdg.markers, &translate),
+        type_assigns: restrict(&spdg.type_assigns, &translate),
+        arguments: arguments.iter().flatten().copied().collect(),
+        return_: spdg.return_.and_then(|n| translate.get(&n).copied()),
+        graph,
+    };
+    Slice {
+        spdg,
+        original,
+        arguments,
+        translate,
+    }
+}
+
+/// A walk through an [`SPDG`] as found by [`shortest_path`] or
+/// [`k_shortest_paths`]. Serves as a witness for [`generic_flows_to`].
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Path {
+    /// The visited nodes, starting with the source and ending with the target.
+    /// Always one longer than `edges`.
+    pub nodes: Vec<Node>,
+    /// The traversed edges, `edges[i]` leads from `nodes[i]` to `nodes[i + 1]`.
+    pub edges: Vec<EdgeIndex>,
+}
+
+impl Path {
+    fn from_edges(start: Node, edges: Vec<EdgeIndex>, spdg: &SPDG) -> Self {
+        let nodes = std::iter::once(start)
+            .chain(
+                edges
+                    .iter()
+                    .map(|e| spdg.graph.edge_endpoints(*e).unwrap().1),
+            )
+            .collect();
+        Self { nodes, edges }
+    }
+
+    /// The node this path starts at
+    pub fn source(&self) -> Node {
+        self.nodes[0]
+    }
+
+    /// The node this path ends at
+    pub fn target(&self) -> Node {
+        *self.nodes.last().unwrap()
+    }
+
+    /// The number of edges in this path
+    pub fn hops(&self) -> usize {
+        self.edges.len()
+    }
+}
+
+/// Breadth first search for a path with at least one edge from any of `from`
+/// to any of `targets`, that never enters `blocked_nodes` or takes
+/// `blocked_edges`.
+fn bfs_path(
+    from: &[Node],
+    targets: &HashSet<Node>,
+    edge_selection: EdgeSelection,
+    spdg: &SPDG,
+    blocked_nodes: &HashSet<Node>,
+    blocked_edges: &HashSet<EdgeIndex>,
+) -> Option<Path> {
+    let mut predecessor: HashMap<Node, Option<EdgeIndex>> = HashMap::new();
+    let mut queue = VecDeque::new();
+    for &n in from {
+        if !blocked_nodes.contains(&n) && predecessor.insert(n, None).is_none() {
+            queue.push_back(n);
+        }
+    }
+    while let Some(n) = queue.pop_front() {
+        for e in spdg.graph.edges(n) {
+            let next = e.target();
+            if !edge_selection.conforms(e.weight().kind)
+                || blocked_edges.contains(&e.id())
+                || blocked_nodes.contains(&next)
+            {
+                continue;
+            }
+            // Targets are checked on the edge rather than on discovery, so
+            // that a source that is also a target still needs a (cyclic) path.
+            if targets.contains(&next) {
+                let mut edges = vec![e.id()];
+                let mut current = n;
+                while let Some(Some(edge)) = predecessor.get(&current) {
+                    edges.push(*edge);
+                    current = spdg.graph.edge_endpoints(*edge).unwrap().0;
+                }
+                edges.reverse();
+                return Some(Path::from_edges(current, edges, spdg));
+            }
+            if let Entry::Vacant(v) = predecessor.entry(next) {
+                v.insert(Some(e.id()));
+                queue.push_back(next);
+            }
+        }
+    }
+    None
+}
+
+/// A path with the fewest edges from any node in `from` to any node in `to`
+/// using only edges admitted by `edge_selection`.
+///
+/// Paths always have at least one edge, so a node only reaches itself if it
+/// lies on a cycle.
+pub fn shortest_path(
+    from: impl IntoIterator<Item = Node>,
+    to: impl IntoIterator<Item = Node>,
+    edge_selection: EdgeSelection,
+    spdg: &SPDG,
+) -> Option<Path> {
+    let from = from.into_iter().collect::<Vec<_>>();
+    let targets = to.into_iter().collect::<HashSet<_>>();
+    bfs_path(
+        &from,
+        &targets,
+        edge_selection,
+        spdg,
+        &HashSet::new(),
+        &HashSet::new(),
+    )
+}
+
+/// Up to `k` distinct paths from `from` to `to` that only use edges admitted
+/// by `edge_selection`, in order of increasing length.
+///
+/// This is Yen's algorithm, where every source is treated as a successor of a
+/// virtual root. Paths therefore differ in at least one edge or in their source,
+/// and besides the target (which may close a cycle) they visit no node twice.
+pub fn k_shortest_paths(
+    from: impl IntoIterator<Item = Node>,
+    to: impl IntoIterator<Item = Node>,
+    edge_selection: EdgeSelection,
+    spdg: &SPDG,
+    k: usize,
+) -> Vec<Path> {
+    let sources = from.into_iter().collect::<Vec<_>>();
+    let targets = to.into_iter().collect::<HashSet<_>>();
+    let search =
+        |from: &[Node], blocked_nodes: &HashSet<Node>, blocked_edges: &HashSet<EdgeIndex>| {
+            bfs_path(
+                from,
+                &targets,
+                edge_selection,
+                spdg,
+                blocked_nodes,
+                blocked_edges,
+            )
+        };
+    let mut found = vec![];
+    if k == 0 {
+        return found;
+    }
+    let Some(first) = search(&sources, &HashSet::new(), &HashSet::new()) else {
+        return found;
+    };
+    found.push(first);
+    let mut candidates: Vec<Path> = vec![];
+    while found.len() < k {
+        let last = found.last().unwrap();
+        let mut deviations = vec![];
+
+        // Deviate at the virtual root by starting from an unused source
+        let fresh_sources = sources
+            .iter()
+            .copied()
+            .filter(|s| found.iter().all(|p| p.source() != *s))
+            .collect::<Vec<_>>();
+        deviations.extend(search(&fresh_sources, &HashSet::new(), &HashSet::new()));
+
+        for i in 0..last.hops() {
+            let spur = last.nodes[i];
+            let root = &last.edges[..i];
+            let blocked_edges = found
+                .iter()
+                .filter(|p| p.source() == last.source() && p.hops() > i && &p.edges[..i] == root)
+                .map(|p| p.edges[i])
+                .collect();
+            let blocked_nodes = last.nodes[..i].iter().copied().collect();
+            if let Some(spur_path) = search(&[spur], &blocked_nodes, &blocked_edges) {
+                let edges = root.iter().chain(&spur_path.edges).copied().collect();
+                deviations.push(Path::from_edges(last.source(), edges, spdg));
+            }
+        }
+
+        for path in deviations {
+            if !found.contains(&path) && !candidates.contains(&path) {
+                candidates.push(path);
+            }
+        }
+        let Some((shortest, _)) = candidates.iter().enumerate().min_by_key(|(_, p)| p.hops())
+        else {
+            break;
+        };
+        found.push(candidates.remove(shortest));
+    }
+    found
+}
+
+#[cfg(test)]
+fn example_controller() -> SPDG {
+    crate::test_utils::example_description()
+        .controllers
+        .into_values()
+        .next()
+        .unwrap()
+}
+
+#[test]
+fn slices_keep_argument_positions() {
+    let spdg = example_controller();
+    let [input, checked, output] = [0, 1, 2].map(Node::new);
+
+    let back = backward_slice([checked], EdgeSelection::Data, &spdg);
+    assert_eq!(back.original, [input, checked]);
+    assert_eq!(back.node_for_original(output), None);
+    let new_input = back.node_for_original(input).unwrap();
+    assert_eq!(back.arguments, [Some(new_input)]);
+    assert_eq!(back.spdg.arguments, [new_input]);
+    assert_eq!(back.spdg.return_, None);
+    assert_eq!(back.spdg.markers[&new_input][0].as_str(), "source");
+    assert_eq!(back.spdg.type_assigns.len(), 1);
+    assert_eq!(back.spdg.graph.edge_count(), 1);
+
+    let forward = forward_slice([checked], EdgeSelection::Data, &spdg);
+    assert_eq!(forward.original, [checked, output]);
+    assert_eq!(forward.arguments, [None]);
+    assert!(forward.spdg.arguments.is_empty());
+    assert_eq!(forward.spdg.return_, forward.node_for_original(output));
+    assert!(forward.spdg.type_assigns.is_empty());
+
+    let control = forward_slice([input], EdgeSelection::Control, &spdg);
+    assert_eq!(control.original, [input, output]);
+    assert_eq!(control.spdg.graph.edge_count(), 1);
+    let all = induced_subgraph(
+        &spdg,
+        &[input, output].into_iter().collect(),
+        EdgeSelection::Both,
+    );
+    assert_eq!(all.spdg.graph.edge_count(), 1);
+}
+
+#[test]
+fn paths_are_found_shortest_first() {
+    let spdg = example_controller();
+    let [input, checked, output] = [0, 1, 2].map(Node::new);
+
+    let direct = shortest_path([input], [output], EdgeSelection::Both, &spdg).unwrap();
+    assert_eq!(direct.nodes, [input, output]);
+    let via_data = shortest_path([input], [output], EdgeSelection::Data, &spdg).unwrap();
+    assert_eq!(via_data.nodes, [input, checked, output]);
+    assert_eq!(via_data.hops(), 2);
+    assert_eq!((via_data.source(), via_data.target()), (input, output));
+    assert_eq!(
+        shortest_path([output], [input], EdgeSelection::Both, &spdg),
+        None
+    );
+    assert_eq!(
+        shortest_path([input], [input], EdgeSelection::Both, &spdg),
+        None
+    );
+
+    let paths = k_shortest_paths([input], [output], EdgeSelection::Both, &spdg, 5);
+    assert_eq!(paths, [direct, via_data]);
+    assert!(k_shortest_paths([input], [output], EdgeSelection::Both, &spdg, 0).is_empty());
+
+    let is_checked = |n| n == checked;
+    assert!(!generic_flows_to_avoiding(
+        [input],
+        EdgeSelection::Data,
+        &spdg,
+        [output],
+        is_checked
+    ));
+    assert!(generic_flows_to_avoiding(
+        [input],
+        EdgeSelection::Both,
+        &spdg,
+        [output],
+        is_checked
+    ));
+    assert_eq!(
+        reachable_from_avoiding([input], EdgeSelection::Data, &spdg, is_checked),
+        [checked].into_iter().collect()
+    );
+    assert_eq!(
+        reachable_from_avoiding([input], EdgeSelection::Control, &spdg, is_checked),
+        [input, output].into_iter().collect()
+    );
+    for (selection, reflexive) in [
+        (EdgeSelection::Data, false),
+        (EdgeSelection::Control, true),
+        (EdgeSelection::Both, true),
+    ] {
+        assert_eq!(
+            generic_flows_to_avoiding([input], selection, &spdg, [input], |_| false),
+            reflexive
+        );
+    }
+}
@@ -72,14 +72,18 @@ pub mod serde_map_via_vec {
     use std::collections::HashMap;
 
     /// Serialize a [`HashMap`] by first converting to a [`Vec`] of tuples and
-    /// then serializing the vector.
+    /// then serializing the vector. The tuples are sorted with
+    /// [`sort_by_serialized_key`](super::sort_by_serialized_key), so the
+    /// output does not depend on the iteration order of the map.
     ///
     /// See module level documentation for usage information.
     pub fn serialize<S: Serializer, K: Serialize, V: Serialize>(
         map: &HashMap<K, V>,
         serializer: S,
     ) -> Result<S::Ok, S::Error> {
-        map.iter().collect::<Vec<_>>().serialize(serializer)
+        let mut entries = map.iter().collect::<Vec<_>>();
+        super::sort_by_serialized_key(&mut entries)?;
+        entries.serialize(serializer)
     }
 
     /// Deserialize a [`HashMap`] by first deserializing a [`Vec`] of tuples and
@@ -97,3 +101,113 @@ pub mod serde_map_via_vec {
         Ok(Vec::deserialize(deserializer)?.into_iter().collect())
     }
 }
+
+/// Sort key-value pairs by the JSON rendering of their keys.
+///
+/// [`HashMap`](std::collections::HashMap)s iterate in a different order in
+/// every run. Sorting by the serialized key gives an order that is stable
+/// across runs for any serializable key, including ones like rustc's
+/// `LocalDefId` that do not implement [`Ord`].
+pub fn sort_by_serialized_key<K: serde::Serialize, V, E: serde::ser::Error>(
+    entries: &mut Vec<(K, V)>,
+) -> Result<(), E> {
+    let mut keyed = entries
+        .drain(..)
+        .map(|(k, v)| Ok((serde_json::to_string(&k).map_err(E::custom)?, k, v)))
+        .collect::<Result<Vec<_>, E>>()?;
+    keyed.sort_by(|a, b| a.0.cmp(&b.0));
+    entries.extend(keyed.into_iter().map(|(_, k, v)| (k, v)));
+    Ok(())
+}
+
+/// Serialize a map with its entries in the order of the keys, for use with
+/// `#[serde(serialize_with = ...)]`. The output is the same as that of the
+/// map's own [`Serialize`](serde::Serialize) implementation, except for
+/// the order.
+pub fn serialize_sorted_map<'a, M, K, V, S>(map: &'a M, serializer: S) -> Result<S::Ok, S::Error>
+where
+    &'a M: IntoIterator<Item = (&'a K, &'a V)>,
+    K: serde::Serialize + Ord + 'a,
+    V: serde::Serialize + 'a,
+    S: serde::Serializer,
+{
+    let mut entries = map.into_iter().collect::<Vec<_>>();
+    entries.sort_by_key(|(k, _)| *k);
+    serializer.collect_map(entries)
+}
+
+/// Converting def ids to and from their raw numbers. Ids of the local crate
+/// have crate number `0`.
+pub(crate) mod raw_id {
+    use flowistry_pdg::rustc_portable::{DefId, LocalDefId};
+
+    cfg_if::cfg_if! {
+        if #[cfg(feature = "rustc")] {
+            use crate::rustc::def_id::{CrateNum, DefIndex};
+
+            /// The crate number and index of `id`
+            pub fn def_id_parts(id: DefId) -> (u32, u32) {
+                (id.krate.as_u32(), id.index.as_u32())
+            }
+
+            /// The inverse of [`def_id_parts`]
+            pub fn def_id(krate: u32, index: u32) -> DefId {
+                DefId {
+                    krate: CrateNum::from_u32(krate),
+                    index: DefIndex::from_u32(index),
+                }
+            }
+
+            /// The index of `id`
+            pub fn local_def_index(id: LocalDefId) -> u32 {
+                id.local_def_index.as_u32()
+            }
+
+            /// The inverse of [`local_def_index`]
+            pub fn local_def_id(index: u32) -> LocalDefId {
+                LocalDefId {
+                    local_def_index: DefIndex::from_u32(index),
+                }
+            }
+        } else {
+            // The proxies keep their numbers private, they can only be taken
+            // apart and built through serde.
+            use serde_json::{json, Value};
+
+            fn index(value: &Value) -> u32 {
+                value["private"]
+                    .as_u64()
+                    .and_then(|i| u32::try_from(i).ok())
+                    .expect("proxy indices serialize as `{\"private\": u32}`")
+            }
+
+            fn from_value<T: serde::de::DeserializeOwned>(value: Value) -> T {
+                serde_json::from_value(value).expect("proxy indices deserialize from `u32`s")
+            }
+
+            /// The crate number and index of `id`
+            pub fn def_id_parts(id: DefId) -> (u32, u32) {
+                let value = json!(id);
+                (index(&value["krate"]), index(&value["index"]))
+            }
+
+            /// The inverse of [`def_id_parts`]
+            pub fn def_id(krate: u32, index: u32) -> DefId {
+                from_value(json!({
+                    "krate": { "private": krate },
+                    "index": { "private": index },
+                }))
+            }
+
+            /// The index of `id`
+            pub fn local_def_index(id: LocalDefId) -> u32 {
+                index(&json!(id)["local_def_index"])
+            }
+
+            /// The inverse of [`local_def_index`]
+            pub fn local_def_id(index: u32) -> LocalDefId {
+                from_value(json!({ "local_def_index": { "private": index } }))
+            }
+        }
+    }
+}
@@ -0,0 +1,308 @@
+//! Checking the internal consistency of a [`ProgramDescription`].
+//!
+//! The extractor guarantees a number of invariants, for instance that every
+//! location in a [`CallString`](crate::CallString) has an entry in
+//! [`ProgramDescription::instruction_info`]. Consumers such as the policy
+//! framework rely on them and panic when they are broken.
+//! [`ProgramDescription::validate`] checks them up front, which is useful after
+//! hand-editing, merging or converting artifacts.
+
+use std::fmt::{self, Display};
+
+use flowistry_pdg::rustc_portable::DefId;
+
+use crate::{Endpoint, GlobalLocation, HashSet, Node, ProgramDescription, TypeId, SPDG};
+
+/// The field of an [`SPDG`] that mentions a node.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, strum::Display)]
+#[strum(serialize_all = "snake_case")]
+pub enum NodeField {
+    /// [`SPDG::markers`]
+    Markers,
+    /// [`SPDG::type_assigns`]
+    TypeAssigns,
+    /// [`SPDG::arguments`]
+    Arguments,
+    /// [`SPDG::return_`]
+    Return,
+}
+
+/// A broken invariant of a [`ProgramDescription`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, strum::EnumIs)]
+pub enum ValidationError {
+    /// `field` of `controller` mentions a node that is not in its graph.
+    MissingNode {
+        /// The controller whose field is broken
+        controller: Endpoint,
+        /// The field that mentions the node
+        field: NodeField,
+        /// The node that does not exist
+        node: Node,
+    },
+    /// A call string of a node or edge in `controller` contains a location
+    /// without [`ProgramDescription::instruction_info`].
+    MissingInstructionInfo {
+        /// The first controller in which the location was found
+        controller: Endpoint,
+        /// The location that has no information
+        location: GlobalLocation,
+    },
+    /// A function called at an instruction, or a type assigned to a node has
+    /// no [`ProgramDescription::def_info`].
+    MissingDefInfo(DefId),
+    /// A type assigned to a node in `controller` has no
+    /// [`ProgramDescription::type_info`].
+    MissingTypeInfo {
+        /// The first controller in which the type was found
+        controller: Endpoint,
+        /// The type that has no information
+        ty: TypeId,
+    },
+}
+
+impl Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationError::MissingNode {
+                controller,
+                field,
+                node,
+            } => write!(
+                f,
+                "{field} of controller {controller:?} mentions nonexistent node {}",
+                node.index()
+            ),
+            ValidationError::MissingInstructionInfo {
+                controller,
+                location,
+            } => write!(
+                f,
+                "location {location:?} in controller {controller:?} has no instruction info"
+            ),
+            ValidationError::MissingDefInfo(id) => write!(f, "{id:?} has no def info"),
+            ValidationError::MissingTypeInfo { controller, ty } => write!(
+                f,
+                "type {ty:?} assigned in controller {controller:?} has no type info"
+            ),
+        }
+    }
+}
+
+impl ProgramDescription {
+    /// Check the invariants of this description.
+    ///
+    /// - Every node in [`SPDG::markers`], [`SPDG::type_assigns`],
+    ///   [`SPDG::arguments`] and [`SPDG::return_`] exists in the graph.
+    /// - Every location in the call strings of nodes and edges has
+    ///   [`Self::instruction_info`].
+    /// - Every function called at an instruction and every type assigned to a
+    ///   node has [`Self::def_info`].
+    /// - Every type assigned to a node has [`Self::type_info`].
+    ///
+    /// Each missing location, id or type is reported once. Controllers are
+    /// visited in order of their name, so the result is deterministic.
+    pub fn validate(&self) -> Result<(), Vec<ValidationError>> {
+        let mut errors = vec![];
+        let mut seen_locations = HashSet::new();
+        let mut seen_types = HashSet::new();
+        for (controller, spdg) in crate::export::sorted_controllers(self) {
+            self.validate_graph(
+                controller,
+                spdg,
+                &mut seen_locations,
+                &mut seen_types,
+                &mut errors,
+            );
+        }
+        let referenced = self
+            .instruction_info
+            .values()
+            .filter_map(|info| Some(info.kind.as_function_call()?.id))
+            .chain(self.controllers.values().flat_map(assigned_types));
+        self.validate_def_info(referenced, &mut errors);
+        into_result(errors)
+    }
+
+    /// Check the invariants of [`Self::validate`] for `spdg`, the graph of
+    /// `controller`, against the information in this description. Use this
+    /// for graphs that are not in [`Self::controllers`], e.g. ones read
+    /// lazily from a [sharded](crate::artifact::sharded) artifact whose index
+    /// is this description.
+    ///
+    /// Only the functions called at locations of `spdg` and the types
+    /// assigned in it need [`Self::def_info`].
+    pub fn validate_controller(
+        &self,
+        controller: Endpoint,
+        spdg: &SPDG,
+    ) -> Result<(), Vec<ValidationError>> {
+        let mut errors = vec![];
+        self.validate_graph(
+            controller,
+            spdg,
+            &mut HashSet::new(),
+            &mut HashSet::new(),
+            &mut errors,
+        );
+        let referenced = locations(spdg)
+            .filter_map(|location| {
+                Some(
+                    self.instruction_info
+                        .get(&location)?
+                        .kind
+                        .as_function_call()?
+                        .id,
+                )
+            })
+            .chain(assigned_types(spdg));
+        self.validate_def_info(referenced, &mut errors);
+        into_result(errors)
+    }
+
+    /// Everything but the def info of one controller. Locations and types in
+    /// `seen_locations` and `seen_types` are not reported again.
+    fn validate_graph(
+        &self,
+        controller: Endpoint,
+        spdg: &SPDG,
+        seen_locations: &mut HashSet<GlobalLocation>,
+        seen_types: &mut HashSet<TypeId>,
+        errors: &mut Vec<ValidationError>,
+    ) {
+        self.validate_nodes(controller, spdg, errors);
+        for location in locations(spdg) {
+            if !self.instruction_info.contains_key(&location) && seen_locations.insert(location) {
+                errors.push(ValidationError::MissingInstructionInfo {
+                    controller,
+                    location,
+                });
+            }
+        }
+        for ty in assigned_types(spdg) {
+            if !self.type_info.contains_key(&ty) && seen_types.insert(ty) {
+                errors.push(ValidationError::MissingTypeInfo { controller, ty });
+            }
+        }
+    }
+
+    fn validate_def_info(
+        &self,
+        referenced: impl Iterator<Item = DefId>,
+        errors: &mut Vec<ValidationError>,
+    ) {
+        let mut missing = referenced
+            .filter(|id| !self.def_info.contains_key(id))
+            .collect::<Vec<_>>();
+        missing.sort();
+        missing.dedup();
+        errors.extend(missing.into_iter().map(ValidationError::MissingDefInfo));
+    }
+
+    fn validate_nodes(&self, controller: Endpoint, spdg: &SPDG, errors: &mut Vec<ValidationError>) {
+        let mentioned = [
+            (NodeField::Markers, spdg.markers.keys().copied().collect()),
+            (
+                NodeField::TypeAssigns,
+                spdg.type_assigns.keys().copied().collect(),
+            ),
+            (NodeField::Arguments, spdg.arguments.clone()),
+            (NodeField::Return, spdg.return_.into_iter().collect()),
+        ];
+        for (field, mut nodes) in mentioned {
+            nodes.sort();
+            errors.extend(
+                nodes
+                    .into_iter()
+                    .filter(|n| spdg.graph.node_weight(*n).is_none())
+                    .map(|node| ValidationError::MissingNode {
+                        controller,
+                        field,
+                        node,
+                    }),
+            );
+        }
+    }
+}
+
+/// All locations in the call strings of nodes and edges of `spdg`
+fn locations(spdg: &SPDG) -> impl Iterator<Item = GlobalLocation> + '_ {
+    spdg.graph
+        .node_weights()
+        .flat_map(|n| n.at.iter())
+        .chain(spdg.graph.edge_weights().flat_map(|e| e.at.iter()))
+}
+
+fn assigned_types(spdg: &SPDG) -> impl Iterator<Item = TypeId> + '_ {
+    spdg.type_assigns.values().flat_map(|t| t.0.iter().copied())
+}
+
+fn into_result(errors: Vec<ValidationError>) -> Result<(), Vec<ValidationError>> {
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+#[test]
+fn validate_reports_dangling_references() {
+    let mut desc = ProgramDescription::default();
+    assert_eq!(desc.validate(), Ok(()));
+
+    // The proxies can only be constructed through serde
+    let controller: Endpoint =
+        serde_json::from_str(r#"{"local_def_index":{"private":0}}"#).unwrap();
+    let ty: DefId =
+        serde_json::from_str(r#"{"krate":{"private":0},"index":{"private":1}}"#).unwrap();
+    let dangling = Node::new(3);
+    let mut spdg = SPDG {
+        name: crate::Identifier::new_intern("ctrl"),
+        graph: Default::default(),
+        markers: Default::default(),
+        arguments: vec![dangling],
+        return_: None,
+        type_assigns: Default::default(),
+    };
+    spdg.type_assigns.insert(dangling, crate::Types(vec![ty]));
+    desc.controllers.insert(controller, spdg);
+
+    let errors = desc.validate().unwrap_err();
+    assert_eq!(
+        errors,
+        vec![
+            ValidationError::MissingNode {
+                controller,
+                field: NodeField::TypeAssigns,
+                node: dangling
+            },
+            ValidationError::MissingNode {
+                controller,
+                field: NodeField::Arguments,
+                node: dangling
+            },
+            ValidationError::MissingTypeInfo { controller, ty },
+            ValidationError::MissingDefInfo(ty),
+        ]
+    );
+}
+
+#[test]
+fn controllers_are_validated_against_the_description() {
+    use crate::test_utils::*;
+    let mut index = example_description();
+    let id = local_def_id(EXAMPLE_CONTROLLER);
+    let ctrl = index.controllers[&id].clone();
+    assert_eq!(index.validate_controller(id, &ctrl), Ok(()));
+
+    // Like the index of a sharded artifact, see `sharded::write_sharded`
+    index.controllers.clear();
+    assert_eq!(index.validate_controller(id, &ctrl), Ok(()));
+    index.type_info.clear();
+    assert_eq!(
+        index.validate_controller(id, &ctrl),
+        Err(vec![ValidationError::MissingTypeInfo {
+            controller: id,
+            ty: def_id(0, EXAMPLE_TYPE)
+        }])
+    );
+}